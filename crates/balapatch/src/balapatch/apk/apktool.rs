@@ -1,11 +1,12 @@
-<<<<<<< HEAD
+use crate::balapatch::utils::command::{self, CommandError};
 use anyhow::Result;
-=======
-use anyhow::{anyhow, Result};
->>>>>>> 2c49caf265594eab24344216ff042097dc9d287e
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 use tracing::info;
 
+/// Where [`get_apktool`] downloads apktool to
+pub const APKTOOL_JAR: &str = "balapatch/apktool.jar";
+
 pub async fn has_apktool() -> Result<PathBuf, ()> {
     if let Ok(apktool_path) = which::which("apktool") {
         Ok(apktool_path)
@@ -14,15 +15,11 @@ pub async fn has_apktool() -> Result<PathBuf, ()> {
     }
 }
 
-<<<<<<< HEAD
 pub async fn get_apktool() -> Result<(), String> {
-=======
-pub async fn get_apktool() -> anyhow::Result<(), String> {
->>>>>>> 2c49caf265594eab24344216ff042097dc9d287e
     if has_apktool().await.is_err() {
         crate::balapatch::utils::misc::download_file(
             "https://github.com/iBotPeaches/Apktool/releases/download/v2.11.0/apktool_2.11.0.jar",
-            "balapatch/apktool.jar",
+            APKTOOL_JAR,
         )
         .await
         .expect("Failed to download Apktool");
@@ -36,3 +33,107 @@ pub async fn get_apktool() -> anyhow::Result<(), String> {
 
     Ok(())
 }
+
+/// How to run apktool
+#[derive(Debug, Clone)]
+pub struct ApktoolConfig {
+    /// An `apktool` executable, or a `.jar` to run with `java`
+    pub path: PathBuf,
+    /// Java used for running a `.jar`
+    pub java: PathBuf,
+    /// Where apktool keeps framework files (`--frame-path`)
+    pub framework_dir: Option<PathBuf>,
+    /// Extra flags for decoding, e.g. `--no-res`
+    pub decode_flags: Vec<String>,
+    /// Extra flags for building, e.g. `--use-aapt2`
+    pub build_flags: Vec<String>,
+}
+
+impl Default for ApktoolConfig {
+    /// `apktool` from the PATH if it's there, otherwise the jar
+    /// [`get_apktool`] downloads, run with `java` from the PATH
+    fn default() -> Self {
+        Self {
+            path: which::which("apktool").unwrap_or_else(|_| PathBuf::from(APKTOOL_JAR)),
+            java: PathBuf::from("java"),
+            framework_dir: None,
+            decode_flags: Vec::new(),
+            build_flags: Vec::new(),
+        }
+    }
+}
+
+impl ApktoolConfig {
+    pub fn with_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.path = path.as_ref().to_path_buf();
+        self
+    }
+
+    pub fn with_java<P: AsRef<Path>>(mut self, java: P) -> Self {
+        self.java = java.as_ref().to_path_buf();
+        self
+    }
+
+    pub fn with_framework_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.framework_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn decode_flag(mut self, flag: impl Into<String>) -> Self {
+        self.decode_flags.push(flag.into());
+        self
+    }
+
+    pub fn build_flag(mut self, flag: impl Into<String>) -> Self {
+        self.build_flags.push(flag.into());
+        self
+    }
+
+    /// Decode `apk` into `out_dir`, replacing anything already there
+    pub fn decode<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        apk: P,
+        out_dir: Q,
+    ) -> Result<(), CommandError> {
+        let mut command = self.command("decode", &self.decode_flags);
+        command
+            .arg("-f")
+            .arg(apk.as_ref())
+            .arg("-o")
+            .arg(out_dir.as_ref());
+
+        command::run(&mut command).map(|_| ())
+    }
+
+    /// Build the decoded APK in `dir` into `out_apk`
+    pub fn build<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        dir: P,
+        out_apk: Q,
+    ) -> Result<(), CommandError> {
+        let mut command = self.command("build", &self.build_flags);
+        command
+            .arg(dir.as_ref())
+            .arg("-o")
+            .arg(out_apk.as_ref());
+
+        command::run(&mut command).map(|_| ())
+    }
+
+    fn command(&self, action: &str, flags: &[String]) -> Command {
+        let mut command = if self.path.extension().is_some_and(|ext| ext == "jar") {
+            let mut command = Command::new(&self.java);
+            command.arg("-jar").arg(&self.path);
+            command
+        } else {
+            Command::new(&self.path)
+        };
+
+        command.arg(action).args(flags);
+        if let Some(dir) = &self.framework_dir {
+            command.arg("--frame-path").arg(dir);
+        }
+
+        command
+    }
+}
@@ -0,0 +1,321 @@
+//! Just enough of a binary XML (AXML) reader to get the package name and
+//! version out of a compiled `AndroidManifest.xml`.
+
+use anyhow::{Context, anyhow, bail};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use zip::ZipArchive;
+
+const RES_XML_TYPE: u16 = 0x0003;
+const RES_STRING_POOL_TYPE: u16 = 0x0001;
+const RES_XML_RESOURCE_MAP_TYPE: u16 = 0x0180;
+const RES_XML_START_ELEMENT_TYPE: u16 = 0x0102;
+
+const UTF8_FLAG: u32 = 1 << 8;
+
+const TYPE_STRING: u8 = 0x03;
+const TYPE_INT_DEC: u8 = 0x10;
+const TYPE_INT_HEX: u8 = 0x11;
+
+/// Resource ids of `android:versionCode` and `android:versionName`
+const ATTR_VERSION_CODE: u32 = 0x0101_021b;
+const ATTR_VERSION_NAME: u32 = 0x0101_021c;
+
+/// What balapatch cares about from an APK's manifest
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApkManifest {
+    pub package: Option<String>,
+    pub version_code: Option<u64>,
+    /// `None` if missing or only given as a resource reference
+    pub version_name: Option<String>,
+}
+
+impl ApkManifest {
+    /// Read the manifest of the APK at `path`
+    pub fn from_apk<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file =
+            File::open(path).with_context(|| format!("Failed to open '{}'", path.display()))?;
+        let mut archive = ZipArchive::new(file)
+            .with_context(|| format!("'{}' isn't a valid APK", path.display()))?;
+
+        let mut entry = archive
+            .by_name("AndroidManifest.xml")
+            .with_context(|| format!("'{}' has no AndroidManifest.xml", path.display()))?;
+        let mut bytes = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut bytes)?;
+
+        Self::from_axml(&bytes)
+    }
+
+    /// Parse a compiled `AndroidManifest.xml`
+    pub fn from_axml(bytes: &[u8]) -> anyhow::Result<Self> {
+        if read_u16(bytes, 0)? != RES_XML_TYPE {
+            bail!("Not a binary XML file");
+        }
+
+        let mut strings = Vec::new();
+        let mut resource_ids = Vec::new();
+        let mut offset = read_u16(bytes, 2)? as usize;
+
+        while offset + 8 <= bytes.len() {
+            let chunk_type = read_u16(bytes, offset)?;
+            let header_size = read_u16(bytes, offset + 2)? as usize;
+            let size = read_u32(bytes, offset + 4)? as usize;
+            if size < 8 || offset + size > bytes.len() {
+                bail!("Malformed chunk at offset {}", offset);
+            }
+            let chunk = &bytes[offset..offset + size];
+
+            match chunk_type {
+                RES_STRING_POOL_TYPE => strings = read_string_pool(chunk)?,
+                RES_XML_RESOURCE_MAP_TYPE => {
+                    resource_ids = (header_size..size)
+                        .step_by(4)
+                        .map(|at| read_u32(chunk, at))
+                        .collect::<anyhow::Result<_>>()?;
+                }
+                RES_XML_START_ELEMENT_TYPE => {
+                    let element = Element::read(chunk, header_size)?;
+                    if strings.get(element.name as usize).map(String::as_str) == Some("manifest") {
+                        return Ok(element.manifest(&strings, &resource_ids));
+                    }
+                }
+                _ => {}
+            }
+
+            offset += size;
+        }
+
+        Err(anyhow!("No <manifest> element found"))
+    }
+}
+
+struct Attribute {
+    name: u32,
+    raw_value: u32,
+    data_type: u8,
+    data: u32,
+}
+
+struct Element {
+    name: u32,
+    attributes: Vec<Attribute>,
+}
+
+impl Element {
+    fn read(chunk: &[u8], header_size: usize) -> anyhow::Result<Self> {
+        let ext = header_size;
+        let name = read_u32(chunk, ext + 4)?;
+        let attribute_start = read_u16(chunk, ext + 8)? as usize;
+        let attribute_size = read_u16(chunk, ext + 10)? as usize;
+        let attribute_count = read_u16(chunk, ext + 12)? as usize;
+
+        let attributes = (0..attribute_count)
+            .map(|idx| {
+                let at = ext + attribute_start + idx * attribute_size;
+                Ok(Attribute {
+                    name: read_u32(chunk, at + 4)?,
+                    raw_value: read_u32(chunk, at + 8)?,
+                    data_type: *chunk.get(at + 15).context("Truncated attribute")?,
+                    data: read_u32(chunk, at + 16)?,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self { name, attributes })
+    }
+
+    fn manifest(&self, strings: &[String], resource_ids: &[u32]) -> ApkManifest {
+        let mut manifest = ApkManifest::default();
+
+        for attr in &self.attributes {
+            let string_value = || match attr.data_type {
+                TYPE_STRING => strings.get(attr.data as usize).cloned(),
+                _ => strings.get(attr.raw_value as usize).cloned(),
+            };
+
+            // Android attributes are matched by resource id, since their
+            // names can be stripped from the string pool
+            match resource_ids.get(attr.name as usize) {
+                Some(&ATTR_VERSION_CODE) => {
+                    if matches!(attr.data_type, TYPE_INT_DEC | TYPE_INT_HEX) {
+                        manifest.version_code = Some(attr.data as u64);
+                    }
+                }
+                Some(&ATTR_VERSION_NAME) => manifest.version_name = string_value(),
+                _ if strings.get(attr.name as usize).map(String::as_str) == Some("package") => {
+                    manifest.package = string_value();
+                }
+                _ => {}
+            }
+        }
+
+        manifest
+    }
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> anyhow::Result<u16> {
+    bytes
+        .get(at..at + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| anyhow!("Unexpected end of binary XML at offset {}", at))
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> anyhow::Result<u32> {
+    bytes
+        .get(at..at + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| anyhow!("Unexpected end of binary XML at offset {}", at))
+}
+
+fn read_string_pool(chunk: &[u8]) -> anyhow::Result<Vec<String>> {
+    let count = read_u32(chunk, 8)? as usize;
+    let flags = read_u32(chunk, 16)?;
+    let strings_start = read_u32(chunk, 20)? as usize;
+    let header_size = read_u16(chunk, 2)? as usize;
+
+    (0..count)
+        .map(|idx| {
+            let at = strings_start + read_u32(chunk, header_size + idx * 4)? as usize;
+            if flags & UTF8_FLAG != 0 {
+                read_utf8(chunk, at)
+            } else {
+                read_utf16(chunk, at)
+            }
+        })
+        .collect()
+}
+
+fn read_utf8(chunk: &[u8], at: usize) -> anyhow::Result<String> {
+    let byte = |at: usize| chunk.get(at).copied().context("Truncated string pool");
+    // Lengths are one byte, or two if the high bit of the first is set
+    let read_len = |at: usize| -> anyhow::Result<(usize, usize)> {
+        match byte(at)? {
+            b if b & 0x80 != 0 => Ok((((b as usize & 0x7f) << 8) | byte(at + 1)? as usize, at + 2)),
+            b => Ok((b as usize, at + 1)),
+        }
+    };
+
+    // Length in UTF-16 units, then in bytes
+    let (_, at) = read_len(at)?;
+    let (len, at) = read_len(at)?;
+
+    chunk
+        .get(at..at + len)
+        .map(|bytes| String::from_utf8_lossy(bytes).into_owned())
+        .context("Truncated string pool")
+}
+
+fn read_utf16(chunk: &[u8], at: usize) -> anyhow::Result<String> {
+    let (len, at) = match read_u16(chunk, at)? {
+        len if len & 0x8000 != 0 => (
+            ((len as usize & 0x7fff) << 16) | read_u16(chunk, at + 2)? as usize,
+            at + 4,
+        ),
+        len => (len as usize, at + 2),
+    };
+
+    let units = (0..len)
+        .map(|idx| read_u16(chunk, at + idx * 2))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(String::from_utf16_lossy(&units))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn chunk(chunk_type: u16, header: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend(chunk_type.to_le_bytes());
+        out.extend((8 + header.len() as u16).to_le_bytes());
+        out.extend((8 + header.len() as u32 + body.len() as u32).to_le_bytes());
+        out.extend(header);
+        out.extend(body);
+        out
+    }
+
+    fn string_pool(strings: &[&str]) -> Vec<u8> {
+        let mut offsets = Vec::new();
+        let mut data = Vec::new();
+        for s in strings {
+            offsets.extend((data.len() as u32).to_le_bytes());
+            let units: Vec<u16> = s.encode_utf16().collect();
+            data.extend((units.len() as u16).to_le_bytes());
+            units
+                .iter()
+                .for_each(|unit| data.extend(unit.to_le_bytes()));
+            data.extend([0, 0]);
+        }
+
+        let mut header = Vec::new();
+        header.extend((strings.len() as u32).to_le_bytes());
+        header.extend(0u32.to_le_bytes());
+        header.extend(0u32.to_le_bytes());
+        header.extend((28 + offsets.len() as u32).to_le_bytes());
+        header.extend(0u32.to_le_bytes());
+
+        chunk(RES_STRING_POOL_TYPE, &header, &[offsets, data].concat())
+    }
+
+    fn attribute(name: u32, raw: u32, data_type: u8, data: u32) -> Vec<u8> {
+        [
+            u32::MAX.to_le_bytes(),
+            name.to_le_bytes(),
+            raw.to_le_bytes(),
+            [8, 0, 0, data_type],
+            data.to_le_bytes(),
+        ]
+        .concat()
+    }
+
+    /// A compiled manifest for Balatro at the given version, for tests
+    /// that need an APK
+    pub(crate) fn compiled_manifest(version_code: u32, version_name: &str) -> Vec<u8> {
+        // Attribute names come first so they line up with the resource map
+        let strings = [
+            "versionCode",
+            "versionName",
+            "package",
+            "manifest",
+            version_name,
+            "com.playstack.balatro.android",
+        ];
+
+        let mut resource_map = Vec::new();
+        resource_map.extend(ATTR_VERSION_CODE.to_le_bytes());
+        resource_map.extend(ATTR_VERSION_NAME.to_le_bytes());
+
+        let mut element = Vec::new();
+        element.extend(u32::MAX.to_le_bytes());
+        element.extend(3u32.to_le_bytes());
+        element.extend([20, 0, 20, 0, 3, 0, 0, 0, 0, 0, 0, 0]);
+        element.extend(attribute(0, u32::MAX, TYPE_INT_DEC, version_code));
+        element.extend(attribute(1, 4, TYPE_STRING, 4));
+        element.extend(attribute(2, 5, TYPE_STRING, 5));
+
+        let body = [
+            string_pool(&strings),
+            chunk(RES_XML_RESOURCE_MAP_TYPE, &[], &resource_map),
+            chunk(RES_XML_START_ELEMENT_TYPE, &[0; 8], &element),
+        ]
+        .concat();
+
+        chunk(RES_XML_TYPE, &[], &body)
+    }
+
+    #[test]
+    fn reads_manifest_version() {
+        assert_eq!(
+            ApkManifest::from_axml(&compiled_manifest(1018, "1.0.1o")).unwrap(),
+            ApkManifest {
+                package: Some("com.playstack.balatro.android".to_string()),
+                version_code: Some(1018),
+                version_name: Some("1.0.1o".to_string()),
+            }
+        );
+    }
+}
@@ -1,5 +1,7 @@
 pub mod apk_utils;
 pub mod apktool;
+pub mod manifest;
+pub mod signature;
 pub mod zipalign;
 
 // pub fn install_apk(server: &mut ADBServer, apk_path: &str) -> anyhow::Result<(), RustADBError> {
@@ -0,0 +1,156 @@
+//! Identifying who signed an APK, in the same form `dumpsys package` uses.
+//!
+//! `dumpsys` lists each signature as the hex of Java's
+//! `Arrays.hashCode` over the certificate's DER encoding, so that's what is
+//! worked out here from the APK's v1 (JAR) signature blocks.
+
+use anyhow::{Context, anyhow, bail};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use zip::ZipArchive;
+
+/// Signature hashes of every v1 signer of the APK at `path`, sorted.
+/// Empty if the APK only has a v2+ signature.
+pub fn signature_hashes<P: AsRef<Path>>(path: P) -> anyhow::Result<Vec<String>> {
+    let path = path.as_ref();
+    let file = File::open(path).with_context(|| format!("Failed to open '{}'", path.display()))?;
+    let mut archive =
+        ZipArchive::new(file).with_context(|| format!("'{}' isn't a valid APK", path.display()))?;
+
+    let blocks: Vec<String> = archive
+        .file_names()
+        .filter(|name| {
+            let upper = name.to_ascii_uppercase();
+            upper.starts_with("META-INF/")
+                && [".RSA", ".DSA", ".EC"]
+                    .iter()
+                    .any(|ext| upper.ends_with(ext))
+        })
+        .map(str::to_string)
+        .collect();
+
+    let mut hashes = Vec::new();
+    for name in blocks {
+        let mut bytes = Vec::new();
+        archive.by_name(&name)?.read_to_end(&mut bytes)?;
+
+        let cert = first_certificate(&bytes)
+            .with_context(|| format!("Failed to read the signature block '{}'", name))?;
+        hashes.push(format!("{:x}", java_hash(cert)));
+    }
+
+    hashes.sort();
+    hashes.dedup();
+    Ok(hashes)
+}
+
+/// Java's `Arrays.hashCode(byte[])`
+pub fn java_hash(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(1i32, |hash, byte| {
+        hash.wrapping_mul(31).wrapping_add(*byte as i8 as i32)
+    }) as u32
+}
+
+/// A DER value: its tag, its contents and the whole encoding
+struct Der<'a> {
+    tag: u8,
+    contents: &'a [u8],
+    encoded: &'a [u8],
+}
+
+/// Read the DER value at the start of `bytes`, returning it and what follows
+fn read_der(bytes: &[u8]) -> anyhow::Result<(Der<'_>, &[u8])> {
+    let tag = *bytes.first().context("Unexpected end of DER data")?;
+    let first = *bytes.get(1).context("Unexpected end of DER data")?;
+
+    let (len, header) = if first & 0x80 == 0 {
+        (first as usize, 2)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 {
+            bail!("Unsupported DER length encoding");
+        }
+        let len = bytes
+            .get(2..2 + count)
+            .context("Unexpected end of DER data")?
+            .iter()
+            .fold(0usize, |len, byte| (len << 8) | *byte as usize);
+        (len, 2 + count)
+    };
+
+    let end = header + len;
+    if end > bytes.len() {
+        bail!("DER value runs past the end of the data");
+    }
+
+    Ok((
+        Der {
+            tag,
+            contents: &bytes[header..end],
+            encoded: &bytes[..end],
+        },
+        &bytes[end..],
+    ))
+}
+
+fn expect_der(bytes: &[u8], tag: u8) -> anyhow::Result<(Der<'_>, &[u8])> {
+    let (der, rest) = read_der(bytes)?;
+    if der.tag != tag {
+        bail!("Expected DER tag {:#04x}, found {:#04x}", tag, der.tag);
+    }
+    Ok((der, rest))
+}
+
+/// The first certificate of a PKCS#7 `SignedData` block
+fn first_certificate(block: &[u8]) -> anyhow::Result<&[u8]> {
+    // ContentInfo ::= SEQUENCE { contentType OID, [0] EXPLICIT SignedData }
+    let (content_info, _) = expect_der(block, 0x30)?;
+    let (_, rest) = expect_der(content_info.contents, 0x06)?;
+    let (explicit, _) = expect_der(rest, 0xa0)?;
+
+    // SignedData ::= SEQUENCE { version, digestAlgorithms, encapContentInfo,
+    //                           [0] IMPLICIT certificates OPTIONAL, ... }
+    let (signed_data, _) = expect_der(explicit.contents, 0x30)?;
+    let (_, rest) = expect_der(signed_data.contents, 0x02)?;
+    let (_, rest) = expect_der(rest, 0x31)?;
+    let (_, rest) = expect_der(rest, 0x30)?;
+    let (certificates, _) =
+        expect_der(rest, 0xa0).map_err(|_| anyhow!("The signature block has no certificates"))?;
+
+    let (cert, _) = expect_der(certificates.contents, 0x30)?;
+    Ok(cert.encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_java_hashes() {
+        assert_eq!(java_hash(&[]), 1);
+        assert_eq!(java_hash(&[1, 2, 3]), 30817);
+        // Negative bytes are sign extended like Java's `byte`
+        assert_eq!(java_hash(&[0xff]), 30);
+    }
+
+    #[test]
+    fn finds_the_first_certificate() {
+        let cert = [0x30, 0x03, 0x02, 0x01, 0x07];
+        let signed_data = [
+            &[
+                0x30, 0x10, 0x02, 0x01, 0x01, 0x31, 0x00, 0x30, 0x00, 0xa0, 0x07,
+            ][..],
+            &cert,
+            &[0x30, 0x00],
+        ]
+        .concat();
+        let block = [
+            &[0x30, 0x17, 0x06, 0x01, 0x2a, 0xa0, 0x12][..],
+            &signed_data,
+        ]
+        .concat();
+
+        assert_eq!(first_certificate(&block).unwrap(), cert);
+    }
+}
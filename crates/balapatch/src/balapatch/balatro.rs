@@ -1,7 +1,7 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use {
     crate::balapatch::{
-        adb, apk,
+        adb, apk, logcat,
         tui::progress::{self, create_bytes_progress},
         utils::string_buf::StringBuf,
     },
@@ -13,6 +13,9 @@ use {
     tracing::info,
 };
 
+/// Package name of the Android release of Balatro
+pub const BALATRO_PACKAGE: &str = "com.playstack.balatro.android";
+
 /// Checks if the Balatro application is installed on the connected ADB device and retrieves its APK paths.
 ///
 /// # Parameters
@@ -34,7 +37,7 @@ pub fn check_balatro_install(server: &mut ADBServer) -> anyhow::Result<(bool, Ve
 
     device
         .shell_command(
-            &["pm", "path", "com.playstack.balatro.android"],
+            &["pm", "path", BALATRO_PACKAGE],
             &mut output,
         )
         .context("Failed to find Balatro")?;
@@ -55,7 +58,7 @@ pub fn check_balatro_install(server: &mut ADBServer) -> anyhow::Result<(bool, Ve
 ///
 /// - `adb_server`: A mutable reference to an `ADBServer` instance used to communicate with the ADB device.
 /// - `out`: An optional reference to a `String` specifying the output directory where the APKs will be saved.
-///   If `None`, defaults to "balapatch/balatro-apks".
+///   If `None`, defaults to [`DEFAULT_APKS_DIR`].
 /// - `all`: An optional `bool` indicating whether to pull all APK splits. Defaults to `false` if `None`.
 /// - `verbose`: A `bool` that, if `true`, enables verbose output during the APK pulling process.
 ///
@@ -74,7 +77,7 @@ pub fn pull_balatro(
 
     let apks_out = match &out {
         Some(folder) => folder,
-        None => "balapatch/balatro-apks",
+        None => DEFAULT_APKS_DIR,
     };
 
     pb.set_message("Creating output directory...");
@@ -117,7 +120,7 @@ pub fn pull_balatro(
 
                 adb::pull_app_apks(
                     &mut adb_server,
-                    "com.playstack.balatro.android",
+                    BALATRO_PACKAGE,
                     apks_out,
                     verbose,
                     all,
@@ -179,3 +182,434 @@ pub async fn unpack_balatro(balatro_path: &str, out_path: &str) -> anyhow::Resul
 
     Ok(())
 }
+
+/// Component name (`package/activity`) of Balatro's launcher activity
+pub fn balatro_main_activity(server: &mut ADBServer) -> anyhow::Result<String> {
+    let mut output = StringBuf::new();
+    let mut device = server
+        .get_device()
+        .context("Failed to connect to ADB device")?;
+
+    device
+        .shell_command(
+            &["cmd", "package", "resolve-activity", "--brief", BALATRO_PACKAGE],
+            &mut output,
+        )
+        .context("Failed to look up Balatro's main activity")?;
+
+    // The last line is the component, anything before it is a priority line
+    output
+        .as_string()?
+        .lines()
+        .map(str::trim)
+        .rfind(|line| line.starts_with(BALATRO_PACKAGE) && line.contains('/'))
+        .map(str::to_string)
+        .ok_or_else(|| anyhow::anyhow!("Balatro has no launcher activity (is it installed?)"))
+}
+
+/// Starts Balatro's main activity
+pub fn launch_balatro(server: &mut ADBServer) -> anyhow::Result<()> {
+    let activity = balatro_main_activity(server)?;
+    let mut device = server
+        .get_device()
+        .context("Failed to connect to ADB device")?;
+
+    device
+        .shell_command(&["am", "start", "-n", &activity], &mut std::io::sink())
+        .context("Failed to launch Balatro")?;
+
+    Ok(())
+}
+
+/// Kills Balatro if it's running
+pub fn force_stop_balatro(server: &mut ADBServer) -> anyhow::Result<()> {
+    let mut device = server
+        .get_device()
+        .context("Failed to connect to ADB device")?;
+
+    device
+        .shell_command(&["am", "force-stop", BALATRO_PACKAGE], &mut std::io::sink())
+        .context("Failed to stop Balatro")?;
+
+    Ok(())
+}
+
+/// Force-stops Balatro and launches it again
+pub fn restart_balatro(server: &mut ADBServer) -> anyhow::Result<()> {
+    force_stop_balatro(server)?;
+    launch_balatro(server)
+}
+
+/// Wipes Balatro's saves and settings, as if it was freshly installed
+pub fn clear_balatro_data(server: &mut ADBServer) -> anyhow::Result<()> {
+    let mut output = StringBuf::new();
+    let mut device = server
+        .get_device()
+        .context("Failed to connect to ADB device")?;
+
+    device
+        .shell_command(&["pm", "clear", BALATRO_PACKAGE], &mut output)
+        .context("Failed to clear Balatro's data")?;
+
+    let output = output.as_string()?.trim();
+    if output != "Success" {
+        return Err(anyhow::anyhow!("Failed to clear Balatro's data: {}", output));
+    }
+
+    Ok(())
+}
+
+/// What's going on with Balatro on the device
+#[derive(Debug, Clone, Default)]
+pub struct BalatroStatus {
+    pub installed: bool,
+    pub version: Option<String>,
+    /// Empty if it isn't running
+    pub pids: Vec<u32>,
+}
+
+impl std::fmt::Display for BalatroStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.installed {
+            return write!(f, "Balatro is not installed");
+        }
+
+        write!(
+            f,
+            "Balatro {} is installed",
+            self.version.as_deref().unwrap_or("(unknown version)")
+        )?;
+        match self.pids.as_slice() {
+            [] => write!(f, " and not running"),
+            pids => write!(
+                f,
+                " and running (pid {})",
+                pids.iter()
+                    .map(|pid| pid.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+pub fn balatro_status(server: &mut ADBServer) -> anyhow::Result<BalatroStatus> {
+    let Some(install) = balatro_install(server)? else {
+        return Ok(BalatroStatus::default());
+    };
+
+    Ok(BalatroStatus {
+        installed: true,
+        version: install.version_name,
+        pids: logcat::balatro_pids(server)?,
+    })
+}
+
+/// Where pulled APKs end up unless told otherwise
+pub const DEFAULT_APKS_DIR: &str = "balapatch/balatro_apks";
+
+/// Where [`pull_balatro`] used to put APKs when not given a directory
+const OLD_APKS_DIR: &str = "balapatch/balatro-apks";
+
+/// The pulled `base.apk` in [`DEFAULT_APKS_DIR`], or in the directory older
+/// versions pulled to if that's the only one there is
+pub fn pulled_base_apk() -> PathBuf {
+    let base_apk = Path::new(DEFAULT_APKS_DIR).join("base.apk");
+    let old_base_apk = Path::new(OLD_APKS_DIR).join("base.apk");
+
+    if !base_apk.is_file() && old_base_apk.is_file() {
+        old_base_apk
+    } else {
+        base_apk
+    }
+}
+
+/// Details of the Balatro install on the device, from `dumpsys package`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BalatroInstall {
+    /// APK paths, as returned by [`check_balatro_install`]
+    pub paths: Vec<String>,
+    pub version_name: Option<String>,
+    pub version_code: Option<u64>,
+    /// Package that installed the game, e.g. `com.android.vending` for the Play Store
+    pub installer: Option<String>,
+    pub first_install_time: Option<String>,
+    pub last_update_time: Option<String>,
+    /// Signing certificates, as hashes (see [`apk::signature`])
+    pub signatures: Vec<String>,
+}
+
+impl BalatroInstall {
+    /// Fill in the details from the output of `dumpsys package <package>`
+    pub fn parse_dumpsys(paths: Vec<String>, dumpsys: &str) -> Self {
+        let mut install = BalatroInstall {
+            paths,
+            ..Default::default()
+        };
+
+        for line in dumpsys.lines().map(str::trim) {
+            if let Some(sigs) = line
+                .split_once("signatures:[")
+                .filter(|_| install.signatures.is_empty())
+                .and_then(|(_, rest)| rest.split_once(']'))
+            {
+                install.signatures = sigs
+                    .0
+                    .split(',')
+                    .map(|sig| sig.trim().to_string())
+                    .filter(|sig| !sig.is_empty())
+                    .collect();
+                install.signatures.sort();
+            }
+
+            // Several `key=value` pairs can share a line
+            for field in line.split_whitespace() {
+                let Some((key, value)) = field.split_once('=').filter(|(_, v)| *v != "null")
+                else {
+                    continue;
+                };
+                let slot = match key {
+                    "versionName" => &mut install.version_name,
+                    "installerPackageName" => &mut install.installer,
+                    "versionCode" => {
+                        install.version_code = install.version_code.or(value.parse().ok());
+                        continue;
+                    }
+                    _ => continue,
+                };
+                slot.get_or_insert_with(|| value.to_string());
+            }
+
+            // Install times contain a space, so they take the rest of the line
+            if let Some(time) = line.strip_prefix("firstInstallTime=") {
+                install.first_install_time.get_or_insert_with(|| time.to_string());
+            }
+            if let Some(time) = line.strip_prefix("lastUpdateTime=") {
+                install.last_update_time.get_or_insert_with(|| time.to_string());
+            }
+        }
+
+        install
+    }
+
+    /// Compare the install with a pulled copy of its `base.apk`
+    pub fn compare_with_pulled<P: AsRef<Path>>(&self, base_apk: P) -> anyhow::Result<PulledApkCheck> {
+        let base_apk = base_apk.as_ref();
+        if !base_apk.is_file() {
+            return Ok(PulledApkCheck::NotPulled);
+        }
+
+        let manifest = apk::manifest::ApkManifest::from_apk(base_apk)?;
+        let pulled_version = manifest
+            .version_name
+            .clone()
+            .or(manifest.version_code.map(|code| code.to_string()))
+            .unwrap_or_else(|| "unknown".to_string());
+        let installed_version = self
+            .version_name
+            .clone()
+            .or(self.version_code.map(|code| code.to_string()))
+            .unwrap_or_else(|| "unknown".to_string());
+
+        // Prefer version codes, since that's what Android itself compares
+        let same_version = match (manifest.version_code, self.version_code) {
+            (Some(pulled), Some(installed)) => pulled == installed,
+            _ => manifest.version_name == self.version_name,
+        };
+        if !same_version {
+            return Ok(PulledApkCheck::Outdated {
+                pulled: pulled_version,
+                installed: installed_version,
+            });
+        }
+
+        let pulled_signatures = apk::signature::signature_hashes(base_apk)?;
+        if !pulled_signatures.is_empty()
+            && !self.signatures.is_empty()
+            && pulled_signatures != self.signatures
+        {
+            return Ok(PulledApkCheck::DifferentSigner);
+        }
+
+        Ok(PulledApkCheck::UpToDate)
+    }
+}
+
+impl std::fmt::Display for BalatroInstall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let or_unknown = |value: &Option<String>| value.clone().unwrap_or("unknown".to_string());
+
+        writeln!(
+            f,
+            "Version      => {} ({})",
+            or_unknown(&self.version_name),
+            self.version_code
+                .map(|code| code.to_string())
+                .unwrap_or("unknown".to_string())
+        )?;
+        writeln!(f, "Installer    => {}", or_unknown(&self.installer))?;
+        writeln!(f, "Installed    => {}", or_unknown(&self.first_install_time))?;
+        writeln!(f, "Updated      => {}", or_unknown(&self.last_update_time))?;
+        writeln!(f, "Signatures   => {}", self.signatures.join(", "))?;
+        write!(f, "APKs         => {}", self.paths.join(", "))
+    }
+}
+
+/// How a pulled `base.apk` relates to what's installed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PulledApkCheck {
+    NotPulled,
+    UpToDate,
+    Outdated { pulled: String, installed: String },
+    /// Same version, but signed by someone else (e.g. an already patched APK)
+    DifferentSigner,
+}
+
+impl PulledApkCheck {
+    pub fn needs_repull(&self) -> bool {
+        !matches!(self, PulledApkCheck::UpToDate)
+    }
+}
+
+impl std::fmt::Display for PulledApkCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PulledApkCheck::NotPulled => write!(f, "No pulled APK found"),
+            PulledApkCheck::UpToDate => write!(f, "The pulled APK matches the installed game"),
+            PulledApkCheck::Outdated { pulled, installed } => write!(
+                f,
+                "The pulled APK is version {}, but {} is installed. Pull it again",
+                pulled, installed
+            ),
+            PulledApkCheck::DifferentSigner => write!(
+                f,
+                "The pulled APK is signed differently from the installed game. Pull it again"
+            ),
+        }
+    }
+}
+
+/// Everything `dumpsys` knows about the Balatro install, or `None` if
+/// it isn't installed
+pub fn balatro_install(server: &mut ADBServer) -> anyhow::Result<Option<BalatroInstall>> {
+    let (installed, paths) = check_balatro_install(server)?;
+    if !installed {
+        return Ok(None);
+    }
+
+    let mut output = StringBuf::new();
+    let mut device = server
+        .get_device()
+        .context("Failed to connect to ADB device")?;
+    device
+        .shell_command(&["dumpsys", "package", BALATRO_PACKAGE], &mut output)
+        .context("Failed to query Balatro's package info")?;
+
+    Ok(Some(BalatroInstall::parse_dumpsys(paths, output.as_string()?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_dumpsys_package() {
+        let dumpsys = "\
+Packages:
+  Package [com.playstack.balatro.android] (a1b2c3):
+    versionCode=1018 minSdk=24 targetSdk=34
+    versionName=1.0.1o
+    signatures=PackageSignatures{9f8e7d6 version:2, signatures:[5b2ac3d1], past signatures:[]}
+    installerPackageName=com.android.vending
+    firstInstallTime=2024-03-10 12:00:01
+    lastUpdateTime=2024-06-02 08:30:45
+";
+
+        let install = BalatroInstall::parse_dumpsys(vec!["/data/app/base.apk".to_string()], dumpsys);
+
+        assert_eq!(install.version_code, Some(1018));
+        assert_eq!(install.version_name.as_deref(), Some("1.0.1o"));
+        assert_eq!(install.installer.as_deref(), Some("com.android.vending"));
+        assert_eq!(install.first_install_time.as_deref(), Some("2024-03-10 12:00:01"));
+        assert_eq!(install.last_update_time.as_deref(), Some("2024-06-02 08:30:45"));
+        assert_eq!(install.signatures, ["5b2ac3d1"]);
+    }
+
+    fn write_apk(path: &Path, version_code: u32, version_name: &str, cert: &[u8]) {
+        use std::io::Write;
+        use zip::write::SimpleFileOptions;
+
+        // Just enough of a PKCS#7 block for `signature_hashes` to find `cert`
+        let cert_len = cert.len() as u8;
+        let signed_data = [
+            &[0x30, 0x0b + cert_len][..],
+            &[0x02, 0x01, 0x01, 0x31, 0x00, 0x30, 0x00],
+            &[0xa0, cert_len],
+            cert,
+            &[0x30, 0x00],
+        ]
+        .concat();
+        let block = [
+            &[0x30, 0x05 + signed_data.len() as u8, 0x06, 0x01, 0x2a, 0xa0][..],
+            &[signed_data.len() as u8],
+            &signed_data,
+        ]
+        .concat();
+
+        let mut apk = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+        let manifest = apk::manifest::tests::compiled_manifest(version_code, version_name);
+        for (name, content) in [
+            ("AndroidManifest.xml", &manifest),
+            ("META-INF/CERT.RSA", &block),
+        ] {
+            apk.start_file(name, SimpleFileOptions::default()).unwrap();
+            apk.write_all(content).unwrap();
+        }
+        apk.finish().unwrap();
+    }
+
+    #[test]
+    fn compares_pulled_apks() {
+        let dir = std::env::temp_dir().join(format!("balapatch_pulled_apk_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let base_apk = dir.join("base.apk");
+
+        let signer = [0x30, 0x03, 0x02, 0x01, 0x07];
+        write_apk(&base_apk, 1018, "1.0.1o", &signer);
+        let install = BalatroInstall {
+            version_code: Some(1018),
+            version_name: Some("1.0.1o".to_string()),
+            signatures: apk::signature::signature_hashes(&base_apk).unwrap(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            install
+                .compare_with_pulled(dir.join("missing.apk"))
+                .unwrap(),
+            PulledApkCheck::NotPulled
+        );
+        assert_eq!(
+            install.compare_with_pulled(&base_apk).unwrap(),
+            PulledApkCheck::UpToDate
+        );
+
+        write_apk(&base_apk, 1017, "1.0.1n", &signer);
+        assert_eq!(
+            install.compare_with_pulled(&base_apk).unwrap(),
+            PulledApkCheck::Outdated {
+                pulled: "1.0.1n".to_string(),
+                installed: "1.0.1o".to_string(),
+            }
+        );
+
+        // Same version, but patched and re-signed by someone else
+        write_apk(&base_apk, 1018, "1.0.1o", &[0x30, 0x03, 0x02, 0x01, 0x08]);
+        assert_eq!(
+            install.compare_with_pulled(&base_apk).unwrap(),
+            PulledApkCheck::DifferentSigner
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,407 @@
+//! Dev mode: iterating on Lua without rebuilding the APK.
+//!
+//! A dev build carries a small module (injected with a module patch before
+//! `main.lua`) that makes `require` look in [`DEV_DIR`] on the device's
+//! external storage before the files packed into the game. Balapatch then
+//! only has to push changed files there and restart the game.
+
+use crate::balapatch::balatro::{self, BALATRO_PACKAGE};
+use crate::balapatch::tui::progress::GLOBAL_MP;
+use adb_client::{ADBDeviceExt, ADBServer};
+use anyhow::Context;
+use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+use crossterm::terminal;
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use walkdir::WalkDir;
+
+/// Where dev files are pushed to. Apps can always read their own
+/// external files directory, so this needs no extra permissions.
+pub static DEV_DIR: Lazy<String> = Lazy::new(|| {
+    format!(
+        "/sdcard/Android/data/{}/files/balapatch_dev",
+        BALATRO_PACKAGE
+    )
+});
+
+/// Name the override module is registered under in `package.preload`
+pub const DEV_MODULE_NAME: &str = "balapatch_dev";
+
+/// Lua run at startup by dev builds. `{{dev_dir}}` is replaced with [`DEV_DIR`].
+const DEV_MODULE: &str = r#"-- Added by balapatch's dev mode: modules pushed to the device are loaded
+-- instead of the ones packed into the game.
+local root = "{{dev_dir}}/"
+local loaders = package.loaders or package.searchers
+
+table.insert(loaders, 2, function(name)
+    local path = root .. (name:gsub("%.", "/")) .. ".lua"
+    local file = io.open(path, "rb")
+    if not file then
+        return "\n\tno file '" .. path .. "'"
+    end
+
+    local content = file:read("*a")
+    file:close()
+
+    local chunk, err = loadstring(content, "@" .. path)
+    if not chunk then
+        error(err, 0)
+    end
+    return chunk
+end)
+
+print("balapatch: dev overrides enabled from " .. root)
+return root
+"#;
+
+/// Write a mod (`lovely.toml` and its module) that turns a build into a
+/// dev build, to be passed to `Patcher` with an `InjectingModuleHandler`.
+/// Returns the path to the `lovely.toml`.
+pub fn write_dev_patch<P: AsRef<Path>>(dir: P) -> anyhow::Result<PathBuf> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create '{}'", dir.display()))?;
+
+    let module_path = dir.join(format!("{}.lua", DEV_MODULE_NAME));
+    fs::write(
+        &module_path,
+        DEV_MODULE.replace("{{dev_dir}}", DEV_DIR.as_str()),
+    )
+    .with_context(|| format!("Failed to write '{}'", module_path.display()))?;
+
+    let manifest = format!(
+        r#"[manifest]
+version = "1.0.0"
+priority = -1000
+
+[[patches]]
+[patches.module]
+source = "{}.lua"
+before = "main.lua"
+name = "{}"
+load_now = true
+"#,
+        DEV_MODULE_NAME, DEV_MODULE_NAME
+    );
+    let manifest_path = dir.join("lovely.toml");
+    fs::write(&manifest_path, manifest)
+        .with_context(|| format!("Failed to write '{}'", manifest_path.display()))?;
+
+    Ok(manifest_path)
+}
+
+/// What a push did on the device, paths relative to the host directory
+#[derive(Debug, Default)]
+pub struct DevPush {
+    pub pushed: Vec<PathBuf>,
+    /// Files deleted from the device because they're gone from the host
+    pub removed: Vec<PathBuf>,
+}
+
+impl DevPush {
+    pub fn is_empty(&self) -> bool {
+        self.pushed.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl Display for DevPush {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Pushed {} file(s)", self.pushed.len())?;
+        if !self.removed.is_empty() {
+            write!(f, ", removed {}", self.removed.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// Files a [`DevSession`] has to sync to the device
+#[derive(Debug, Default, PartialEq)]
+struct DevChanges {
+    /// New files, or ones modified since they were pushed
+    changed: Vec<(PathBuf, SystemTime)>,
+    /// Pushed files that no longer exist on the host
+    removed: Vec<PathBuf>,
+}
+
+/// Keeps track of what has already been pushed from a host directory
+pub struct DevSession {
+    host_dir: PathBuf,
+    pushed: HashMap<PathBuf, SystemTime>,
+    /// Whether files left on the device by earlier sessions were looked up
+    listed_device: bool,
+}
+
+impl DevSession {
+    pub fn new<P: AsRef<Path>>(host_dir: P) -> Self {
+        Self {
+            host_dir: host_dir.as_ref().to_path_buf(),
+            pushed: HashMap::new(),
+            listed_device: false,
+        }
+    }
+
+    /// Push every file that changed since the last push (all of them the
+    /// first time) and delete the ones removed from the host directory
+    pub fn push_changed(&mut self, server: &mut ADBServer) -> anyhow::Result<DevPush> {
+        if !self.listed_device {
+            let mut device = server
+                .get_device()
+                .context("Failed to connect to ADB device")?;
+
+            // `find` exits non-zero when nothing was pushed yet, which isn't an error here
+            let mut output = Vec::new();
+            let _ = device.shell_command(&["find", DEV_DIR.as_str(), "-type", "f"], &mut output);
+            self.track_device_files(&String::from_utf8_lossy(&output));
+            self.listed_device = true;
+        }
+
+        let changes = self.changed_files()?;
+        let mut push = DevPush::default();
+        if changes.changed.is_empty() && changes.removed.is_empty() {
+            return Ok(push);
+        }
+
+        let mut device = server
+            .get_device()
+            .context("Failed to connect to ADB device")?;
+
+        for (relative, modified) in changes.changed {
+            let local = self.host_dir.join(&relative);
+            let remote = remote_path(&relative);
+
+            let mut file = File::open(&local)
+                .with_context(|| format!("Failed to open '{}'", local.display()))?;
+            device
+                .push(&mut file, &remote)
+                .with_context(|| format!("Failed to push '{}' to {}", local.display(), remote))?;
+
+            self.pushed.insert(relative.clone(), modified);
+            push.pushed.push(relative);
+        }
+
+        for relative in changes.removed {
+            let remote = remote_path(&relative);
+            device
+                .shell_command(&["rm", "-f", &remote], &mut std::io::sink())
+                .with_context(|| format!("Failed to remove {}", remote))?;
+
+            self.pushed.remove(&relative);
+            push.removed.push(relative);
+        }
+
+        Ok(push)
+    }
+
+    /// Track the files `find` listed under [`DEV_DIR`] as pushed, so the
+    /// ones no longer on the host get removed
+    fn track_device_files(&mut self, find_output: &str) {
+        let prefix = format!("{}/", DEV_DIR.as_str());
+
+        for line in find_output.lines() {
+            if let Some(relative) = line.trim().strip_prefix(&prefix) {
+                // Never matches a real modification time, so files that are
+                // still on the host get pushed again
+                self.pushed
+                    .entry(PathBuf::from(relative))
+                    .or_insert(SystemTime::UNIX_EPOCH);
+            }
+        }
+    }
+
+    fn changed_files(&self) -> anyhow::Result<DevChanges> {
+        let mut changes = DevChanges::default();
+        let mut present = HashSet::new();
+
+        for entry in WalkDir::new(&self.host_dir) {
+            let entry =
+                entry.with_context(|| format!("Failed to walk '{}'", self.host_dir.display()))?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            let modified = entry
+                .metadata()
+                .context("Failed to read file metadata")?
+                .modified()
+                .context("File modification times aren't supported here")?;
+            let relative = entry
+                .path()
+                .strip_prefix(&self.host_dir)
+                .unwrap_or(entry.path())
+                .to_path_buf();
+
+            if self.pushed.get(&relative) != Some(&modified) {
+                changes.changed.push((relative.clone(), modified));
+            }
+            present.insert(relative);
+        }
+
+        changes.removed = self
+            .pushed
+            .keys()
+            .filter(|relative| !present.contains(*relative))
+            .cloned()
+            .collect();
+        changes.changed.sort();
+        changes.removed.sort();
+
+        Ok(changes)
+    }
+}
+
+/// Where `relative` ends up on the device
+fn remote_path(relative: &Path) -> String {
+    format!(
+        "{}/{}",
+        DEV_DIR.as_str(),
+        relative.to_string_lossy().replace('\\', "/")
+    )
+}
+
+/// Remove everything pushed to the device, turning a dev build back into
+/// the game it was built from
+pub fn clear_dev_dir(server: &mut ADBServer) -> anyhow::Result<()> {
+    let mut device = server
+        .get_device()
+        .context("Failed to connect to ADB device")?;
+
+    device
+        .shell_command(&["rm", "-rf", DEV_DIR.as_str()], &mut std::io::sink())
+        .with_context(|| {
+            format!(
+                "Failed to clear {} for {}",
+                DEV_DIR.as_str(),
+                BALATRO_PACKAGE
+            )
+        })?;
+
+    Ok(())
+}
+
+/// Push `session`'s files and restart Balatro whenever they change, until
+/// `q` is pressed
+pub fn watch(
+    server: &mut ADBServer,
+    session: &mut DevSession,
+    interval: Duration,
+) -> anyhow::Result<()> {
+    terminal::enable_raw_mode().context("Failed to set up the terminal")?;
+    let result = watch_loop(server, session, interval);
+    terminal::disable_raw_mode().context("Failed to restore the terminal")?;
+
+    result
+}
+
+fn watch_loop(
+    server: &mut ADBServer,
+    session: &mut DevSession,
+    interval: Duration,
+) -> anyhow::Result<()> {
+    loop {
+        let pushed = session.push_changed(server)?;
+        if !pushed.is_empty() {
+            balatro::restart_balatro(server)?;
+            GLOBAL_MP.suspend(|| {
+                print!("{} and restarted Balatro\r\n", pushed);
+            });
+        }
+
+        // Waiting on key presses doubles as the poll interval
+        if event::poll(interval).context("Failed to read the keyboard")?
+            && let Event::Key(key) = event::read().context("Failed to read the keyboard")?
+        {
+            let ctrl_c =
+                key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+            if ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn changed(session: &DevSession) -> Vec<PathBuf> {
+        let changes = session.changed_files().unwrap();
+        changes.changed.into_iter().map(|(path, _)| path).collect()
+    }
+
+    #[test]
+    fn writes_a_dev_patch() {
+        let dir = temp_dir("balapatch_dev_patch");
+        let manifest = write_dev_patch(&dir).unwrap();
+
+        let patch: toml::Value = toml::from_str(&fs::read_to_string(&manifest).unwrap()).unwrap();
+        let module = &patch["patches"][0]["module"];
+        assert_eq!(module["source"].as_str(), Some("balapatch_dev.lua"));
+        assert_eq!(module["before"].as_str(), Some("main.lua"));
+
+        let lua = fs::read_to_string(dir.join("balapatch_dev.lua")).unwrap();
+        assert!(lua.contains(&format!("local root = \"{}/\"", DEV_DIR.as_str())));
+        assert!(DEV_DIR.contains(BALATRO_PACKAGE));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tracks_changed_and_removed_files() {
+        let dir = temp_dir("balapatch_dev_session");
+        fs::create_dir_all(dir.join("engine")).unwrap();
+        fs::write(dir.join("main.lua"), "print('main')").unwrap();
+        fs::write(dir.join("engine/ui.lua"), "print('ui')").unwrap();
+
+        let mut session = DevSession::new(&dir);
+        assert_eq!(
+            changed(&session),
+            [PathBuf::from("engine/ui.lua"), PathBuf::from("main.lua")]
+        );
+
+        // As if everything had been pushed
+        for (path, modified) in session.changed_files().unwrap().changed {
+            session.pushed.insert(path, modified);
+        }
+        assert_eq!(session.changed_files().unwrap(), DevChanges::default());
+
+        fs::remove_file(dir.join("engine/ui.lua")).unwrap();
+        fs::write(dir.join("game.lua"), "print('game')").unwrap();
+        let changes = session.changed_files().unwrap();
+        assert_eq!(changed(&session), [PathBuf::from("game.lua")]);
+        assert_eq!(changes.removed, [PathBuf::from("engine/ui.lua")]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn removes_files_left_by_earlier_sessions() {
+        let dir = temp_dir("balapatch_dev_listed");
+        fs::write(dir.join("main.lua"), "print('main')").unwrap();
+
+        let mut session = DevSession::new(&dir);
+        session.track_device_files(&format!(
+            "{dev}/main.lua\n{dev}/functions/old.lua\n",
+            dev = DEV_DIR.as_str()
+        ));
+
+        let changes = session.changed_files().unwrap();
+        assert_eq!(changed(&session), [PathBuf::from("main.lua")]);
+        assert_eq!(changes.removed, [PathBuf::from("functions/old.lua")]);
+        assert_eq!(
+            remote_path(Path::new("functions/old.lua")),
+            format!("{}/functions/old.lua", DEV_DIR.as_str())
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
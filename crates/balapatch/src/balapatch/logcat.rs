@@ -0,0 +1,743 @@
+//! Reading Balatro's output from the device's logcat.
+//!
+//! LÖVE sends `print` output and error screens to logcat, so that's the
+//! only place a Lua traceback from a crashed (patched) game ends up.
+
+use crate::balapatch::balatro::BALATRO_PACKAGE;
+use adb_client::{ADBDeviceExt, ADBServer};
+use anyhow::Context;
+use balapatch_derive::{EnumChoice, EnumDisplay};
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Tags LÖVE and SDL log under
+pub const LOVE_TAGS: &[&str] = &["SDL", "SDL/APP", "LOVE", "love", "lua"];
+
+/// A line of `logcat -v threadtime` output:
+/// `03-14 12:34:56.789  1234  1250 E SDL/APP : message`
+static THREADTIME_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(\d\d-\d\d \d\d:\d\d:\d\d\.\d+)\s+(\d+)\s+(\d+)\s+([VDIWEFS])\s+(.*?)\s*: ?(.*)$")
+        .expect("valid logcat regex")
+});
+
+/// How many lines the live view keeps around for scrolling and saving
+const STREAM_BUFFER_LINES: usize = 5000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, EnumDisplay, EnumChoice)]
+pub enum LogLevel {
+    Verbose,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal,
+    Silent,
+}
+
+impl LogLevel {
+    pub fn from_char(c: char) -> Option<Self> {
+        Some(match c {
+            'V' => LogLevel::Verbose,
+            'D' => LogLevel::Debug,
+            'I' => LogLevel::Info,
+            'W' => LogLevel::Warn,
+            'E' => LogLevel::Error,
+            'F' => LogLevel::Fatal,
+            'S' => LogLevel::Silent,
+            _ => return None,
+        })
+    }
+
+    pub fn as_char(&self) -> char {
+        match self {
+            LogLevel::Verbose => 'V',
+            LogLevel::Debug => 'D',
+            LogLevel::Info => 'I',
+            LogLevel::Warn => 'W',
+            LogLevel::Error => 'E',
+            LogLevel::Fatal => 'F',
+            LogLevel::Silent => 'S',
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogLine {
+    pub time: String,
+    pub pid: u32,
+    pub tid: u32,
+    pub level: LogLevel,
+    pub tag: String,
+    pub message: String,
+}
+
+impl LogLine {
+    pub fn parse(line: &str) -> Option<Self> {
+        let caps = THREADTIME_RE.captures(line.trim_end_matches('\r'))?;
+
+        Some(Self {
+            time: caps[1].to_string(),
+            pid: caps[2].parse().ok()?,
+            tid: caps[3].parse().ok()?,
+            level: LogLevel::from_char(caps[4].chars().next()?)?,
+            tag: caps[5].to_string(),
+            message: caps[6].to_string(),
+        })
+    }
+
+    /// Whether the line came from Balatro (`pids`) or LÖVE
+    pub fn is_balatro(&self, pids: &[u32]) -> bool {
+        pids.contains(&self.pid)
+            || LOVE_TAGS.contains(&self.tag.as_str())
+            || (self.tag == "AndroidRuntime" && self.message.contains(BALATRO_PACKAGE))
+    }
+}
+
+impl Display for LogLine {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {:>5} {:>5} {} {}: {}",
+            self.time,
+            self.pid,
+            self.tid,
+            self.level.as_char(),
+            self.tag,
+            self.message
+        )
+    }
+}
+
+/// A saved capture of Balatro's log
+#[derive(Debug)]
+pub struct CrashLog {
+    pub path: PathBuf,
+    pub lines: Vec<LogLine>,
+    /// Every Lua traceback in the log, error message first
+    pub tracebacks: Vec<String>,
+}
+
+/// Process ids Balatro is currently running as (empty if it isn't running)
+pub fn balatro_pids(server: &mut ADBServer) -> anyhow::Result<Vec<u32>> {
+    let mut device = server
+        .get_device()
+        .context("Failed to connect to ADB device")?;
+    let mut output = Vec::new();
+
+    // `pidof` exits non-zero when nothing matches, which isn't an error here
+    let _ = device.shell_command(&["pidof", BALATRO_PACKAGE], &mut output);
+
+    Ok(String::from_utf8_lossy(&output)
+        .split_whitespace()
+        .filter_map(|pid| pid.parse().ok())
+        .collect())
+}
+
+/// Dump the device's log buffer, keep what Balatro and LÖVE logged and
+/// save it under `out_dir`
+pub fn capture_crash_log(server: &mut ADBServer, out_dir: &str) -> anyhow::Result<CrashLog> {
+    let pids = balatro_pids(server)?;
+    let mut device = server
+        .get_device()
+        .context("Failed to connect to ADB device")?;
+
+    let mut output = Vec::new();
+    device
+        .shell_command(&["logcat", "-d", "-v", "threadtime"], &mut output)
+        .context("Failed to read logcat")?;
+
+    let lines: Vec<LogLine> = String::from_utf8_lossy(&output)
+        .lines()
+        .filter_map(LogLine::parse)
+        .filter(|line| line.is_balatro(&pids))
+        .collect();
+
+    let path = save_log(&lines, out_dir)?;
+    let messages: Vec<&str> = lines.iter().map(|line| line.message.as_str()).collect();
+
+    Ok(CrashLog {
+        path,
+        tracebacks: extract_tracebacks(&messages),
+        lines,
+    })
+}
+
+/// Write `lines` to a new timestamped file in `out_dir`
+pub fn save_log<'a, I>(lines: I, out_dir: &str) -> anyhow::Result<PathBuf>
+where
+    I: IntoIterator<Item = &'a LogLine>,
+{
+    std::fs::create_dir_all(out_dir)
+        .with_context(|| format!("Failed to create log directory '{}'", out_dir))?;
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis())
+        .unwrap_or_default();
+    let path = Path::new(out_dir).join(format!("balatro-{}.log", timestamp));
+
+    let text = lines
+        .into_iter()
+        .map(|line| line.to_string())
+        .collect::<Vec<_>>()
+        .join("\n");
+    std::fs::write(&path, text)
+        .with_context(|| format!("Failed to save log to '{}'", path.display()))?;
+
+    Ok(path)
+}
+
+/// Which lines the live view shows
+#[derive(Debug, Clone)]
+pub struct LogFilter {
+    /// Only show these tags (all of them if empty)
+    pub tags: Vec<String>,
+    pub min_level: LogLevel,
+}
+
+impl LogFilter {
+    pub fn matches(&self, line: &LogLine) -> bool {
+        line.level >= self.min_level && (self.tags.is_empty() || self.tags.contains(&line.tag))
+    }
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        Self {
+            tags: Vec::new(),
+            min_level: LogLevel::Verbose,
+        }
+    }
+}
+
+/// Device-side command for the live view. The shell echoes its pid before
+/// turning into `logcat`, so the stream can be killed once the view closes.
+const STREAM_COMMAND: &str = "echo $$; exec logcat -b main,system,crash,events -v threadtime -T 1";
+
+/// `ActivityManager: Start proc 1234:com.playstack.balatro.android/u0a123 for ...`
+static START_PROC_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^Start proc (\d+):([^/\s]+)").expect("valid start proc regex"));
+
+/// Balatro's new pid if `line` reports the system starting it
+fn started_pid(line: &LogLine) -> Option<u32> {
+    let (pid, process) = match line.tag.as_str() {
+        "ActivityManager" => {
+            let caps = START_PROC_RE.captures(&line.message)?;
+            (caps[1].to_string(), caps[2].to_string())
+        }
+        // `[user, pid, uid, process, type, component]`
+        "am_proc_start" => {
+            let fields: Vec<&str> = line.message.trim_matches(['[', ']']).split(',').collect();
+            (fields.get(1)?.to_string(), fields.get(3)?.to_string())
+        }
+        _ => return None,
+    };
+
+    if process == BALATRO_PACKAGE {
+        pid.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// What the live view has received so far
+#[derive(Debug, Default)]
+struct StreamState {
+    /// The last [`STREAM_BUFFER_LINES`] lines that passed the filter
+    lines: VecDeque<LogLine>,
+    /// Number of lines ever kept, so the view can tell when to redraw
+    received: usize,
+    /// Balatro's pids, including the ones it got after being (re)started
+    pids: Vec<u32>,
+    /// The device-side `logcat`, once the shell has echoed it
+    logcat_pid: Option<u32>,
+}
+
+/// Receives the raw `logcat` stream and keeps Balatro's lines for the view.
+/// Fails the write once `stop` is set so a stream that outlives the view
+/// still ends.
+struct LogStreamWriter {
+    partial: Vec<u8>,
+    filter: LogFilter,
+    state: Arc<Mutex<StreamState>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl LogStreamWriter {
+    fn receive(&self, raw: &str) {
+        let mut state = self.state.lock().unwrap();
+
+        if state.logcat_pid.is_none()
+            && let Ok(pid) = raw.parse()
+        {
+            state.logcat_pid = Some(pid);
+            return;
+        }
+
+        let Some(line) = LogLine::parse(raw) else {
+            return;
+        };
+
+        if let Some(pid) = started_pid(&line)
+            && !state.pids.contains(&pid)
+        {
+            state.pids.push(pid);
+        }
+
+        if !line.is_balatro(&state.pids) || !self.filter.matches(&line) {
+            return;
+        }
+
+        if state.lines.len() == STREAM_BUFFER_LINES {
+            state.lines.pop_front();
+        }
+        state.lines.push_back(line);
+        state.received += 1;
+    }
+}
+
+impl Write for LogStreamWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.stop.load(Ordering::Relaxed) {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "log view closed"));
+        }
+
+        self.partial.extend_from_slice(buf);
+
+        while let Some(end) = self.partial.iter().position(|b| *b == b'\n') {
+            let raw: Vec<u8> = self.partial.drain(..=end).collect();
+            self.receive(String::from_utf8_lossy(&raw).trim_end());
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Range of the `len` buffered lines shown in `height` rows when scrolled
+/// `offset` lines up from the newest one
+fn visible_range(len: usize, height: usize, offset: usize) -> Range<usize> {
+    let end = len.saturating_sub(offset);
+    end.saturating_sub(height)..end
+}
+
+/// Full screen, scrollable view of the buffered lines
+struct LogView {
+    out_dir: String,
+    /// How many lines up from the newest one the view is scrolled, 0 follows the log
+    offset: usize,
+    /// Lines received as of the last redraw
+    seen: usize,
+    status: String,
+}
+
+impl LogView {
+    const KEYS: &'static str = "[↑↓/PgUp/PgDn] scroll, [End] follow, [s] save, [q] quit";
+
+    fn new(out_dir: &str) -> Self {
+        Self {
+            out_dir: out_dir.to_string(),
+            offset: 0,
+            seen: 0,
+            status: Self::KEYS.to_string(),
+        }
+    }
+
+    /// Show the stream until `q` is pressed or the stream ends
+    fn run(
+        &mut self,
+        state: &Mutex<StreamState>,
+        stream: &JoinHandle<anyhow::Result<()>>,
+    ) -> anyhow::Result<()> {
+        let mut redraw = true;
+
+        while !stream.is_finished() {
+            if redraw {
+                self.draw(state)?;
+            }
+
+            redraw = if event::poll(Duration::from_millis(100))
+                .context("Failed to read the keyboard")?
+            {
+                match event::read().context("Failed to read the keyboard")? {
+                    Event::Key(key) => {
+                        let ctrl_c = key.code == KeyCode::Char('c')
+                            && key.modifiers.contains(KeyModifiers::CONTROL);
+                        if ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) {
+                            return Ok(());
+                        }
+                        self.handle_key(key.code, state)?
+                    }
+                    Event::Resize(..) => true,
+                    _ => false,
+                }
+            } else {
+                false
+            };
+
+            let received = state.lock().unwrap().received;
+            if received != self.seen {
+                // Keep a scrolled back view on the same lines as new ones come in
+                if self.offset > 0 {
+                    self.offset += received - self.seen;
+                }
+                redraw = true;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_key(&mut self, code: KeyCode, state: &Mutex<StreamState>) -> anyhow::Result<bool> {
+        let page = Self::rows()?;
+
+        match code {
+            KeyCode::Up | KeyCode::Char('k') => self.offset += 1,
+            KeyCode::Down | KeyCode::Char('j') => self.offset = self.offset.saturating_sub(1),
+            KeyCode::PageUp => self.offset += page,
+            KeyCode::PageDown => self.offset = self.offset.saturating_sub(page),
+            KeyCode::Home => self.offset = usize::MAX,
+            KeyCode::End => self.offset = 0,
+            KeyCode::Char('s') => {
+                let lines = state.lock().unwrap().lines.clone();
+                self.status = match save_log(&lines, &self.out_dir) {
+                    Ok(path) => format!("Saved {} lines to {}", lines.len(), path.display()),
+                    Err(e) => format!("Failed to save the log: {:#}", e),
+                };
+            }
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Rows available for log lines, leaving the last one for the status
+    fn rows() -> anyhow::Result<usize> {
+        let (_, rows) = terminal::size().context("Failed to read the terminal size")?;
+        Ok(rows.saturating_sub(1).max(1) as usize)
+    }
+
+    fn draw(&mut self, state: &Mutex<StreamState>) -> anyhow::Result<()> {
+        let (columns, _) = terminal::size().context("Failed to read the terminal size")?;
+        let rows = Self::rows()?;
+        let state = state.lock().unwrap();
+
+        self.offset = self.offset.min(state.lines.len().saturating_sub(rows));
+        self.seen = state.received;
+
+        let mut stdout = io::stdout();
+        queue!(stdout, Clear(ClearType::All))?;
+        for (row, idx) in visible_range(state.lines.len(), rows, self.offset).enumerate() {
+            let text: String = state.lines[idx]
+                .to_string()
+                .chars()
+                .take(columns as usize)
+                .collect();
+            queue!(stdout, MoveTo(0, row as u16), Print(text))?;
+        }
+
+        let position = if self.offset == 0 {
+            "following".to_string()
+        } else {
+            format!("{} lines up", self.offset)
+        };
+        let status: String = format!(
+            " {} lines, {} | {}",
+            state.lines.len(),
+            position,
+            self.status
+        )
+        .chars()
+        .take(columns as usize)
+        .collect();
+        queue!(
+            stdout,
+            MoveTo(0, rows as u16),
+            SetAttribute(Attribute::Reverse),
+            Print(status),
+            SetAttribute(Attribute::Reset)
+        )?;
+
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+/// Show Balatro's log live in a scrollable view until `q` is pressed. `s`
+/// saves everything received so far (up to the last few thousand lines) to
+/// `out_dir`. Balatro's output keeps showing up when it's restarted.
+pub fn stream_logcat(
+    server: &mut ADBServer,
+    filter: LogFilter,
+    out_dir: &str,
+) -> anyhow::Result<()> {
+    let pids = balatro_pids(server)?;
+    if pids.is_empty() {
+        println!("Balatro isn't running, only LÖVE's own tags will show up until it starts");
+    }
+
+    let mut device = server
+        .get_device()
+        .context("Failed to connect to ADB device")?;
+
+    let state = Arc::new(Mutex::new(StreamState {
+        pids,
+        ..StreamState::default()
+    }));
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let mut writer = LogStreamWriter {
+        partial: Vec::new(),
+        filter,
+        state: Arc::clone(&state),
+        stop: Arc::clone(&stop),
+    };
+    let stream = thread::spawn(move || {
+        device
+            .shell_command(&[STREAM_COMMAND], &mut writer)
+            .context("logcat stopped unexpectedly")
+    });
+
+    terminal::enable_raw_mode().context("Failed to set up the terminal")?;
+    execute!(io::stdout(), EnterAlternateScreen, Hide).context("Failed to set up the terminal")?;
+
+    let viewed = LogView::new(out_dir).run(&state, &stream);
+
+    execute!(io::stdout(), Show, LeaveAlternateScreen).context("Failed to restore the terminal")?;
+    terminal::disable_raw_mode().context("Failed to restore the terminal")?;
+    viewed?;
+
+    if stream.is_finished() {
+        return stream.join().expect("logcat stream panicked");
+    }
+
+    // Closed by the user: kill logcat so the stream ends even on a quiet device
+    stop.store(true, Ordering::Relaxed);
+    let logcat_pid = state.lock().unwrap().logcat_pid;
+    if let Some(pid) = logcat_pid {
+        server
+            .get_device()
+            .context("Failed to connect to ADB device")?
+            .shell_command(&["kill", &pid.to_string()], &mut io::sink())
+            .context("Failed to stop logcat")?;
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while !stream.is_finished() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(20));
+    }
+    if stream.is_finished() {
+        // The stream failing on its way out is expected here
+        let _ = stream.join();
+    }
+
+    println!("Stopped streaming Balatro's log");
+    Ok(())
+}
+
+/// Pull the Lua tracebacks out of a list of log messages
+pub fn extract_tracebacks(messages: &[&str]) -> Vec<String> {
+    let mut tracebacks = Vec::new();
+
+    for (idx, message) in messages.iter().enumerate() {
+        if message.trim() != "stack traceback:" {
+            continue;
+        }
+
+        let mut trace: Vec<&str> = Vec::new();
+        if let Some(error) = idx.checked_sub(1).map(|prev| messages[prev])
+            && error.contains(".lua:")
+        {
+            trace.push(error);
+        }
+        trace.push(message);
+
+        trace.extend(
+            messages[idx + 1..]
+                .iter()
+                .take_while(|line| is_traceback_entry(line)),
+        );
+
+        tracebacks.push(trace.join("\n"));
+    }
+
+    tracebacks
+}
+
+fn is_traceback_entry(line: &str) -> bool {
+    let line = line.trim();
+
+    line.starts_with("[C]")
+        || line.starts_with("[string")
+        || line.starts_with("(...tail calls...)")
+        || line.contains(".lua:")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_threadtime_lines() {
+        let line =
+            LogLine::parse("03-14 12:34:56.789  1234  1250 E SDL/APP : Error: main.lua:5: boom")
+                .unwrap();
+
+        assert_eq!(line.pid, 1234);
+        assert_eq!(line.level, LogLevel::Error);
+        assert_eq!(line.tag, "SDL/APP");
+        assert_eq!(line.message, "Error: main.lua:5: boom");
+        assert!(LogLine::parse("--------- beginning of main").is_none());
+    }
+
+    #[test]
+    fn extracts_tracebacks() {
+        let messages = [
+            "loading",
+            "Error: game.lua:1234: attempt to index a nil value",
+            "stack traceback:",
+            "\t[C]: in function 'error'",
+            "\tgame.lua:1234: in function 'update'",
+            "\tmain.lua:10: in main chunk",
+            "some other output",
+        ];
+
+        assert_eq!(
+            extract_tracebacks(&messages),
+            vec![messages[1..6].join("\n")]
+        );
+    }
+
+    fn line(pid: u32, level: char, tag: &str, message: &str) -> String {
+        format!(
+            "03-14 12:34:56.789 {:>5} {:>5} {} {}: {}\n",
+            pid, pid, level, tag, message
+        )
+    }
+
+    fn writer(filter: LogFilter, pids: Vec<u32>) -> LogStreamWriter {
+        LogStreamWriter {
+            partial: Vec::new(),
+            filter,
+            state: Arc::new(Mutex::new(StreamState {
+                pids,
+                ..StreamState::default()
+            })),
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn messages(writer: &LogStreamWriter) -> Vec<String> {
+        let state = writer.state.lock().unwrap();
+        state
+            .lines
+            .iter()
+            .map(|line| line.message.clone())
+            .collect()
+    }
+
+    #[test]
+    fn filters_by_tag_and_level() {
+        let filter = LogFilter {
+            tags: vec!["LOVE".to_string()],
+            min_level: LogLevel::Warn,
+        };
+        let parse = |line: String| LogLine::parse(line.trim_end()).unwrap();
+
+        assert!(filter.matches(&parse(line(1, 'E', "LOVE", "boom"))));
+        assert!(!filter.matches(&parse(line(1, 'I', "LOVE", "hi"))));
+        assert!(!filter.matches(&parse(line(1, 'E', "SDL", "boom"))));
+        assert!(LogFilter::default().matches(&parse(line(1, 'V', "SDL", "hi"))));
+    }
+
+    #[test]
+    fn keeps_balatro_lines_split_across_writes() {
+        let mut writer = writer(LogFilter::default(), vec![42]);
+        let stream = [
+            "4711\n".to_string(),
+            line(42, 'I', "Unity", "from balatro"),
+            line(7, 'I', "Launcher", "someone else"),
+            line(7, 'E', "LOVE", "from love"),
+        ]
+        .concat();
+
+        let (first, second) = stream.as_bytes().split_at(30);
+        writer.write_all(first).unwrap();
+        writer.write_all(second).unwrap();
+
+        assert_eq!(messages(&writer), ["from balatro", "from love"]);
+        let state = writer.state.lock().unwrap();
+        assert_eq!(state.logcat_pid, Some(4711));
+        assert_eq!(state.received, 2);
+    }
+
+    #[test]
+    fn follows_balatro_across_restarts() {
+        let mut writer = writer(LogFilter::default(), vec![42]);
+        let stream = [
+            line(
+                500,
+                'I',
+                "ActivityManager",
+                &format!("Start proc 43:{}/u0a123 for activity", BALATRO_PACKAGE),
+            ),
+            line(43, 'I', "Unity", "relaunched"),
+            line(
+                500,
+                'I',
+                "am_proc_start",
+                &format!("[0,44,10123,{},activity,x]", BALATRO_PACKAGE),
+            ),
+            line(44, 'I', "Unity", "relaunched again"),
+            line(
+                500,
+                'I',
+                "ActivityManager",
+                "Start proc 45:com.example/u0a1 for service",
+            ),
+            line(45, 'I', "Unity", "not balatro"),
+        ]
+        .concat();
+        writer.write_all(stream.as_bytes()).unwrap();
+
+        assert_eq!(messages(&writer), ["relaunched", "relaunched again"]);
+        assert_eq!(writer.state.lock().unwrap().pids, [42, 43, 44]);
+    }
+
+    #[test]
+    fn stops_once_the_view_closes() {
+        let mut writer = writer(LogFilter::default(), vec![42]);
+        writer.stop.store(true, Ordering::Relaxed);
+
+        assert!(
+            writer
+                .write(line(42, 'I', "Unity", "late").as_bytes())
+                .is_err()
+        );
+        assert!(messages(&writer).is_empty());
+    }
+
+    #[test]
+    fn scrolls_within_the_buffer() {
+        assert_eq!(visible_range(100, 10, 0), 90..100);
+        assert_eq!(visible_range(100, 10, 5), 85..95);
+        assert_eq!(visible_range(100, 10, 95), 0..5);
+        assert_eq!(visible_range(3, 10, 0), 0..3);
+    }
+}
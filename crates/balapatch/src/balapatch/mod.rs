@@ -1,6 +1,8 @@
 pub mod adb;
 pub mod apk;
 pub mod balatro;
+pub mod dev;
+pub mod logcat;
 pub mod patch;
 pub mod tui;
 pub mod utils;
@@ -0,0 +1,346 @@
+//! Changes to a class as a whole rather than to a method's instructions:
+//! adding members, removing and changing fields, and changing access.
+
+use super::MethodFilter;
+use super::dex;
+use anyhow::{Context, bail};
+use smali::types::{Modifier, SmaliClass, SmaliField, SmaliInstruction, SmaliMethod};
+use std::fmt::{Display, Formatter};
+
+/// Java access level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Public,
+    Protected,
+    /// No access modifier
+    Package,
+    Private,
+}
+
+impl Access {
+    fn modifier(&self) -> Option<Modifier> {
+        match self {
+            Access::Public => Some(Modifier::Public),
+            Access::Protected => Some(Modifier::Protected),
+            Access::Package => None,
+            Access::Private => Some(Modifier::Private),
+        }
+    }
+}
+
+/// A change to a class
+pub enum ClassEdit {
+    /// Add the fields and methods in a snippet of smali, e.g.
+    /// `.field public static enabled:Z = true`
+    AddMembers(String),
+    RemoveField(String),
+    /// Set the initial value of a static field, as written in smali
+    /// (`0x1`, `"text"`, ...). `None` removes it.
+    SetFieldValue { name: String, value: Option<String> },
+    SetFieldAccess { name: String, access: Access },
+    /// Change the access of every method the filter accepts. Calls to a
+    /// private method made accessible are switched from `invoke-direct` to
+    /// `invoke-virtual`, since `invoke-direct` is only for private methods
+    /// and constructors.
+    SetMethodAccess { filter: MethodFilter, access: Access },
+}
+
+impl ClassEdit {
+    /// Make the edit, returning how many members it changed
+    pub fn apply(&self, class: &mut SmaliClass) -> anyhow::Result<usize> {
+        match self {
+            ClassEdit::AddMembers(source) => {
+                let members = parse_members(source)?;
+
+                for field in &members.fields {
+                    if class.fields.iter().any(|existing| existing.name == field.name) {
+                        bail!(
+                            "{} already has a field named {}",
+                            class.name.as_java_type(),
+                            field.name
+                        );
+                    }
+                }
+                for method in &members.methods {
+                    let reference = reference_suffix(method);
+                    if class
+                        .methods
+                        .iter()
+                        .any(|existing| reference_suffix(existing) == reference)
+                    {
+                        bail!(
+                            "{} already has a method {} with the same signature",
+                            class.name.as_java_type(),
+                            method.name
+                        );
+                    }
+                }
+
+                let added = members.fields.len() + members.methods.len();
+                class.fields.extend(members.fields);
+                class.methods.extend(members.methods);
+                Ok(added)
+            }
+            ClassEdit::RemoveField(name) => {
+                let before = class.fields.len();
+                class.fields.retain(|field| field.name != *name);
+                Ok(before - class.fields.len())
+            }
+            ClassEdit::SetFieldValue { name, value } => {
+                let mut changed = 0;
+                for field in class.fields.iter_mut().filter(|field| field.name == *name) {
+                    if value.is_some() && !field.modifiers.contains(&Modifier::Static) {
+                        bail!(
+                            "{} isn't static, so it can't have an initial value. Set it in a constructor instead",
+                            name
+                        );
+                    }
+                    field.initial_value = value.clone();
+                    changed += 1;
+                }
+                Ok(changed)
+            }
+            ClassEdit::SetFieldAccess { name, access } => {
+                let mut changed = 0;
+                for field in class.fields.iter_mut().filter(|field| field.name == *name) {
+                    set_access(&mut field.modifiers, *access);
+                    changed += 1;
+                }
+                Ok(changed)
+            }
+            ClassEdit::SetMethodAccess { filter, access } => {
+                let descriptor = class_descriptor(class);
+                let mut opened = Vec::new();
+                let mut changed = 0;
+
+                for method in class.methods.iter_mut().filter(|method| filter(method)) {
+                    if method.modifiers.contains(&Modifier::Private)
+                        && *access != Access::Private
+                        && !method.modifiers.contains(&Modifier::Static)
+                        && method.name != "<init>"
+                    {
+                        opened.push(format!("{}{}", descriptor, reference_suffix(method)));
+                    }
+                    set_access(&mut method.modifiers, *access);
+                    changed += 1;
+                }
+
+                if !opened.is_empty() {
+                    for method in class.methods.iter_mut() {
+                        for instruction in method.instructions.iter_mut() {
+                            if let SmaliInstruction::Instruction(text) = instruction
+                                && text.trim_start().starts_with("invoke-direct")
+                                && dex::invoked_method(text)
+                                    .is_some_and(|called| opened.iter().any(|r| r == called))
+                            {
+                                *text = text.replacen("invoke-direct", "invoke-virtual", 1);
+                            }
+                        }
+                    }
+                }
+
+                Ok(changed)
+            }
+        }
+    }
+}
+
+impl Display for ClassEdit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClassEdit::AddMembers(_) => write!(f, "member addition"),
+            ClassEdit::RemoveField(name) => write!(f, "removal of field {}", name),
+            ClassEdit::SetFieldValue { name, .. } => write!(f, "initial value of field {}", name),
+            ClassEdit::SetFieldAccess { name, .. } => write!(f, "access of field {}", name),
+            ClassEdit::SetMethodAccess { .. } => write!(f, "method access change"),
+        }
+    }
+}
+
+/// Fields and methods parsed from a snippet of smali
+struct Members {
+    fields: Vec<SmaliField>,
+    methods: Vec<SmaliMethod>,
+}
+
+/// Parse `.field`s and `.method`s by wrapping them in a throwaway class
+fn parse_members(source: &str) -> anyhow::Result<Members> {
+    let wrapped = format!(
+        ".class public Lbalapatch/Members;\n.super Ljava/lang/Object;\n\n{}\n",
+        source
+    );
+    let class = SmaliClass::from_smali(&wrapped).context("Failed to parse the smali to add")?;
+
+    Ok(Members {
+        fields: class.fields,
+        methods: class.methods,
+    })
+}
+
+/// How smali refers to `class`, e.g. `Lcom/example/Loader;`
+pub(super) fn class_descriptor(class: &SmaliClass) -> String {
+    format!("L{};", class.name.as_java_type().replace('.', "/"))
+}
+
+/// The end of a reference to `method`, e.g. `->helper(I)V`
+pub(super) fn reference_suffix(method: &SmaliMethod) -> String {
+    let args: String = method
+        .signature
+        .args
+        .iter()
+        .map(|arg| arg.to_jni())
+        .collect();
+
+    format!(
+        "->{}({}){}",
+        method.name,
+        args,
+        method.signature.return_type.to_jni()
+    )
+}
+
+fn set_access(modifiers: &mut Vec<Modifier>, access: Access) {
+    modifiers.retain(|modifier| {
+        !matches!(
+            modifier,
+            Modifier::Public | Modifier::Protected | Modifier::Private
+        )
+    });
+
+    if let Some(modifier) = access.modifier() {
+        modifiers.insert(0, modifier);
+    }
+}
+
+/// Where an injected class goes under a `smali` directory, e.g.
+/// `com/example/Loader.smali` for `Lcom/example/Loader;`
+pub fn class_file_path(source: &str) -> anyhow::Result<String> {
+    let descriptor = source
+        .lines()
+        .map(str::trim)
+        .find(|line| line.starts_with(".class"))
+        .and_then(|line| line.split_whitespace().last())
+        .context("The class to inject has no `.class` line")?;
+
+    let name = descriptor
+        .strip_prefix('L')
+        .and_then(|name| name.strip_suffix(';'))
+        .filter(|name| !name.is_empty())
+        .with_context(|| format!("'{}' isn't a class descriptor", descriptor))?;
+
+    Ok(format!("{}.smali", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::balapatch::patch::instructor::method_filters;
+
+    const GAME: &str = r#".class public Lcom/game/Game;
+.super Ljava/lang/Object;
+
+.field private score:I
+
+.method public constructor <init>()V
+    .locals 1
+
+    invoke-direct {p0}, Ljava/lang/Object;-><init>()V
+
+    new-instance v0, Lcom/game/Other;
+
+    invoke-direct {v0}, Lcom/game/Other;->helper()V
+
+    invoke-direct {p0}, Lcom/game/Game;->helper()V
+
+    return-void
+.end method
+
+.method private helper()V
+    .locals 0
+
+    return-void
+.end method
+"#;
+
+    fn game() -> SmaliClass {
+        SmaliClass::from_smali(GAME).unwrap()
+    }
+
+    fn instructions(method: &SmaliMethod) -> Vec<&str> {
+        method
+            .instructions
+            .iter()
+            .filter_map(|instruction| match instruction {
+                SmaliInstruction::Instruction(text) => Some(text.trim()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn refuses_duplicate_members() {
+        let mut class = game();
+
+        let field = ClassEdit::AddMembers(".field public score:J".to_string());
+        assert!(field.apply(&mut class).is_err());
+
+        let method = ClassEdit::AddMembers(
+            ".method public helper()V\n    .locals 0\n    return-void\n.end method".to_string(),
+        );
+        assert!(method.apply(&mut class).is_err());
+
+        // Only the return type differs from the existing helper, which is
+        // enough for a dex
+        let added = ClassEdit::AddMembers(
+            ".field public static enabled:Z = true\n\n.method public helper()I\n    .locals 1\n    const/4 v0, 0x1\n    return v0\n.end method".to_string(),
+        );
+        assert_eq!(added.apply(&mut class).unwrap(), 2);
+        assert_eq!(class.fields.len(), 2);
+        assert_eq!(class.methods.len(), 3);
+    }
+
+    #[test]
+    fn sets_access() {
+        let mut modifiers = vec![Modifier::Private, Modifier::Static, Modifier::Final];
+
+        set_access(&mut modifiers, Access::Public);
+        assert!(modifiers == [Modifier::Public, Modifier::Static, Modifier::Final]);
+
+        set_access(&mut modifiers, Access::Package);
+        assert!(modifiers == [Modifier::Static, Modifier::Final]);
+    }
+
+    #[test]
+    fn opens_private_methods_to_virtual_calls() {
+        let mut class = game();
+        let edit = ClassEdit::SetMethodAccess {
+            filter: method_filters::named("helper".to_string()),
+            access: Access::Public,
+        };
+
+        assert_eq!(edit.apply(&mut class).unwrap(), 1);
+        assert!(class.methods[1].modifiers.contains(&Modifier::Public));
+        assert_eq!(
+            instructions(&class.methods[0]),
+            [
+                "invoke-direct {p0}, Ljava/lang/Object;-><init>()V",
+                "new-instance v0, Lcom/game/Other;",
+                "invoke-direct {v0}, Lcom/game/Other;->helper()V",
+                "invoke-virtual {p0}, Lcom/game/Game;->helper()V",
+                "return-void",
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_class_file_paths() {
+        let source = "# a loader\n.class public final Lcom/example/Loader$1;\n.super Ljava/lang/Object;\n";
+
+        assert_eq!(
+            class_file_path(source).unwrap(),
+            "com/example/Loader$1.smali"
+        );
+        assert!(class_file_path(".super Ljava/lang/Object;").is_err());
+        assert!(class_file_path(".class public I").is_err());
+    }
+}
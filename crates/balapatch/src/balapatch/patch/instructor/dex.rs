@@ -0,0 +1,324 @@
+//! Multi-dex APKs. apktool decodes each dex file into its own directory:
+//! `classes.dex` into `smali`, `classes2.dex` into `smali_classes2`, and
+//! so on.
+//!
+//! A dex file can reference at most 65536 methods and 65536 fields,
+//! counting both the ones it defines and the ones it uses, so new classes
+//! have to go in a dex with room for theirs.
+
+use super::class_edit::{class_descriptor, reference_suffix};
+use anyhow::{Context, bail};
+use smali::types::{SmaliClass, SmaliInstruction};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Most method references a single dex file can have
+pub const MAX_METHOD_REFS: usize = 65536;
+
+/// Most field references a single dex file can have
+pub const MAX_FIELD_REFS: usize = 65536;
+
+/// apktool's smali directories in `work_dir`, in dex order
+pub fn smali_roots(work_dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let entries =
+        fs::read_dir(work_dir).with_context(|| format!("Failed to read {}", work_dir.display()))?;
+
+    let mut roots: Vec<(u32, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            dex_number(&name).map(|number| (number, entry.path()))
+        })
+        .collect();
+    roots.sort();
+
+    if roots.is_empty() {
+        bail!("{} has no smali directories", work_dir.display());
+    }
+
+    Ok(roots.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Which dex a smali directory came from, e.g. `classes2.dex` for
+/// `smali_classes2`
+pub fn dex_name(root: &Path) -> String {
+    let name = root
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+
+    match dex_number(&name) {
+        Some(1) | None => "classes.dex".to_string(),
+        Some(number) => format!("classes{}.dex", number),
+    }
+}
+
+/// The smali directory for dex number `number`
+pub fn root_for(work_dir: &Path, number: usize) -> PathBuf {
+    match number {
+        1 => work_dir.join("smali"),
+        _ => work_dir.join(format!("smali_classes{}", number)),
+    }
+}
+
+/// The number the next new dex gets, one past the highest in `roots`
+pub fn next_dex_number(roots: &[PathBuf]) -> usize {
+    roots
+        .iter()
+        .filter_map(|root| dex_number(&root.file_name()?.to_string_lossy()))
+        .max()
+        .unwrap_or(0) as usize
+        + 1
+}
+
+/// `smali` is dex 1, `smali_classesN` is dex N. Anything else, like
+/// `smali_assets`, isn't a dex apktool will build into the APK's root.
+fn dex_number(dir_name: &str) -> Option<u32> {
+    if dir_name == "smali" {
+        return Some(1);
+    }
+
+    dir_name
+        .strip_prefix("smali_classes")?
+        .parse()
+        .ok()
+        .filter(|number| *number > 1)
+}
+
+/// The methods and fields a class, or a whole dex, references
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DexRefs {
+    /// As `Lcom/Foo;->bar(I)V`
+    pub methods: HashSet<String>,
+    /// As `Lcom/Foo;->baz`. Fields are told apart by name only, so two of
+    /// a class's fields that only differ in type count once.
+    pub fields: HashSet<String>,
+}
+
+impl DexRefs {
+    /// Every method and field `class` defines or uses
+    pub fn of(class: &SmaliClass) -> Self {
+        let descriptor = class_descriptor(class);
+        let mut refs = DexRefs {
+            methods: class
+                .methods
+                .iter()
+                .map(|method| format!("{}{}", descriptor, reference_suffix(method)))
+                .collect(),
+            fields: class
+                .fields
+                .iter()
+                .map(|field| format!("{}->{}", descriptor, field.name))
+                .collect(),
+        };
+
+        for method in &class.methods {
+            for instruction in &method.instructions {
+                let SmaliInstruction::Instruction(text) = instruction else {
+                    continue;
+                };
+                if let Some(called) = invoked_method(text) {
+                    refs.methods.insert(called.to_string());
+                } else if let Some(field) = accessed_field(text) {
+                    refs.fields.insert(field.to_string());
+                }
+            }
+        }
+
+        refs
+    }
+
+    pub fn extend(&mut self, other: DexRefs) {
+        self.methods.extend(other.methods);
+        self.fields.extend(other.fields);
+    }
+
+    /// Whether adding `other` keeps both counts within a dex's limits
+    fn has_room_for(&self, other: &DexRefs) -> bool {
+        self.methods.len() + other.methods.difference(&self.methods).count() <= MAX_METHOD_REFS
+            && self.fields.len() + other.fields.difference(&self.fields).count() <= MAX_FIELD_REFS
+    }
+}
+
+/// Pick a dex for each new class, given the references every dex already
+/// has and each class's own. A class goes in the first dex it fits in, and
+/// when none has room a new dex is added to `dex_refs`. Returns an index
+/// into `dex_refs` for each class.
+pub fn place_classes(dex_refs: &mut Vec<DexRefs>, class_refs: Vec<DexRefs>) -> Vec<usize> {
+    class_refs
+        .into_iter()
+        .map(|refs| {
+            let dex = dex_refs
+                .iter()
+                .position(|existing| existing.has_room_for(&refs))
+                .unwrap_or_else(|| {
+                    dex_refs.push(DexRefs::default());
+                    dex_refs.len() - 1
+                });
+
+            dex_refs[dex].extend(refs);
+            dex
+        })
+        .collect()
+}
+
+/// The method an `invoke-*` instruction calls
+pub(super) fn invoked_method(instruction: &str) -> Option<&str> {
+    let instruction = instruction.trim();
+    if !instruction.starts_with("invoke-") {
+        return None;
+    }
+
+    instruction
+        .split(", ")
+        .map(str::trim)
+        .find(|operand| operand.contains("->") && operand.contains('('))
+}
+
+/// The field an `iget*`, `iput*`, `sget*` or `sput*` instruction uses,
+/// without its type
+fn accessed_field(instruction: &str) -> Option<&str> {
+    let instruction = instruction.trim();
+    if !["iget", "iput", "sget", "sput"]
+        .iter()
+        .any(|opcode| instruction.starts_with(opcode))
+    {
+        return None;
+    }
+
+    let field = instruction.rsplit(", ").next()?.trim();
+    field
+        .contains("->")
+        .then(|| field.split_once(':').map_or(field, |(name, _)| name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_smali_roots() {
+        let dir =
+            std::env::temp_dir().join(format!("balapatch_smali_roots_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for name in ["smali_classes10", "smali", "smali_assets", "smali_classes2"] {
+            fs::create_dir_all(dir.join(name)).unwrap();
+        }
+
+        let names: Vec<String> = smali_roots(&dir)
+            .unwrap()
+            .iter()
+            .map(|root| dex_name(root))
+            .collect();
+        assert_eq!(names, ["classes.dex", "classes2.dex", "classes10.dex"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn numbers_new_dexes_after_the_highest() {
+        let roots = [
+            PathBuf::from("work/smali"),
+            PathBuf::from("work/smali_classes3"),
+            PathBuf::from("work/smali_assets"),
+        ];
+
+        assert_eq!(next_dex_number(&roots), 4);
+        assert_eq!(next_dex_number(&[]), 1);
+    }
+
+    fn refs(prefix: &str, count: usize) -> HashSet<String> {
+        (0..count)
+            .map(|idx| format!("L{};->m{}()V", prefix, idx))
+            .collect()
+    }
+
+    fn methods(methods: HashSet<String>) -> DexRefs {
+        DexRefs {
+            methods,
+            ..DexRefs::default()
+        }
+    }
+
+    #[test]
+    fn places_classes_where_they_fit() {
+        let mut dex_refs = vec![
+            methods(refs("Full", MAX_METHOD_REFS - 1)),
+            methods(refs("Small", 10)),
+        ];
+
+        let placed = place_classes(
+            &mut dex_refs,
+            vec![
+                // Fits in the nearly full dex
+                methods(refs("A", 1)),
+                // Doesn't anymore, but calls into the second dex, so only
+                // one of its references is new there
+                methods(refs("Small", 10).union(&refs("B", 1)).cloned().collect()),
+                methods(refs("C", MAX_METHOD_REFS)),
+                methods(refs("D", 1)),
+            ],
+        );
+
+        assert_eq!(placed, [0, 1, 2, 1]);
+        assert_eq!(dex_refs.len(), 3);
+        assert_eq!(dex_refs[0].methods.len(), MAX_METHOD_REFS);
+        assert_eq!(dex_refs[1].methods.len(), 12);
+    }
+
+    #[test]
+    fn counts_field_refs_separately() {
+        let mut dex_refs = vec![DexRefs {
+            methods: refs("Few", 10),
+            fields: refs("Full", MAX_FIELD_REFS),
+        }];
+
+        let placed = place_classes(
+            &mut dex_refs,
+            vec![
+                // Only uses fields the dex already has
+                DexRefs {
+                    methods: refs("A", 1),
+                    fields: refs("Full", 5),
+                },
+                DexRefs {
+                    methods: refs("B", 1),
+                    fields: refs("B", 1),
+                },
+            ],
+        );
+
+        assert_eq!(placed, [0, 1]);
+        assert_eq!(dex_refs[0].fields.len(), MAX_FIELD_REFS);
+    }
+
+    #[test]
+    fn finds_accessed_fields() {
+        assert_eq!(
+            accessed_field("iget-object v0, p0, Lcom/Foo;->name:Ljava/lang/String;"),
+            Some("Lcom/Foo;->name")
+        );
+        assert_eq!(
+            accessed_field("sput-wide v0, Lcom/Foo;->total:J"),
+            Some("Lcom/Foo;->total")
+        );
+        assert_eq!(accessed_field("invoke-static {}, Lcom/Foo;->baz()I"), None);
+        assert_eq!(accessed_field("const/4 v0, 0x1"), None);
+    }
+
+    #[test]
+    fn finds_invoked_methods() {
+        assert_eq!(
+            invoked_method("invoke-virtual {p0, v1}, Lcom/Foo;->bar(ILjava/lang/String;)V"),
+            Some("Lcom/Foo;->bar(ILjava/lang/String;)V")
+        );
+        assert_eq!(
+            invoked_method("invoke-static/range {v0 .. v5}, Lcom/Foo;->baz()I"),
+            Some("Lcom/Foo;->baz()I")
+        );
+        assert_eq!(invoked_method("iget v0, p0, Lcom/Foo;->x:I"), None);
+    }
+}
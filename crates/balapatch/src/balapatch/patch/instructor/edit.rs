@@ -0,0 +1,358 @@
+//! Edits within a method body, for when replacing the whole thing would
+//! throw away too much of it.
+//!
+//! Matching only looks at plain instructions, skipping over labels, `.line`s
+//! and other directives in between. A replaced sequence takes its `.line`s
+//! with it, but one that spans a label or `.catch` is rejected rather than
+//! moving where a branch lands or what a try block covers.
+
+use super::InstructionGenerator;
+use regex::Regex;
+use smali::types::SmaliInstruction;
+use std::fmt::{Display, Formatter};
+
+/// Matches a single instruction, compared with surrounding whitespace trimmed
+pub enum InstructionMatcher {
+    /// The whole instruction, e.g. `invoke-static {}, Lcom/Foo;->check()Z`
+    Exact(String),
+    /// Just the opcode, e.g. `move-result`
+    Opcode(String),
+    Contains(String),
+    Regex(Regex),
+    Any,
+}
+
+impl InstructionMatcher {
+    pub fn matches(&self, instruction: &str) -> bool {
+        let instruction = instruction.trim();
+
+        match self {
+            InstructionMatcher::Exact(expected) => instruction == expected.trim(),
+            InstructionMatcher::Opcode(opcode) => {
+                instruction.split_whitespace().next() == Some(opcode.as_str())
+            }
+            InstructionMatcher::Contains(needle) => instruction.contains(needle.as_str()),
+            InstructionMatcher::Regex(regex) => regex.is_match(instruction),
+            InstructionMatcher::Any => true,
+        }
+    }
+}
+
+/// A run of consecutive instructions, one matcher each
+pub struct InstructionPattern {
+    matchers: Vec<InstructionMatcher>,
+}
+
+impl InstructionPattern {
+    pub fn new(matchers: Vec<InstructionMatcher>) -> Self {
+        Self { matchers }
+    }
+
+    /// Every non-overlapping match, as the indices of its first and last
+    /// instruction in `instructions`
+    pub fn find_all(&self, instructions: &[SmaliInstruction]) -> Vec<(usize, usize)> {
+        if self.matchers.is_empty() {
+            return Vec::new();
+        }
+
+        let plain: Vec<(usize, &str)> = instructions
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, instruction)| match instruction {
+                SmaliInstruction::Instruction(text) => Some((idx, text.as_str())),
+                _ => None,
+            })
+            .collect();
+
+        let mut found = Vec::new();
+        let mut start = 0;
+        while start + self.matchers.len() <= plain.len() {
+            let window = &plain[start..start + self.matchers.len()];
+            let matched = window
+                .iter()
+                .zip(&self.matchers)
+                .all(|((_, text), matcher)| matcher.matches(text));
+
+            if matched {
+                found.push((window[0].0, window[window.len() - 1].0));
+                start += self.matchers.len();
+            } else {
+                start += 1;
+            }
+        }
+
+        found
+    }
+}
+
+/// A change to a method's instructions.
+///
+/// Injected code runs with the method's registers as they are at that
+/// point, so it must only use registers that are free there (and not the
+/// one being returned, for [`MethodEdit::InjectBeforeReturns`]). Code
+/// that's inserted more than once can't define labels.
+pub enum MethodEdit {
+    /// Throw away the body and use these instructions instead
+    ReplaceBody(InstructionGenerator),
+    /// Run these instructions before anything else in the method
+    InjectAtEntry(InstructionGenerator),
+    /// Run these instructions right before every `return*`
+    InjectBeforeReturns(InstructionGenerator),
+    /// Surround every match of `pattern`
+    Wrap {
+        pattern: InstructionPattern,
+        before: InstructionGenerator,
+        after: InstructionGenerator,
+    },
+    /// Swap every match of `pattern` for other instructions
+    ReplaceSequence {
+        pattern: InstructionPattern,
+        with: InstructionGenerator,
+    },
+}
+
+impl MethodEdit {
+    /// Make the edit, returning how many places it was made in. Nothing
+    /// is changed if it fails.
+    pub fn apply(&self, instructions: &mut Vec<SmaliInstruction>) -> anyhow::Result<usize> {
+        Ok(match self {
+            MethodEdit::ReplaceBody(generator) => {
+                *instructions = generator();
+                1
+            }
+            MethodEdit::InjectAtEntry(generator) => {
+                instructions.splice(0..0, generator());
+                1
+            }
+            MethodEdit::InjectBeforeReturns(generator) => {
+                let returns: Vec<usize> = instructions
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, instruction)| is_return(instruction))
+                    .map(|(idx, _)| idx)
+                    .collect();
+
+                // Back to front, so earlier indices stay valid
+                for idx in returns.iter().rev() {
+                    instructions.splice(*idx..*idx, generator());
+                }
+                returns.len()
+            }
+            MethodEdit::Wrap {
+                pattern,
+                before,
+                after,
+            } => {
+                let matches = pattern.find_all(instructions);
+                for (first, last) in matches.iter().rev() {
+                    instructions.splice(last + 1..last + 1, after());
+                    instructions.splice(*first..*first, before());
+                }
+                matches.len()
+            }
+            MethodEdit::ReplaceSequence { pattern, with } => {
+                let matches = pattern.find_all(instructions);
+                if let Some(directive) = matches
+                    .iter()
+                    .flat_map(|(first, last)| &instructions[*first..=*last])
+                    .find(|instruction| !is_replaceable(instruction))
+                {
+                    anyhow::bail!(
+                        "A match spans `{:?}`, which replacing it would move. Match only the instructions before or after it",
+                        directive
+                    );
+                }
+
+                for (first, last) in matches.iter().rev() {
+                    instructions.splice(*first..=*last, with());
+                }
+                matches.len()
+            }
+        })
+    }
+
+    /// Every instruction the edit adds, for checking what registers it uses
+    pub fn generated(&self) -> Vec<SmaliInstruction> {
+        match self {
+            MethodEdit::ReplaceBody(generator)
+            | MethodEdit::InjectAtEntry(generator)
+            | MethodEdit::InjectBeforeReturns(generator)
+            | MethodEdit::ReplaceSequence {
+                with: generator, ..
+            } => generator(),
+            MethodEdit::Wrap { before, after, .. } => {
+                let mut generated = before();
+                generated.extend(after());
+                generated
+            }
+        }
+    }
+}
+
+impl Display for MethodEdit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            MethodEdit::ReplaceBody(_) => "body replacement",
+            MethodEdit::InjectAtEntry(_) => "entry injection",
+            MethodEdit::InjectBeforeReturns(_) => "injection before returns",
+            MethodEdit::Wrap { .. } => "wrap",
+            MethodEdit::ReplaceSequence { .. } => "sequence replacement",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Whether `instruction` can go along with a sequence replaced around it
+fn is_replaceable(instruction: &SmaliInstruction) -> bool {
+    matches!(
+        instruction,
+        SmaliInstruction::Instruction(_) | SmaliInstruction::Line(_)
+    )
+}
+
+fn is_return(instruction: &SmaliInstruction) -> bool {
+    matches!(
+        instruction,
+        SmaliInstruction::Instruction(text) if text.trim_start().starts_with("return")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use SmaliInstruction::Instruction;
+    use smali::types::SmaliClass;
+
+    fn instructions(lines: &[&str]) -> Vec<SmaliInstruction> {
+        lines
+            .iter()
+            .map(|line| Instruction(line.to_string()))
+            .collect()
+    }
+
+    fn generator(lines: &'static [&'static str]) -> InstructionGenerator {
+        Box::new(move || instructions(lines))
+    }
+
+    #[test]
+    fn replaces_and_wraps_sequences() {
+        let mut body = instructions(&[
+            "invoke-static {}, Lcom/Foo;->isLicensed()Z",
+            "move-result v0",
+            "if-eqz v0, :cond_0",
+            "return-void",
+        ]);
+
+        let replace = MethodEdit::ReplaceSequence {
+            pattern: InstructionPattern::new(vec![
+                InstructionMatcher::Contains("->isLicensed()Z".to_string()),
+                InstructionMatcher::Opcode("move-result".to_string()),
+            ]),
+            with: generator(&["const/4 v0, 0x1"]),
+        };
+        assert_eq!(replace.apply(&mut body).unwrap(), 1);
+
+        let inject = MethodEdit::InjectBeforeReturns(generator(&["nop"]));
+        assert_eq!(inject.apply(&mut body).unwrap(), 1);
+
+        assert_eq!(
+            body,
+            instructions(&[
+                "const/4 v0, 0x1",
+                "if-eqz v0, :cond_0",
+                "nop",
+                "return-void"
+            ])
+        );
+    }
+
+    #[test]
+    fn injects_at_entry_and_before_every_return() {
+        let mut body = instructions(&[
+            "if-eqz p0, :cond_0",
+            "return-void",
+            "const/4 v0, 0x0",
+            "return-void",
+        ]);
+
+        let entry = MethodEdit::InjectAtEntry(generator(&["const/4 v1, 0x1"]));
+        assert_eq!(entry.apply(&mut body).unwrap(), 1);
+        let returns = MethodEdit::InjectBeforeReturns(generator(&["nop"]));
+        assert_eq!(returns.apply(&mut body).unwrap(), 2);
+
+        assert_eq!(
+            body,
+            instructions(&[
+                "const/4 v1, 0x1",
+                "if-eqz p0, :cond_0",
+                "nop",
+                "return-void",
+                "const/4 v0, 0x0",
+                "nop",
+                "return-void",
+            ])
+        );
+    }
+
+    #[test]
+    fn wraps_every_match() {
+        let mut body = instructions(&[
+            "invoke-virtual {v0}, Lcom/Foo;->save()V",
+            "const/4 v1, 0x0",
+            "invoke-virtual {v0}, Lcom/Foo;->save()V",
+        ]);
+
+        let wrap = MethodEdit::Wrap {
+            pattern: InstructionPattern::new(vec![InstructionMatcher::Contains(
+                "->save()V".to_string(),
+            )]),
+            before: generator(&["monitor-enter v0"]),
+            after: generator(&["monitor-exit v0"]),
+        };
+        assert_eq!(wrap.apply(&mut body).unwrap(), 2);
+
+        assert_eq!(
+            body,
+            instructions(&[
+                "monitor-enter v0",
+                "invoke-virtual {v0}, Lcom/Foo;->save()V",
+                "monitor-exit v0",
+                "const/4 v1, 0x0",
+                "monitor-enter v0",
+                "invoke-virtual {v0}, Lcom/Foo;->save()V",
+                "monitor-exit v0",
+            ])
+        );
+    }
+
+    #[test]
+    fn keeps_lines_but_rejects_labels_inside_replaced_sequences() {
+        let body = |between: &str| {
+            let class = SmaliClass::from_smali(&format!(
+                ".class public Lcom/game/Game;\n.super Ljava/lang/Object;\n\n\
+                 .method public static score()I\n    .locals 1\n\n    \
+                 const/4 v0, 0x0\n\n    {}\n\n    return v0\n.end method\n",
+                between
+            ))
+            .unwrap();
+            class.methods[0].instructions.clone()
+        };
+        let replace = MethodEdit::ReplaceSequence {
+            pattern: InstructionPattern::new(vec![
+                InstructionMatcher::Opcode("const/4".to_string()),
+                InstructionMatcher::Opcode("return".to_string()),
+            ]),
+            with: generator(&["const/4 v0, 0x1", "return v0"]),
+        };
+
+        let mut with_line = body(".line 12");
+        assert_eq!(replace.apply(&mut with_line).unwrap(), 1);
+        assert_eq!(with_line, instructions(&["const/4 v0, 0x1", "return v0"]));
+
+        // A branch to `:cond_0` must still land on the `return`
+        let mut with_label = body(":cond_0");
+        let original = with_label.clone();
+        assert!(replace.apply(&mut with_label).is_err());
+        assert_eq!(with_label, original);
+    }
+}
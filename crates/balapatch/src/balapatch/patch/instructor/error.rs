@@ -0,0 +1,157 @@
+//! Errors from loading smali patch files.
+//!
+//! Like `lovely.toml` errors, these keep the file's text around so
+//! [`SmaliPatchError::render`] can show the offending key.
+
+use crate::balapatch::patch::the_lovers::error::Snippet;
+use miette::{Diagnostic, GraphicalReportHandler, LabeledSpan, SourceCode};
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum SmaliPatchError {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// Not valid TOML, or doesn't fit the patch format
+    Parse {
+        path: PathBuf,
+        message: String,
+        snippet: Snippet,
+    },
+    /// Fits the format, but a value can't be used, e.g. a broken regex.
+    /// `key` is the path to it, like `patches[0].methods[1].edits[0].pattern[2]`.
+    Invalid {
+        path: PathBuf,
+        key: String,
+        message: String,
+        snippet: Snippet,
+    },
+}
+
+impl SmaliPatchError {
+    pub fn io<P: AsRef<Path>>(path: P, source: std::io::Error) -> Self {
+        SmaliPatchError::Io {
+            path: path.as_ref().to_path_buf(),
+            source,
+        }
+    }
+
+    pub fn parse<P: AsRef<Path>>(path: P, text: &str, err: toml::de::Error) -> Self {
+        let message = err.message().to_string();
+        let labels = err
+            .span()
+            .map(|span| vec![LabeledSpan::new_with_span(Some(message.clone()), span)])
+            .unwrap_or_default();
+
+        SmaliPatchError::Parse {
+            path: path.as_ref().to_path_buf(),
+            message,
+            snippet: Snippet::new(path, text, labels),
+        }
+    }
+
+    pub fn invalid<P: AsRef<Path>>(
+        path: P,
+        text: &str,
+        key: String,
+        span: Range<usize>,
+        message: String,
+    ) -> Self {
+        let labels = vec![LabeledSpan::new_with_span(Some(message.clone()), span)];
+
+        SmaliPatchError::Invalid {
+            path: path.as_ref().to_path_buf(),
+            key,
+            message,
+            snippet: Snippet::new(path, text, labels),
+        }
+    }
+
+    /// Pretty print the error with a snippet of the patch file
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        if GraphicalReportHandler::new()
+            .render_report(&mut out, self)
+            .is_err()
+        {
+            return self.to_string();
+        }
+
+        out
+    }
+
+    fn snippet(&self) -> Option<&Snippet> {
+        match self {
+            SmaliPatchError::Parse { snippet, .. } | SmaliPatchError::Invalid { snippet, .. } => {
+                Some(snippet)
+            }
+            SmaliPatchError::Io { .. } => None,
+        }
+    }
+}
+
+impl Display for SmaliPatchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SmaliPatchError::Io { path, source } => {
+                write!(f, "IO Error: '{}': {}", path.display(), source)
+            }
+            SmaliPatchError::Parse { path, message, .. } => {
+                write!(f, "TOML Parse Error: '{}': {}", path.display(), message)
+            }
+            SmaliPatchError::Invalid {
+                path, key, message, ..
+            } => write!(
+                f,
+                "Smali Patch Error: '{}' at `{}`: {}",
+                path.display(),
+                key,
+                message
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SmaliPatchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SmaliPatchError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl Diagnostic for SmaliPatchError {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        let code = match self {
+            SmaliPatchError::Io { .. } => "balapatch::smali::io",
+            SmaliPatchError::Parse { .. } => "balapatch::smali::parse",
+            SmaliPatchError::Invalid { .. } => "balapatch::smali::invalid",
+        };
+
+        Some(Box::new(code))
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        match self {
+            SmaliPatchError::Parse { .. } => Some(Box::new(
+                "smali patch files need `[manifest]` plus `[[patches]]` tables with `class` and `methods`",
+            )),
+            _ => None,
+        }
+    }
+
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        self.snippet().map(|s| &s.source as &dyn SourceCode)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        self.snippet()
+            .filter(|s| !s.labels.is_empty())
+            .map(|s| Box::new(s.labels.iter().cloned()) as Box<dyn Iterator<Item = LabeledSpan>>)
+    }
+}
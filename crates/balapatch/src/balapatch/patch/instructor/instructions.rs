@@ -0,0 +1,300 @@
+//! Helper functions to create common instruction patterns.
+//!
+//! Constants are loaded with the smallest `const*` opcode that can hold
+//! them, since e.g. `const/4` only has room for -8..=7.
+
+use super::{InstructionGenerator, InstructionMatcher, InstructionPattern, MethodEdit};
+use SmaliInstruction::Instruction;
+use smali::types::SmaliInstruction;
+
+/// A value for a method to return
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReturnValue {
+    Void,
+    Boolean(bool),
+    /// Also used for `byte`, `short` and `char`
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    String(String),
+    Null,
+}
+
+impl ReturnValue {
+    /// Load the value into `register` (and the one after it, for wide
+    /// values) and return it
+    pub fn instructions(&self, register: u32) -> Vec<SmaliInstruction> {
+        let load = match self {
+            ReturnValue::Void => None,
+            ReturnValue::Boolean(value) => Some(load_int(register, *value as i32)),
+            ReturnValue::Int(value) => Some(load_int(register, *value)),
+            ReturnValue::Float(value) => Some(load_int(register, value.to_bits() as i32)),
+            ReturnValue::Long(value) => Some(load_long(register, *value)),
+            ReturnValue::Double(value) => Some(load_long(register, value.to_bits() as i64)),
+            ReturnValue::String(value) => Some(load_string(register, value)),
+            ReturnValue::Null => Some(load_int(register, 0)),
+        };
+
+        let opcode = self.return_opcode();
+        let ret = match self {
+            ReturnValue::Void => Instruction(opcode.to_string()),
+            _ => Instruction(format!("{} v{}", opcode, register)),
+        };
+
+        load.into_iter().chain([ret]).collect()
+    }
+
+    /// The `return*` opcode for methods returning this type of value
+    pub fn return_opcode(&self) -> &'static str {
+        match self {
+            ReturnValue::Void => "return-void",
+            ReturnValue::Long(_) | ReturnValue::Double(_) => "return-wide",
+            ReturnValue::String(_) | ReturnValue::Null => "return-object",
+            _ => "return",
+        }
+    }
+}
+
+/// `const*` instruction putting `value` in `v<register>`
+pub fn load_int(register: u32, value: i32) -> SmaliInstruction {
+    let opcode = if (-8..=7).contains(&value) {
+        "const/4"
+    } else if i16::try_from(value).is_ok() {
+        "const/16"
+    } else if value & 0xffff == 0 {
+        "const/high16"
+    } else {
+        "const"
+    };
+
+    Instruction(format!("{} v{}, {}", opcode, register, hex(value as i64)))
+}
+
+/// `const-wide*` instruction putting `value` in `v<register>` and the one after it
+pub fn load_long(register: u32, value: i64) -> SmaliInstruction {
+    let opcode = if i16::try_from(value).is_ok() {
+        "const-wide/16"
+    } else if i32::try_from(value).is_ok() {
+        "const-wide/32"
+    } else if value & 0xffff_ffff_ffff == 0 {
+        "const-wide/high16"
+    } else {
+        "const-wide"
+    };
+
+    Instruction(format!("{} v{}, {}L", opcode, register, hex(value)))
+}
+
+/// `const-string` instruction putting `value` in `v<register>`
+pub fn load_string(register: u32, value: &str) -> SmaliInstruction {
+    Instruction(format!("const-string v{}, \"{}\"", register, escape(value)))
+}
+
+/// Create a pattern that returns `value` straight away
+pub fn return_value(value: ReturnValue) -> InstructionGenerator {
+    Box::new(move || value.instructions(0))
+}
+
+/// An edit that runs the original method as usual, but returns `value`
+/// from every `return` instead of what the method computed
+pub fn override_return(value: ReturnValue) -> MethodEdit {
+    MethodEdit::ReplaceSequence {
+        pattern: InstructionPattern::new(vec![InstructionMatcher::Opcode(
+            value.return_opcode().to_string(),
+        )]),
+        with: return_value(value),
+    }
+}
+
+/// Create a simple pattern that returns a constant boolean value
+pub fn return_boolean(value: bool) -> InstructionGenerator {
+    return_value(ReturnValue::Boolean(value))
+}
+
+/// Create a simple pattern that returns a constant int value
+pub fn return_int(value: i32) -> InstructionGenerator {
+    return_value(ReturnValue::Int(value))
+}
+
+/// Create a simple pattern that returns a constant long value
+pub fn return_long(value: i64) -> InstructionGenerator {
+    return_value(ReturnValue::Long(value))
+}
+
+/// Create a simple pattern that returns a constant float value
+pub fn return_float(value: f32) -> InstructionGenerator {
+    return_value(ReturnValue::Float(value))
+}
+
+/// Create a simple pattern that returns a constant double value
+pub fn return_double(value: f64) -> InstructionGenerator {
+    return_value(ReturnValue::Double(value))
+}
+
+/// Create a simple pattern that returns a constant string
+pub fn return_string(value: impl Into<String>) -> InstructionGenerator {
+    return_value(ReturnValue::String(value.into()))
+}
+
+/// Create a pattern that returns null
+pub fn return_null() -> InstructionGenerator {
+    return_value(ReturnValue::Null)
+}
+
+/// Create a pattern that returns from a void method straight away
+pub fn return_void() -> InstructionGenerator {
+    return_value(ReturnValue::Void)
+}
+
+/// Create custom instructions
+pub fn custom<F>(generator: F) -> InstructionGenerator
+where
+    F: Fn() -> Vec<SmaliInstruction> + Send + Sync + 'static,
+{
+    Box::new(generator)
+}
+
+/// Signed hex, as smali writes literals
+fn hex(value: i64) -> String {
+    if value < 0 {
+        format!("-{:#x}", value.unsigned_abs())
+    } else {
+        format!("{:#x}", value)
+    }
+}
+
+/// Escape a string for a smali string literal
+pub(super) fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\'' => out.push_str("\\'"),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            ' '..='~' => out.push(c),
+            _ => {
+                let mut units = [0u16; 2];
+                for unit in c.encode_utf16(&mut units) {
+                    out.push_str(&format!("\\u{:04x}", unit));
+                }
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn text(instruction: SmaliInstruction) -> String {
+        match instruction {
+            Instruction(text) => text,
+            other => panic!("expected an instruction, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn picks_the_smallest_const() {
+        assert_eq!(text(load_int(0, 7)), "const/4 v0, 0x7");
+        assert_eq!(text(load_int(0, -8)), "const/4 v0, -0x8");
+        assert_eq!(text(load_int(1, 100)), "const/16 v1, 0x64");
+        assert_eq!(text(load_int(0, 0x7f0000)), "const/high16 v0, 0x7f0000");
+        assert_eq!(text(load_int(0, 100_000)), "const v0, 0x186a0");
+        assert_eq!(text(load_int(0, i32::MIN)), "const/high16 v0, -0x80000000");
+
+        assert_eq!(text(load_long(0, -1)), "const-wide/16 v0, -0x1L");
+        assert_eq!(text(load_long(0, 1 << 20)), "const-wide/32 v0, 0x100000L");
+        assert_eq!(
+            text(load_long(0, 1.5f64.to_bits() as i64)),
+            "const-wide/high16 v0, 0x3ff8000000000000L"
+        );
+        assert_eq!(
+            text(load_long(2, i64::MAX)),
+            "const-wide v2, 0x7fffffffffffffffL"
+        );
+
+        assert_eq!(
+            text(load_string(0, "say \"hi\"\n☆")),
+            "const-string v0, \"say \\\"hi\\\"\\n\\u2606\""
+        );
+    }
+
+    #[test]
+    #[ignore = "needs smali on PATH"]
+    fn helpers_assemble() {
+        let smali = which::which("smali").expect("smali isn't on the PATH");
+
+        let methods = [
+            ("Z", ReturnValue::Boolean(true)),
+            ("I", ReturnValue::Int(100_000)),
+            ("J", ReturnValue::Long(i64::MIN)),
+            ("F", ReturnValue::Float(1.5)),
+            ("D", ReturnValue::Double(-0.1)),
+            (
+                "Ljava/lang/String;",
+                ReturnValue::String("é\"\\".to_string()),
+            ),
+            ("Ljava/lang/Object;", ReturnValue::Null),
+            ("V", ReturnValue::Void),
+        ];
+
+        let mut class = String::from(".class public LHelpers;\n.super Ljava/lang/Object;\n\n");
+        for (idx, (returns, value)) in methods.iter().enumerate() {
+            class.push_str(&format!(
+                ".method public static m{}(){}\n    .locals 2\n",
+                idx, returns
+            ));
+            for instruction in value.instructions(0) {
+                class.push_str(&format!("    {}\n", text(instruction)));
+            }
+            class.push_str(".end method\n\n");
+        }
+
+        // The original body still runs, but its result is thrown away
+        let mut body = vec![
+            Instruction("invoke-static {}, Ljava/lang/System;->nanoTime()J".to_string()),
+            Instruction("move-result-wide v0".to_string()),
+            Instruction("long-to-int v0, v0".to_string()),
+            Instruction("return v0".to_string()),
+        ];
+        assert_eq!(
+            override_return(ReturnValue::Int(100))
+                .apply(&mut body)
+                .unwrap(),
+            1
+        );
+        class.push_str(".method public static overridden()I\n    .locals 2\n");
+        for instruction in body {
+            class.push_str(&format!("    {}\n", text(instruction)));
+        }
+        class.push_str(".end method\n");
+
+        let dir =
+            std::env::temp_dir().join(format!("balapatch_helpers_assemble_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Helpers.smali"), class).unwrap();
+
+        let output = Command::new(smali)
+            .arg("assemble")
+            .arg("-o")
+            .arg(dir.join("classes.dex"))
+            .arg(&dir)
+            .output()
+            .unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
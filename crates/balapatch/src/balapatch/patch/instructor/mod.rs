@@ -0,0 +1,1170 @@
+//! lmao this is mostly gonna go unused
+//! still fun to write
+
+pub mod class_edit;
+pub mod dex;
+pub mod edit;
+pub mod error;
+pub mod instructions;
+pub mod patch_file;
+pub mod query;
+pub mod registers;
+pub mod report;
+
+use crate::balapatch::apk::apktool::ApktoolConfig;
+use crate::balapatch::tui::progress::create_spinner;
+use anyhow::{Context, Result};
+use lua_validator::{BatchReport, BatchValidator};
+use rayon::prelude::*;
+use report::{ClassMatch, ClassPatchReport, MethodChange, diff_instructions};
+use smali::find_smali_files;
+use smali::types::*;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+pub use class_edit::{Access, ClassEdit};
+pub use edit::{InstructionMatcher, InstructionPattern, MethodEdit};
+pub use error::SmaliPatchError;
+pub use report::{PatchReport, PatchWarning};
+
+/// A trait for filtering smali classes. Filters are run on many classes
+/// at once, so they must be thread safe.
+pub trait ClassFilter: Send + Sync {
+    fn matches(&self, class: &SmaliClass) -> bool;
+}
+
+/// Function type for generating instructions
+pub type InstructionGenerator = Box<dyn Fn() -> Vec<SmaliInstruction> + Send + Sync>;
+
+/// Function type for selecting methods
+pub type MethodFilter = Box<dyn Fn(&SmaliMethod) -> bool + Send + Sync>;
+
+/// A struct representing a patch to be applied to a smali method
+pub struct MethodPatch {
+    /// Filter to select methods to patch
+    pub method_filter: MethodFilter,
+    /// Edits to make to the method's instructions, in order
+    pub edits: Vec<MethodEdit>,
+    /// New locals count (if None, keeps original)
+    pub locals: Option<u32>,
+    /// How many methods per class the filter should match, warned about
+    /// when it matches more
+    pub expected_matches: Option<usize>,
+}
+
+impl MethodPatch {
+    /// A patch for every method `method_filter` accepts, with no edits yet
+    pub fn new(method_filter: MethodFilter) -> Self {
+        Self {
+            method_filter,
+            edits: Vec::new(),
+            locals: None,
+            expected_matches: None,
+        }
+    }
+
+    /// Add an edit, applied after the ones already added
+    pub fn edit(mut self, edit: MethodEdit) -> Self {
+        self.edits.push(edit);
+        self
+    }
+
+    /// Force `.locals` to this. Normally it's raised automatically to fit
+    /// the registers the edits use.
+    pub fn with_locals(mut self, locals: u32) -> Self {
+        self.locals = Some(locals);
+        self
+    }
+
+    /// Warn if the filter matches more than `count` methods in a class
+    pub fn expect(mut self, count: usize) -> Self {
+        self.expected_matches = Some(count);
+        self
+    }
+
+    /// Make every edit to `method`, raising its `.locals` if needed.
+    /// Fails without touching the method if an edit would write to a
+    /// parameter register or uses one the method doesn't have, or if the
+    /// locals it needs push a parameter out of reach of an instruction
+    /// that can only name `v0..v15`, or if an edit itself fails.
+    ///
+    /// Returns how many places each edit was made in.
+    pub fn apply_to(&self, method: &mut SmaliMethod) -> Result<Vec<usize>> {
+        let params = registers::param_registers(method);
+        let mut locals_needed = 0;
+        let generated: Vec<Vec<SmaliInstruction>> =
+            self.edits.iter().map(MethodEdit::generated).collect();
+
+        for (edit, instructions) in self.edits.iter().zip(&generated) {
+            let usage = registers::RegisterUse::of(instructions);
+
+            // A new body is free to reuse the parameter registers, nothing
+            // else reads them afterwards
+            if !matches!(edit, MethodEdit::ReplaceBody(_))
+                && let Some(param) = usage.written_params.first()
+            {
+                anyhow::bail!(
+                    "The {} of {} writes to p{}, which would clobber a parameter. Use a v register instead",
+                    edit,
+                    method.name,
+                    param
+                );
+            }
+            if usage.params_needed > params {
+                anyhow::bail!(
+                    "The {} of {} uses p{}, but the method only has {} parameter register(s)",
+                    edit,
+                    method.name,
+                    usage.params_needed - 1,
+                    params
+                );
+            }
+
+            locals_needed = locals_needed.max(usage.locals_needed);
+        }
+
+        let locals = match self.locals {
+            Some(locals) if locals < locals_needed => anyhow::bail!(
+                "{} is given {} locals, but its edits use {}",
+                method.name,
+                locals,
+                locals_needed
+            ),
+            Some(locals) => locals,
+            None => method.locals.max(locals_needed),
+        };
+
+        // The old body only survives if nothing replaces it
+        let kept: &[SmaliInstruction] = if self
+            .edits
+            .iter()
+            .any(|edit| matches!(edit, MethodEdit::ReplaceBody(_)))
+        {
+            &[]
+        } else {
+            &method.instructions
+        };
+        if let Some((instruction, param)) = std::iter::once(kept)
+            .chain(generated.iter().map(Vec::as_slice))
+            .find_map(|instructions| registers::first_narrow_param(instructions, locals))
+        {
+            anyhow::bail!(
+                "With {} locals, p{} of {} becomes v{}, but `{}` can only use v0 to v15. Use fewer locals in the edits, or patch that instruction to a /from16 or /range form",
+                locals,
+                param,
+                method.name,
+                locals + param,
+                instruction
+            );
+        }
+
+        let mut instructions = method.instructions.clone();
+        let counts = self
+            .edits
+            .iter()
+            .map(|edit| {
+                edit.apply(&mut instructions)
+                    .with_context(|| format!("The {} of {} failed", edit, method.name))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        method.locals = locals;
+        method.instructions = instructions;
+        Ok(counts)
+    }
+}
+
+/// A struct representing a patch to be applied to smali classes
+pub struct ClassPatch {
+    /// Filter to select classes to patch
+    pub class_filter: Box<dyn ClassFilter>,
+    /// Changes to matching classes themselves, made before the method
+    /// patches so those can edit added methods too
+    pub class_edits: Vec<ClassEdit>,
+    /// Method patches to apply to matching classes
+    pub method_patches: Vec<MethodPatch>,
+    /// How many classes the filter should match, warned about when it
+    /// matches more
+    pub expected_matches: Option<usize>,
+}
+
+impl ClassPatch {
+    /// A patch for every class `class_filter` accepts, with no changes yet
+    pub fn new(class_filter: Box<dyn ClassFilter>) -> Self {
+        Self {
+            class_filter,
+            class_edits: Vec::new(),
+            method_patches: Vec::new(),
+            expected_matches: None,
+        }
+    }
+
+    /// Add a class edit
+    pub fn class_edit(mut self, edit: ClassEdit) -> Self {
+        self.class_edits.push(edit);
+        self
+    }
+
+    /// Add a method patch
+    pub fn method(mut self, method_patch: MethodPatch) -> Self {
+        self.method_patches.push(method_patch);
+        self
+    }
+
+    /// Warn if the filter matches more than `count` classes
+    pub fn expect(mut self, count: usize) -> Self {
+        self.expected_matches = Some(count);
+        self
+    }
+}
+
+/// Classes of every dex in the unpacked APK
+struct LoadedClasses {
+    classes: Vec<SmaliClass>,
+    /// Index into `roots` of each class's smali directory
+    class_roots: Vec<usize>,
+    roots: Vec<PathBuf>,
+}
+
+/// The index of a patch, what it did to one class, and any warnings
+type PatchedClass = (usize, ClassMatch, Vec<PatchWarning>);
+
+/// The main struct for patching APK files
+pub struct Instructor {
+    /// Path to the APK file to patch
+    apk_path: PathBuf,
+    /// Collection of patches to apply
+    patches: Vec<ClassPatch>,
+    /// Smali source of whole classes to add
+    injected_classes: Vec<String>,
+    /// Output APK path
+    output_path: PathBuf,
+    /// Working directory for unpacked APK
+    work_dir: PathBuf,
+    /// How apktool is run
+    apktool: ApktoolConfig,
+    /// Whether the game's Lua is checked before repacking
+    validate_lua: bool,
+}
+
+impl Instructor {
+    /// Create a new Instructor instance for the given APK file
+    pub fn new<P: AsRef<Path>>(apk_path: P) -> Self {
+        let apk_path = apk_path.as_ref().to_path_buf();
+        let file_stem = apk_path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+
+        Instructor {
+            apk_path,
+            patches: Vec::new(),
+            injected_classes: Vec::new(),
+            output_path: PathBuf::from(format!("{}_patched.apk", file_stem)),
+            work_dir: PathBuf::from(format!("{}_unpacked", file_stem)),
+            apktool: ApktoolConfig::default(),
+            validate_lua: true,
+        }
+    }
+
+    /// Set the output path for the patched APK
+    pub fn with_output<P: AsRef<Path>>(mut self, output_path: P) -> Self {
+        self.output_path = output_path.as_ref().to_path_buf();
+        self
+    }
+
+    /// Set the working directory for unpacking the APK
+    pub fn with_work_dir<P: AsRef<Path>>(mut self, work_dir: P) -> Self {
+        self.work_dir = work_dir.as_ref().to_path_buf();
+        self
+    }
+
+    /// Set how apktool is run, e.g. to use a specific jar or `--use-aapt2`
+    pub fn with_apktool(mut self, apktool: ApktoolConfig) -> Self {
+        self.apktool = apktool;
+        self
+    }
+
+    /// Check the game's Lua under `assets` before repacking (on by default),
+    /// so Lua broken while the APK was unpacked, e.g. by a `Patcher` run on
+    /// the working directory, fails the build instead of crashing on the phone
+    pub fn with_lua_validation(mut self, validate: bool) -> Self {
+        self.validate_lua = validate;
+        self
+    }
+
+    /// Add a patch to be applied
+    pub fn add_patch(mut self, patch: ClassPatch) -> Self {
+        self.patches.push(patch);
+        self
+    }
+
+    /// Add a whole new class, written in smali, e.g. a loader for Java mods.
+    /// It mustn't have the same name as a class the APK already has.
+    pub fn inject_class(mut self, source: impl Into<String>) -> Self {
+        self.injected_classes.push(source.into());
+        self
+    }
+
+    /// Add every patch from a TOML patch file (see [`patch_file`])
+    pub fn add_patch_file<P: AsRef<Path>>(mut self, path: P) -> Result<Self, SmaliPatchError> {
+        self.patches.extend(patch_file::load_patch_file(path)?);
+        Ok(self)
+    }
+
+    /// Apply all patches and create a modified APK. Nothing is built if
+    /// the patches change nothing.
+    pub fn apply(self) -> Result<()> {
+        let mut loaded = self.load_classes()?;
+        let report = self.patch_classes(&mut loaded)?;
+        let injected = self.injected_class_files(&loaded)?;
+
+        for warning in &report.warnings {
+            warn!("{}", warning);
+        }
+
+        let modified = report.modified_classes();
+        if modified.is_empty() && injected.is_empty() {
+            // Building would only reproduce the original APK
+            warn!(
+                "No classes were modified by the patches, not building {}",
+                self.output_path.display()
+            );
+            return Ok(());
+        }
+        info!(
+            "Patched {} method(s) in {} class(es)",
+            report.changed_methods(),
+            modified.len()
+        );
+
+        // Untouched classes are already on disk as apktool wrote them
+        save_classes(&mut loaded.classes, &modified)?;
+
+        for (path, source) in injected.iter().zip(&self.injected_classes) {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(path, source)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            info!("Injected {}", path.display());
+        }
+
+        if self.validate_lua {
+            self.check_lua()?;
+        }
+
+        // Repack the APK
+        self.repack_apk()?;
+
+        Ok(())
+    }
+
+    /// Work out what [`Instructor::apply`] would change, without saving
+    /// any classes or building an APK. The APK is still unpacked into the
+    /// working directory to read its classes.
+    pub fn dry_run(&self) -> Result<PatchReport> {
+        let mut loaded = self.load_classes()?;
+        let mut report = self.patch_classes(&mut loaded)?;
+        let injected = self.injected_class_files(&loaded)?;
+
+        report.injected_classes = injected;
+        Ok(report)
+    }
+
+    /// Where each injected class will be written, checking that they parse
+    /// and don't replace an existing class. Each goes in the first dex with
+    /// room for its method and field references, or a new dex if none has.
+    /// Call this after patching, since patches can add references too.
+    fn injected_class_files(&self, loaded: &LoadedClasses) -> Result<Vec<PathBuf>> {
+        if self.injected_classes.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut files: Vec<String> = Vec::new();
+        let mut class_refs = Vec::new();
+        for source in &self.injected_classes {
+            let class =
+                SmaliClass::from_smali(source).context("Failed to parse an injected class")?;
+            let file = class_edit::class_file_path(source)?;
+
+            if loaded.roots.iter().any(|root| root.join(&file).exists()) || files.contains(&file) {
+                anyhow::bail!(
+                    "An injected class would replace {}. Give it a name of its own",
+                    file
+                );
+            }
+
+            class_refs.push(dex::DexRefs::of(&class));
+            files.push(file);
+        }
+
+        let mut refs = vec![dex::DexRefs::default(); loaded.roots.len()];
+        for (class, root) in loaded.classes.iter().zip(&loaded.class_roots) {
+            refs[*root].extend(dex::DexRefs::of(class));
+        }
+        let placed = dex::place_classes(&mut refs, class_refs);
+
+        let mut roots = loaded.roots.clone();
+        let mut number = dex::next_dex_number(&roots);
+        while roots.len() < refs.len() {
+            roots.push(dex::root_for(&self.work_dir, number));
+            number += 1;
+        }
+
+        Ok(placed
+            .into_iter()
+            .zip(files)
+            .map(|(root, file)| roots[root].join(file))
+            .collect())
+    }
+
+    /// Unpack the APK and parse the smali of every dex
+    fn load_classes(&self) -> Result<LoadedClasses> {
+        self.unpack_apk()?;
+
+        let roots = dex::smali_roots(&self.work_dir)?;
+        let mut classes = Vec::new();
+        let mut class_roots = Vec::new();
+
+        let spinner = create_spinner("Parsing smali...");
+        for (idx, root) in roots.iter().enumerate() {
+            let found = find_smali_files(root)
+                .with_context(|| format!("Failed to find smali files in {}", root.display()))?;
+
+            class_roots.extend(std::iter::repeat_n(idx, found.len()));
+            classes.extend(found);
+        }
+        spinner.finish_and_clear();
+
+        info!(
+            "Loaded {} smali classes from {} dex file(s)",
+            classes.len(),
+            roots.len()
+        );
+
+        Ok(LoadedClasses {
+            classes,
+            class_roots,
+            roots,
+        })
+    }
+
+    /// Apply every patch to the loaded classes in memory. Classes are
+    /// patched in parallel, but the report lists them in their original order.
+    fn patch_classes(&self, loaded: &mut LoadedClasses) -> Result<PatchReport> {
+        let dex_names: Vec<String> = loaded
+            .roots
+            .iter()
+            .map(|root| dex::dex_name(root))
+            .collect();
+
+        let spinner = create_spinner("Applying smali patches...");
+        let matches: Result<Vec<Vec<PatchedClass>>> = loaded
+            .classes
+            .par_iter_mut()
+            .zip(loaded.class_roots.par_iter())
+            .enumerate()
+            .map(|(class_idx, (class, root))| {
+                self.patch_class(class_idx, class, &dex_names[*root])
+            })
+            .collect();
+        spinner.finish_and_clear();
+        let matches = matches?;
+
+        let mut patches: Vec<ClassPatchReport> = self
+            .patches
+            .iter()
+            .map(|_| ClassPatchReport::default())
+            .collect();
+        let mut warnings: Vec<Vec<PatchWarning>> = vec![Vec::new(); self.patches.len()];
+
+        for (patch_idx, class_match, class_warnings) in matches.into_iter().flatten() {
+            patches[patch_idx].classes.push(class_match);
+            warnings[patch_idx].extend(class_warnings);
+        }
+
+        for (patch_idx, patch) in self.patches.iter().enumerate() {
+            let found = patches[patch_idx].classes.len();
+
+            if found == 0 {
+                warnings[patch_idx].push(PatchWarning::NoClasses { patch: patch_idx });
+            } else if let Some(expected) = patch.expected_matches
+                && found > expected
+            {
+                warnings[patch_idx].push(PatchWarning::TooManyClasses {
+                    patch: patch_idx,
+                    expected,
+                    found,
+                });
+            }
+        }
+
+        Ok(PatchReport {
+            patches,
+            injected_classes: Vec::new(),
+            warnings: warnings.concat(),
+        })
+    }
+
+    /// Apply every patch that matches `class`, returning the index of each
+    /// of those patches with what it did
+    fn patch_class(
+        &self,
+        class_idx: usize,
+        class: &mut SmaliClass,
+        dex_name: &str,
+    ) -> Result<Vec<PatchedClass>> {
+        let mut results = Vec::new();
+
+        for (patch_idx, patch) in self.patches.iter().enumerate() {
+            if !patch.class_filter.matches(class) {
+                continue;
+            }
+
+            let class_name = class.name.as_java_type();
+            let mut warnings = Vec::new();
+            let mut class_match = ClassMatch {
+                name: class_name.clone(),
+                index: class_idx,
+                dex: dex_name.to_string(),
+                class_edits: Vec::new(),
+                methods: Vec::new(),
+            };
+
+            for edit in &patch.class_edits {
+                let count = edit
+                    .apply(class)
+                    .with_context(|| format!("Failed to patch {}", class_name))?;
+
+                if count == 0 {
+                    warnings.push(PatchWarning::ClassEditMatchedNothing {
+                        patch: patch_idx,
+                        class: class_name.clone(),
+                        edit: edit.to_string(),
+                    });
+                }
+                class_match.class_edits.push((edit.to_string(), count));
+            }
+
+            for (method_patch_idx, method_patch) in patch.method_patches.iter().enumerate() {
+                let filter = &method_patch.method_filter;
+                let mut found = 0;
+
+                for method in class.methods.iter_mut().filter(|method| filter(method)) {
+                    found += 1;
+
+                    let before = method.instructions.clone();
+                    let locals_before = method.locals;
+                    let counts = method_patch
+                        .apply_to(method)
+                        .with_context(|| format!("Failed to patch {}", class_name))?;
+
+                    for (edit, count) in method_patch.edits.iter().zip(&counts) {
+                        if *count == 0 {
+                            warnings.push(PatchWarning::EditMatchedNothing {
+                                patch: patch_idx,
+                                class: class_name.clone(),
+                                method: method.name.clone(),
+                                edit: edit.to_string(),
+                            });
+                        }
+                    }
+
+                    class_match.methods.push(MethodChange {
+                        name: method.name.clone(),
+                        locals_before,
+                        locals_after: method.locals,
+                        edits: method_patch
+                            .edits
+                            .iter()
+                            .map(ToString::to_string)
+                            .zip(counts)
+                            .collect(),
+                        diff: diff_instructions(&before, &method.instructions),
+                    });
+                }
+
+                if found == 0 {
+                    warnings.push(PatchWarning::NoMethods {
+                        patch: patch_idx,
+                        method_patch: method_patch_idx,
+                        class: class_name.clone(),
+                    });
+                } else if let Some(expected) = method_patch.expected_matches
+                    && found > expected
+                {
+                    warnings.push(PatchWarning::TooManyMethods {
+                        patch: patch_idx,
+                        method_patch: method_patch_idx,
+                        class: class_name.clone(),
+                        expected,
+                        found,
+                    });
+                }
+            }
+
+            results.push((patch_idx, class_match, warnings));
+        }
+
+        Ok(results)
+    }
+
+    /// Unpack the APK using apktool
+    fn unpack_apk(&self) -> Result<()> {
+        info!("Unpacking APK to {}", self.work_dir.display());
+
+        let spinner = create_spinner("Unpacking APK with apktool...");
+        let result = self.apktool.decode(&self.apk_path, &self.work_dir);
+        spinner.finish_and_clear();
+
+        result.context("Failed to unpack APK with apktool")
+    }
+
+    /// Fail if any Lua file under the unpacked `assets` doesn't parse
+    fn check_lua(&self) -> Result<()> {
+        let assets = self.work_dir.join("assets");
+        if !assets.is_dir() {
+            return Ok(());
+        }
+
+        let spinner = create_spinner("Validating Lua sources...");
+        let report = Self::validate_assets(&assets);
+        spinner.finish_and_clear();
+        let report = report.context("Failed to validate the game's Lua")?;
+
+        if !report.is_ok() {
+            anyhow::bail!(
+                "Not repacking, the game's Lua is invalid ({})\n{}",
+                report,
+                report.render()
+            );
+        }
+
+        info!("{}", report);
+        Ok(())
+    }
+
+    /// Validate the loose `.lua` files under `assets`, and the ones packed
+    /// into `.love` archives there, which is where Balatro keeps its code
+    fn validate_assets(assets: &Path) -> Result<BatchReport> {
+        let validator = BatchValidator::new();
+        let mut report = validator.validate_dir(assets)?;
+
+        for entry in walkdir::WalkDir::new(assets) {
+            let entry = entry?;
+            let is_love = entry
+                .path()
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("love"));
+            if !entry.file_type().is_file() || !is_love {
+                continue;
+            }
+
+            let archive = entry.path().strip_prefix(assets).unwrap_or(entry.path());
+            for mut file in validator.validate_archive(entry.path())?.files {
+                file.path = format!("{}/{}", archive.display(), file.path);
+                report.files.push(file);
+            }
+        }
+
+        report.files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(report)
+    }
+
+    /// Repack the APK using apktool
+    fn repack_apk(&self) -> Result<()> {
+        info!("Repacking APK to {}", self.output_path.display());
+
+        let spinner = create_spinner("Repacking APK with apktool...");
+        let result = self.apktool.build(&self.work_dir, &self.output_path);
+        spinner.finish_and_clear();
+
+        result.context("Failed to repack APK with apktool")
+    }
+}
+
+/// Save the classes at `indices` (sorted) in parallel
+fn save_classes(classes: &mut [SmaliClass], indices: &[usize]) -> Result<()> {
+    classes
+        .par_iter_mut()
+        .enumerate()
+        .filter(|(idx, _)| indices.binary_search(idx).is_ok())
+        .try_for_each(|(_, class)| class.save().context("Failed to save smali class"))
+}
+
+/// Convenience struct for filtering classes by their Java name
+pub struct ClassNameFilter {
+    pattern: NamePattern,
+}
+
+enum NamePattern {
+    Contains(String),
+    Exact(String),
+    Regex(regex::Regex),
+}
+
+impl ClassNameFilter {
+    /// Create a new filter that matches classes whose name contains the given pattern
+    pub fn contains(pattern: impl Into<String>) -> Self {
+        Self {
+            pattern: NamePattern::Contains(pattern.into()),
+        }
+    }
+
+    /// Match one class, e.g. `com.playstack.balatro.MainActivity`
+    pub fn exact(name: impl Into<String>) -> Self {
+        Self {
+            pattern: NamePattern::Exact(name.into()),
+        }
+    }
+
+    /// Match classes whose Java name matches `regex`. It isn't anchored,
+    /// so it can match anywhere in the name unless it uses `^` and `$`,
+    /// e.g. `^com\.[a-z]\.[a-z]$` for short obfuscated names
+    pub fn regex(regex: regex::Regex) -> Self {
+        Self {
+            pattern: NamePattern::Regex(regex),
+        }
+    }
+}
+
+impl ClassFilter for ClassNameFilter {
+    fn matches(&self, class: &SmaliClass) -> bool {
+        let name = class.name.as_java_type();
+
+        match &self.pattern {
+            NamePattern::Contains(pattern) => name.contains(pattern.as_str()),
+            NamePattern::Exact(expected) => name == *expected,
+            NamePattern::Regex(regex) => regex.is_match(&name),
+        }
+    }
+}
+
+/// Filter for classes directly extending a class, given by its Java name
+pub struct SuperclassFilter {
+    name: String,
+}
+
+impl SuperclassFilter {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into() }
+    }
+}
+
+impl ClassFilter for SuperclassFilter {
+    fn matches(&self, class: &SmaliClass) -> bool {
+        class.super_class.as_java_type() == self.name
+    }
+}
+
+/// Filter for classes directly implementing an interface, given by its
+/// Java name
+pub struct InterfaceFilter {
+    name: String,
+}
+
+impl InterfaceFilter {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into() }
+    }
+}
+
+impl ClassFilter for InterfaceFilter {
+    fn matches(&self, class: &SmaliClass) -> bool {
+        class
+            .implements
+            .iter()
+            .any(|interface| interface.as_java_type() == self.name)
+    }
+}
+
+/// Filter for classes with an annotation on the class itself, given by
+/// the annotation's Java name
+pub struct AnnotationFilter {
+    descriptor: String,
+}
+
+impl AnnotationFilter {
+    pub fn new(name: &str) -> Self {
+        Self {
+            descriptor: format!("L{};", name.replace('.', "/")),
+        }
+    }
+}
+
+impl ClassFilter for AnnotationFilter {
+    fn matches(&self, class: &SmaliClass) -> bool {
+        class
+            .annotations
+            .iter()
+            .any(|annotation| annotation.annotation_type.to_jni() == self.descriptor)
+    }
+}
+
+/// Filter for classes loading a string constant. Obfuscation renames
+/// classes but leaves their strings alone, so this is usually the most
+/// reliable way to find one.
+pub struct StringConstantFilter {
+    /// The string as it's written in a smali literal
+    escaped: String,
+}
+
+impl StringConstantFilter {
+    pub fn new(value: &str) -> Self {
+        Self {
+            escaped: format!("\"{}\"", instructions::escape(value)),
+        }
+    }
+}
+
+impl ClassFilter for StringConstantFilter {
+    fn matches(&self, class: &SmaliClass) -> bool {
+        class.methods.iter().any(|method| {
+            method.instructions.iter().any(|instruction| {
+                let SmaliInstruction::Instruction(text) = instruction else {
+                    return false;
+                };
+                let text = text.trim();
+
+                text.starts_with("const-string")
+                    && text
+                        .split_once(", ")
+                        .is_some_and(|(_, literal)| literal == self.escaped)
+            })
+        })
+    }
+}
+
+/// Convenience struct for filtering classes by method signature patterns
+pub struct MethodSignatureFilter {
+    method_count: Option<usize>,
+    field_count: Option<usize>,
+    required_method_signatures: Vec<(TypeSignature, usize)>, // (return_type, arg_count)
+}
+
+impl MethodSignatureFilter {
+    pub fn new() -> Self {
+        Self {
+            method_count: None,
+            field_count: None,
+            required_method_signatures: Vec::new(),
+        }
+    }
+
+    pub fn with_method_count(mut self, count: usize) -> Self {
+        self.method_count = Some(count);
+        self
+    }
+
+    pub fn with_field_count(mut self, count: usize) -> Self {
+        self.field_count = Some(count);
+        self
+    }
+
+    pub fn require_method_signature(
+        mut self,
+        return_type: TypeSignature,
+        arg_count: usize,
+    ) -> Self {
+        self.required_method_signatures
+            .push((return_type, arg_count));
+        self
+    }
+}
+
+impl ClassFilter for MethodSignatureFilter {
+    fn matches(&self, class: &SmaliClass) -> bool {
+        // Check method count if specified
+        if let Some(count) = self.method_count {
+            if class.methods.len() != count {
+                return false;
+            }
+        }
+
+        // Check field count if specified
+        if let Some(count) = self.field_count {
+            if class.fields.len() != count {
+                return false;
+            }
+        }
+
+        // Check for required method signatures
+        for (return_type, arg_count) in &self.required_method_signatures {
+            let has_matching_method = class.methods.iter().any(|m| {
+                // Based on original code, it seems the return type is just "signature.result"
+                m.signature.return_type == *return_type && m.signature.args.len() == *arg_count
+            });
+
+            if !has_matching_method {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Complex filter that combines multiple filters with AND logic
+pub struct AndFilter {
+    filters: Vec<Box<dyn ClassFilter>>,
+}
+
+impl AndFilter {
+    pub fn new(filters: Vec<Box<dyn ClassFilter>>) -> Self {
+        Self { filters }
+    }
+}
+
+impl ClassFilter for AndFilter {
+    fn matches(&self, class: &SmaliClass) -> bool {
+        self.filters.iter().all(|f| f.matches(class))
+    }
+}
+
+/// Complex filter that combines multiple filters with OR logic
+pub struct OrFilter {
+    filters: Vec<Box<dyn ClassFilter>>,
+}
+
+impl OrFilter {
+    pub fn new(filters: Vec<Box<dyn ClassFilter>>) -> Self {
+        Self { filters }
+    }
+}
+
+impl ClassFilter for OrFilter {
+    fn matches(&self, class: &SmaliClass) -> bool {
+        self.filters.iter().any(|f| f.matches(class))
+    }
+}
+
+/// Filter that matches the classes another filter doesn't
+pub struct NotFilter {
+    filter: Box<dyn ClassFilter>,
+}
+
+impl NotFilter {
+    pub fn new(filter: Box<dyn ClassFilter>) -> Self {
+        Self { filter }
+    }
+}
+
+impl ClassFilter for NotFilter {
+    fn matches(&self, class: &SmaliClass) -> bool {
+        !self.filter.matches(class)
+    }
+}
+
+/// Helper functions to create method filters
+pub mod method_filters {
+    type SmaliMethodVec = Vec<MethodFilter>;
+
+    use super::*;
+
+    /// Create a filter for methods with a specific return type
+    pub fn returns_type(return_type: TypeSignature) -> MethodFilter {
+        Box::new(move |method: &SmaliMethod| method.signature.return_type == return_type)
+    }
+
+    /// Create a filter for methods with a specific name
+    pub fn named(name: String) -> MethodFilter {
+        Box::new(move |method: &SmaliMethod| method.name == name)
+    }
+
+    /// Create a filter for methods with a specific number of arguments
+    pub fn with_arg_count(count: usize) -> MethodFilter {
+        Box::new(move |method: &SmaliMethod| method.signature.args.len() == count)
+    }
+
+    /// Create a filter that combines multiple conditions with AND logic
+    pub fn all_of(filters: SmaliMethodVec) -> MethodFilter {
+        Box::new(move |method: &SmaliMethod| filters.iter().all(|f| f(method)))
+    }
+
+    /// Create a filter that combines multiple conditions with OR logic
+    pub fn any_of(filters: SmaliMethodVec) -> MethodFilter {
+        Box::new(move |method: &SmaliMethod| filters.iter().any(|f| f(method)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use instructions::ReturnValue;
+
+    const TREE_CLASSES: usize = 300;
+    const TREE_METHODS: usize = 5;
+
+    fn tree_class(class: usize) -> String {
+        let mut smali = format!(
+            ".class public Lcom/tree/C{};\n.super Ljava/lang/Object;\n\n",
+            class
+        );
+        for method in 0..TREE_METHODS {
+            smali.push_str(&format!(
+                ".method public static m{}()I\n    .locals 1\n\n    const/4 v0, 0x1\n\n    return v0\n.end method\n\n",
+                method
+            ));
+        }
+        smali
+    }
+
+    fn write_tree(dir: &Path) {
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir.join("com/tree")).unwrap();
+
+        for class in 0..TREE_CLASSES {
+            let path = dir.join(format!("com/tree/C{}.smali", class));
+            std::fs::write(path, tree_class(class)).unwrap();
+        }
+    }
+
+    fn class(name: &str) -> SmaliClass {
+        SmaliClass::from_smali(&format!(
+            ".class public Lcom/game/{};\n.super Ljava/lang/Object;\n\n.method public static score()I\n    .locals 1\n\n    const/4 v0, 0x1\n\n    return v0\n.end method\n",
+            name
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn validates_lua_inside_love_archives() {
+        let dir = std::env::temp_dir().join(format!("balapatch_assets_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("conf.lua"), "function love.conf(t) end\n").unwrap();
+
+        let mut love = zip::ZipWriter::new(std::fs::File::create(dir.join("game.love")).unwrap());
+        love.start_file("main.lua", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        std::io::Write::write_all(&mut love, b"local broken = \n").unwrap();
+        love.finish().unwrap();
+
+        let report = Instructor::validate_assets(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let failed: Vec<&str> = report.failed().map(|file| file.path.as_str()).collect();
+        assert_eq!(report.files.len(), 2);
+        assert_eq!(failed, ["game.love/main.lua"]);
+    }
+
+    #[test]
+    fn warns_about_patches_that_do_nothing() {
+        let nothing = || MethodEdit::ReplaceSequence {
+            pattern: InstructionPattern::new(vec![InstructionMatcher::Opcode("nop".to_string())]),
+            with: instructions::custom(Vec::new),
+        };
+        let instructor = Instructor::new("game.apk")
+            .add_patch(ClassPatch::new(Box::new(ClassNameFilter::contains(
+                "Missing",
+            ))))
+            .add_patch(
+                ClassPatch::new(Box::new(ClassNameFilter::contains("com.game.")))
+                    .expect(1)
+                    .method(MethodPatch::new(method_filters::named(
+                        "missing".to_string(),
+                    ))),
+            )
+            .add_patch(
+                ClassPatch::new(Box::new(ClassNameFilter::exact("com.game.Shop"))).method(
+                    MethodPatch::new(method_filters::named("score".to_string())).edit(nothing()),
+                ),
+            );
+        let mut loaded = LoadedClasses {
+            classes: vec![class("Game"), class("Shop")],
+            class_roots: vec![0, 0],
+            roots: vec![PathBuf::from("smali")],
+        };
+
+        let report = instructor.patch_classes(&mut loaded).unwrap();
+
+        assert!(report.modified_classes().is_empty());
+        assert_eq!(
+            report.warnings,
+            [
+                PatchWarning::NoClasses { patch: 0 },
+                PatchWarning::NoMethods {
+                    patch: 1,
+                    method_patch: 0,
+                    class: "com.game.Game".to_string(),
+                },
+                PatchWarning::NoMethods {
+                    patch: 1,
+                    method_patch: 0,
+                    class: "com.game.Shop".to_string(),
+                },
+                PatchWarning::TooManyClasses {
+                    patch: 1,
+                    expected: 1,
+                    found: 2,
+                },
+                PatchWarning::EditMatchedNothing {
+                    patch: 2,
+                    class: "com.game.Shop".to_string(),
+                    method: "score".to_string(),
+                    edit: nothing().to_string(),
+                },
+            ]
+        );
+    }
+
+    /// Patching in parallel and saving only the modified classes has to
+    /// give the same classes as patching serially and saving all of them,
+    /// without rewriting the ones no patch changed
+    #[test]
+    fn parallel_patching_matches_serial() {
+        let dir = |name: &str| {
+            let name = format!("balapatch_instructor_{}_{}", name, std::process::id());
+            std::env::temp_dir().join(name)
+        };
+        let (serial_dir, parallel_dir) = (dir("serial"), dir("parallel"));
+        let instructor = Instructor::new("tree.apk").add_patch(
+            ClassPatch::new(Box::new(ClassNameFilter::contains("C1"))).method(
+                MethodPatch::new(method_filters::named("m0".to_string()))
+                    .edit(instructions::override_return(ReturnValue::Int(100))),
+            ),
+        );
+        let load = |dir: &Path| LoadedClasses {
+            classes: find_smali_files(dir).unwrap(),
+            class_roots: vec![0; TREE_CLASSES],
+            roots: vec![dir.to_path_buf()],
+        };
+
+        write_tree(&serial_dir);
+        let mut loaded = load(&serial_dir);
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let serial = pool.install(|| instructor.patch_classes(&mut loaded).unwrap());
+        for class in loaded.classes.iter_mut() {
+            class.save().unwrap();
+        }
+
+        write_tree(&parallel_dir);
+        let mut loaded = load(&parallel_dir);
+        let parallel = instructor.patch_classes(&mut loaded).unwrap();
+        let modified = parallel.modified_classes();
+        save_classes(&mut loaded.classes, &modified).unwrap();
+
+        // C1, C10 to C19 and C100 to C199
+        assert_eq!(modified.len(), 111);
+        assert_eq!(serial.modified_classes(), modified);
+
+        for class in 0..TREE_CLASSES {
+            let file = format!("com/tree/C{}.smali", class);
+            let read = |dir: &Path| std::fs::read_to_string(dir.join(&file)).unwrap();
+
+            if format!("C{}", class).contains("C1") {
+                assert_eq!(read(&parallel_dir), read(&serial_dir), "{}", file);
+                assert_ne!(read(&parallel_dir), tree_class(class), "{}", file);
+            } else {
+                assert_eq!(read(&parallel_dir), tree_class(class), "{}", file);
+            }
+        }
+
+        std::fs::remove_dir_all(&serial_dir).unwrap();
+        std::fs::remove_dir_all(&parallel_dir).unwrap();
+    }
+}
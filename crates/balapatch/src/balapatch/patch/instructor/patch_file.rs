@@ -0,0 +1,483 @@
+//! Smali patches written as TOML, in the same spirit as `lovely.toml`:
+//!
+//! ```toml
+//! [manifest]
+//! version = "1.0.0"
+//!
+//! [[patches]]
+//! [patches.class]
+//! name_contains = "LicenseChecker"
+//!
+//! [[patches.methods]]
+//! name = "isLicensed"
+//! returns = "Z"
+//!
+//! [[patches.methods.edits]]
+//! op = "replace_sequence"
+//! pattern = [{ contains = "->check()Z" }, { opcode = "move-result" }]
+//! payload = "const/4 v0, 0x1"
+//! ```
+//!
+//! Classes are selected with `name` (exact Java name), `name_contains`,
+//! `method_count`, `field_count` and `query` (see [`super::query`]), like
+//! `query = 'string:"License check failed" and not name_contains:google'`;
+//! methods with `name`, `returns` (a JNI type like `Z` or
+//! `Ljava/lang/String;`) and `args`, at least one of which has to be set.
+//! Every edit has an `op` (see [`MethodEdit`]) and takes its instructions,
+//! one per line, from `payload`, or `before`/`after` for `wrap`.
+//!
+//! `expect` on a patch or a method is the most classes, or methods per
+//! class, it should match; more than that is reported as a warning.
+
+use super::error::SmaliPatchError;
+use super::query::parse_query;
+use super::{
+    AndFilter, ClassFilter, ClassNameFilter, ClassPatch, InstructionGenerator, InstructionMatcher,
+    InstructionPattern, MethodEdit, MethodFilter, MethodPatch, MethodSignatureFilter,
+};
+use regex::Regex;
+use serde::Deserialize;
+use smali::types::{SmaliInstruction, SmaliMethod};
+use std::fs;
+use std::path::Path;
+use toml::Spanned;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PatchFileDef {
+    #[allow(unused)]
+    manifest: ManifestDef,
+    #[serde(default)]
+    patches: Vec<ClassPatchDef>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestDef {
+    #[allow(unused)]
+    version: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClassPatchDef {
+    class: Spanned<ClassSelector>,
+    expect: Option<usize>,
+    #[serde(default)]
+    methods: Vec<Spanned<MethodPatchDef>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClassSelector {
+    name: Option<String>,
+    name_contains: Option<String>,
+    method_count: Option<usize>,
+    field_count: Option<usize>,
+    /// See [`super::query`]. Taken out when the patch is built.
+    query: Option<Spanned<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MethodPatchDef {
+    name: Option<String>,
+    returns: Option<String>,
+    args: Option<usize>,
+    locals: Option<u32>,
+    expect: Option<usize>,
+    #[serde(default)]
+    edits: Vec<Spanned<EditDef>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EditDef {
+    op: Spanned<String>,
+    payload: Option<String>,
+    before: Option<String>,
+    after: Option<String>,
+    pattern: Option<Vec<Spanned<MatcherDef>>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum MatcherDef {
+    Exact(String),
+    Opcode(String),
+    Contains(String),
+    Regex(String),
+    Any,
+}
+
+impl ClassSelector {
+    /// The filter for every key that's set, along with the parsed `query`
+    fn into_filter(self, query: Option<Box<dyn ClassFilter>>) -> Box<dyn ClassFilter> {
+        let mut filters: Vec<Box<dyn ClassFilter>> = Vec::new();
+
+        if let Some(name) = self.name {
+            filters.push(Box::new(ClassNameFilter::exact(name)));
+        }
+        if let Some(needle) = self.name_contains {
+            filters.push(Box::new(ClassNameFilter::contains(needle)));
+        }
+        if self.method_count.is_some() || self.field_count.is_some() {
+            let mut counts = MethodSignatureFilter::new();
+            if let Some(count) = self.method_count {
+                counts = counts.with_method_count(count);
+            }
+            if let Some(count) = self.field_count {
+                counts = counts.with_field_count(count);
+            }
+            filters.push(Box::new(counts));
+        }
+        filters.extend(query);
+
+        if filters.len() == 1 {
+            filters.remove(0)
+        } else {
+            Box::new(AndFilter::new(filters))
+        }
+    }
+}
+
+/// Read a smali patch file
+pub fn load_patch_file<P: AsRef<Path>>(path: P) -> Result<Vec<ClassPatch>, SmaliPatchError> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path).map_err(|e| SmaliPatchError::io(path, e))?;
+
+    parse_patch_file(path, &text)
+}
+
+/// Parse an already read smali patch file. `path` is only used for errors.
+pub fn parse_patch_file<P: AsRef<Path>>(
+    path: P,
+    text: &str,
+) -> Result<Vec<ClassPatch>, SmaliPatchError> {
+    let path = path.as_ref();
+    let file: PatchFileDef =
+        toml::from_str(text).map_err(|e| SmaliPatchError::parse(path, text, e))?;
+    let invalid = |key: String, span: std::ops::Range<usize>, message: &str| {
+        SmaliPatchError::invalid(path, text, key, span, message.to_string())
+    };
+
+    let mut patches = Vec::new();
+    for (patch_idx, patch) in file.patches.into_iter().enumerate() {
+        let key = format!("patches[{}]", patch_idx);
+        let class_span = patch.class.span();
+        let mut selector = patch.class.into_inner();
+
+        let query = match selector.query.take() {
+            Some(query) => Some(parse_query(query.get_ref()).map_err(|e| {
+                let span = query_error_span(text, query.span(), e.offset);
+                invalid(format!("{}.class.query", key), span, &e.to_string())
+            })?),
+            None => None,
+        };
+
+        if selector.name.is_none() && selector.name_contains.is_none() && query.is_none() {
+            return Err(invalid(
+                format!("{}.class", key),
+                class_span,
+                "a class selector needs `name`, `name_contains` or `query`",
+            ));
+        }
+
+        let class_filter = selector.into_filter(query);
+
+        let mut method_patches = Vec::new();
+        for (method_idx, method) in patch.methods.into_iter().enumerate() {
+            let key = format!("{}.methods[{}]", key, method_idx);
+            let method_span = method.span();
+            let method = method.into_inner();
+
+            // Without any of these every method matches, and e.g. a
+            // `replace_body` would rewrite the whole class
+            if method.name.is_none() && method.returns.is_none() && method.args.is_none() {
+                return Err(invalid(
+                    key,
+                    method_span,
+                    "a method selector needs `name`, `returns` or `args`",
+                ));
+            }
+
+            let mut method_patch = MethodPatch::new(method_filter(&method));
+            method_patch.locals = method.locals;
+            method_patch.expected_matches = method.expect;
+
+            for (edit_idx, edit) in method.edits.into_iter().enumerate() {
+                let key = format!("{}.edits[{}]", key, edit_idx);
+                let edit = build_edit(&key, edit, &invalid)?;
+                method_patch = method_patch.edit(edit);
+            }
+
+            method_patches.push(method_patch);
+        }
+
+        patches.push(ClassPatch {
+            class_filter,
+            class_edits: Vec::new(),
+            method_patches,
+            expected_matches: patch.expect,
+        });
+    }
+
+    Ok(patches)
+}
+
+fn method_filter(def: &MethodPatchDef) -> MethodFilter {
+    let name = def.name.clone();
+    let returns = def.returns.clone();
+    let args = def.args;
+
+    Box::new(move |method: &SmaliMethod| {
+        name.as_ref().is_none_or(|name| method.name == *name)
+            && returns
+                .as_ref()
+                .is_none_or(|returns| method.signature.return_type.to_jni() == *returns)
+            && args.is_none_or(|args| method.signature.args.len() == args)
+    })
+}
+
+fn build_edit<F>(key: &str, edit: Spanned<EditDef>, invalid: &F) -> Result<MethodEdit, SmaliPatchError>
+where
+    F: Fn(String, std::ops::Range<usize>, &str) -> SmaliPatchError,
+{
+    let span = edit.span();
+    let edit = edit.into_inner();
+    let payload = |name: &str, value: Option<String>| {
+        value
+            .map(|text| payload_generator(&text))
+            .ok_or_else(|| invalid(key.to_string(), span.clone(), &format!("this edit needs `{}`", name)))
+    };
+    let pattern = |value: Option<Vec<Spanned<MatcherDef>>>| {
+        build_pattern(&format!("{}.pattern", key), span.clone(), value, invalid)
+    };
+
+    let edit = match edit.op.get_ref().as_str() {
+        "replace_body" => MethodEdit::ReplaceBody(payload("payload", edit.payload)?),
+        "inject_at_entry" => MethodEdit::InjectAtEntry(payload("payload", edit.payload)?),
+        "inject_before_returns" => {
+            MethodEdit::InjectBeforeReturns(payload("payload", edit.payload)?)
+        }
+        "wrap" if edit.before.is_none() && edit.after.is_none() => {
+            return Err(invalid(
+                key.to_string(),
+                span,
+                "this edit needs `before`, `after` or both",
+            ));
+        }
+        "wrap" => MethodEdit::Wrap {
+            pattern: pattern(edit.pattern)?,
+            before: payload_generator(edit.before.as_deref().unwrap_or_default()),
+            after: payload_generator(edit.after.as_deref().unwrap_or_default()),
+        },
+        "replace_sequence" => MethodEdit::ReplaceSequence {
+            pattern: pattern(edit.pattern)?,
+            with: payload("payload", edit.payload)?,
+        },
+        _ => {
+            return Err(invalid(
+                format!("{}.op", key),
+                edit.op.span(),
+                "unknown op, expected one of `replace_body`, `inject_at_entry`, \
+                 `inject_before_returns`, `wrap` or `replace_sequence`",
+            ));
+        }
+    };
+
+    Ok(edit)
+}
+
+fn build_pattern<F>(
+    key: &str,
+    edit_span: std::ops::Range<usize>,
+    matchers: Option<Vec<Spanned<MatcherDef>>>,
+    invalid: &F,
+) -> Result<InstructionPattern, SmaliPatchError>
+where
+    F: Fn(String, std::ops::Range<usize>, &str) -> SmaliPatchError,
+{
+    let matchers = matchers
+        .filter(|matchers| !matchers.is_empty())
+        .ok_or_else(|| invalid(key.to_string(), edit_span, "this edit needs a non-empty `pattern`"))?;
+
+    let matchers = matchers
+        .into_iter()
+        .enumerate()
+        .map(|(idx, matcher)| {
+            let span = matcher.span();
+            Ok(match matcher.into_inner() {
+                MatcherDef::Exact(text) => InstructionMatcher::Exact(text),
+                MatcherDef::Opcode(opcode) => InstructionMatcher::Opcode(opcode),
+                MatcherDef::Contains(text) => InstructionMatcher::Contains(text),
+                MatcherDef::Any => InstructionMatcher::Any,
+                MatcherDef::Regex(regex) => InstructionMatcher::Regex(
+                    Regex::new(&regex)
+                        .map_err(|e| invalid(format!("{}[{}]", key, idx), span, &e.to_string()))?,
+                ),
+            })
+        })
+        .collect::<Result<_, SmaliPatchError>>()?;
+
+    Ok(InstructionPattern::new(matchers))
+}
+
+/// Where in the file an error `offset` bytes into a query string is. That's
+/// only known when the string has no escapes, otherwise the whole string is
+/// used.
+fn query_error_span(
+    text: &str,
+    span: std::ops::Range<usize>,
+    offset: usize,
+) -> std::ops::Range<usize> {
+    let raw = &text[span.clone()];
+    let quotes = if raw.starts_with("'''") || raw.starts_with("\"\"\"") {
+        3
+    } else {
+        1
+    };
+
+    if !raw.starts_with('\'') && raw.contains('\\') {
+        return span;
+    }
+
+    // Past the end of the query is the closing quote
+    let start = (span.start + quotes + offset).min(span.end - quotes);
+    start..start + 1
+}
+
+/// One instruction per non-empty line. Lines starting with `#` are comments.
+fn payload_generator(text: &str) -> InstructionGenerator {
+    let lines: Vec<String> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect();
+
+    Box::new(move || {
+        lines
+            .iter()
+            .map(|line| SmaliInstruction::Instruction(line.clone()))
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smali::types::SmaliClass;
+
+    const PATCH: &str = r#"
+[manifest]
+version = "1.0.0"
+
+[[patches]]
+[patches.class]
+name_contains = "LicenseChecker"
+
+[[patches.methods]]
+name = "isLicensed"
+returns = "Z"
+
+[[patches.methods.edits]]
+op = "inject_at_entry"
+payload = """
+const/4 v0, 0x1
+return v0
+"""
+
+[[patches.methods.edits]]
+op = "wrap"
+pattern = [{ opcode = "invoke-static" }, { regex = "move-result(" }]
+before = "nop"
+"#;
+
+    fn invalid_key(text: &str) -> String {
+        match parse_patch_file("license.toml", text) {
+            Err(SmaliPatchError::Invalid { key, .. }) => key,
+            Err(other) => panic!("unexpected error: {}", other),
+            Ok(_) => panic!("the patch file should be rejected"),
+        }
+    }
+
+    #[test]
+    fn points_errors_at_their_key() {
+        let err = match parse_patch_file("license.toml", PATCH) {
+            Err(err) => err,
+            Ok(_) => panic!("the broken regex should be rejected"),
+        };
+
+        match err {
+            SmaliPatchError::Invalid { key, .. } => {
+                assert_eq!(key, "patches[0].methods[0].edits[1].pattern[1]")
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+
+        let fixed = PATCH.replace("move-result(", "move-result");
+        let patches = parse_patch_file("license.toml", &fixed).unwrap();
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].method_patches[0].edits.len(), 2);
+
+        let query = fixed.replace(
+            "name_contains = \"LicenseChecker\"",
+            "query = 'string:\"LVL\" and methods:'",
+        );
+        assert_eq!(invalid_key(&query), "patches[0].class.query");
+
+        // The label points at the missing value, not the whole query
+        let labels = match parse_patch_file("license.toml", &query) {
+            Err(SmaliPatchError::Invalid { snippet, .. }) => snippet.labels,
+            _ => panic!("the query should be rejected"),
+        };
+        let closing = query.find("methods:'").unwrap() + "methods:".len();
+        assert_eq!(labels[0].offset(), closing);
+        assert_eq!(labels[0].len(), 1);
+    }
+
+    #[test]
+    fn rejects_edits_that_would_do_too_much_or_nothing() {
+        let fixed = PATCH.replace("move-result(", "move-result");
+
+        let every_method = fixed.replace("name = \"isLicensed\"\nreturns = \"Z\"\n", "");
+        assert_eq!(invalid_key(&every_method), "patches[0].methods[0]");
+
+        let empty_wrap = fixed.replace("before = \"nop\"\n", "");
+        assert_eq!(invalid_key(&empty_wrap), "patches[0].methods[0].edits[1]");
+    }
+
+    #[test]
+    fn selects_classes_by_every_key() {
+        let class = |name: &str, methods: usize| {
+            let mut smali = format!(".class public L{};\n.super Ljava/lang/Object;\n\n", name);
+            for idx in 0..methods {
+                smali.push_str(&format!(
+                    ".method public m{}()V\n    .locals 0\n\n    return-void\n.end method\n\n",
+                    idx
+                ));
+            }
+            SmaliClass::from_smali(&smali).unwrap()
+        };
+        let selector = |keys: &str| {
+            let patch = PATCH
+                .replace("move-result(", "move-result")
+                .replace("name_contains = \"LicenseChecker\"", keys);
+            parse_patch_file("license.toml", &patch)
+                .unwrap()
+                .remove(0)
+                .class_filter
+        };
+
+        let contains = selector("name_contains = \"LicenseChecker\"\nmethod_count = 2");
+        assert!(contains.matches(&class("com/lvl/LicenseChecker", 2)));
+        assert!(!contains.matches(&class("com/lvl/LicenseChecker", 1)));
+        assert!(!contains.matches(&class("com/lvl/Policy", 2)));
+
+        let exact = selector("name = \"com.lvl.LicenseChecker\"\nquery = 'methods:1'");
+        assert!(exact.matches(&class("com/lvl/LicenseChecker", 1)));
+        assert!(!exact.matches(&class("com/lvl/LicenseChecker", 2)));
+        assert!(!exact.matches(&class("com/lvl/LicenseCheckerImpl", 1)));
+    }
+}
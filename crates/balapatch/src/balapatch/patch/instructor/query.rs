@@ -0,0 +1,353 @@
+//! A small query language for picking classes, so patch files can use
+//! every [`ClassFilter`]:
+//!
+//! ```text
+//! string:"License check failed" and not name_contains:google
+//! (extends:android.app.Activity or implements:java.lang.Runnable) and methods:12
+//! ```
+//!
+//! Each condition is `key:value`, combined with `and`, `or` and `not`
+//! (tightest first) and parentheses. Values with spaces, parentheses or
+//! colons need double quotes, with `\"` and `\\` as escapes.
+//!
+//! | key             | matches classes                                   |
+//! |-----------------|---------------------------------------------------|
+//! | `name`          | named exactly this                                |
+//! | `name_contains` | whose name contains this                          |
+//! | `name_matches`  | whose name matches this regex                     |
+//! | `extends`       | directly extending this class                     |
+//! | `implements`    | directly implementing this interface              |
+//! | `annotated`     | with this annotation on the class                 |
+//! | `string`        | loading this string constant in any method        |
+//! | `methods`       | with this many methods                            |
+//! | `fields`        | with this many fields                             |
+
+use super::{
+    AndFilter, AnnotationFilter, ClassFilter, ClassNameFilter, InterfaceFilter,
+    MethodSignatureFilter, NotFilter, OrFilter, StringConstantFilter, SuperclassFilter,
+};
+use regex::Regex;
+use std::fmt::{Display, Formatter};
+
+const KEYS: &str = "`name`, `name_contains`, `name_matches`, `extends`, `implements`, \
+                    `annotated`, `string`, `methods` or `fields`";
+
+/// Why a query couldn't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryError {
+    /// Byte offset into the query
+    pub offset: usize,
+    pub message: String,
+}
+
+impl Display for QueryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (at offset {})", self.message, self.offset)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Colon,
+    Word(String),
+    Quoted(String),
+}
+
+/// Parse a query into a filter
+pub fn parse_query(query: &str) -> Result<Box<dyn ClassFilter>, QueryError> {
+    let mut parser = Parser {
+        tokens: tokenize(query)?,
+        pos: 0,
+        end: query.len(),
+    };
+
+    let filter = parser.or()?;
+    match parser.tokens.get(parser.pos) {
+        None => Ok(filter),
+        Some((offset, token)) => Err(QueryError {
+            offset: *offset,
+            message: format!("Expected `and`, `or` or the end of the query, found {}", describe(token)),
+        }),
+    }
+}
+
+fn tokenize(query: &str) -> Result<Vec<(usize, Token)>, QueryError> {
+    let mut tokens = Vec::new();
+    let mut chars = query.char_indices().peekable();
+
+    while let Some((offset, c)) = chars.next() {
+        match c {
+            _ if c.is_whitespace() => {}
+            '(' => tokens.push((offset, Token::Open)),
+            ')' => tokens.push((offset, Token::Close)),
+            ':' => tokens.push((offset, Token::Colon)),
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, escaped @ ('"' | '\\'))) => value.push(escaped),
+                            Some((at, other)) => {
+                                return Err(QueryError {
+                                    offset: at,
+                                    message: format!("Unknown escape `\\{}`", other),
+                                });
+                            }
+                            None => {}
+                        },
+                        Some((_, other)) => value.push(other),
+                        None => {
+                            return Err(QueryError {
+                                offset,
+                                message: "Unclosed quote".to_string(),
+                            });
+                        }
+                    }
+                }
+                tokens.push((offset, Token::Quoted(value)));
+            }
+            _ => {
+                let mut word = c.to_string();
+                while let Some((_, next)) =
+                    chars.next_if(|(_, next)| !next.is_whitespace() && !"():\"".contains(*next))
+                {
+                    word.push(next);
+                }
+                tokens.push((offset, Token::Word(word)));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Open => "`(`".to_string(),
+        Token::Close => "`)`".to_string(),
+        Token::Colon => "`:`".to_string(),
+        Token::Word(word) => format!("`{}`", word),
+        Token::Quoted(value) => format!("\"{}\"", value),
+    }
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    /// Offset of the end of the query, for errors there
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map_or(self.end, |(offset, _)| *offset)
+    }
+
+    fn error(&self, message: impl Into<String>) -> QueryError {
+        QueryError {
+            offset: self.offset(),
+            message: message.into(),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Word(word)) if word == keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn or(&mut self) -> Result<Box<dyn ClassFilter>, QueryError> {
+        let mut filters = vec![self.and()?];
+        while self.keyword("or") {
+            filters.push(self.and()?);
+        }
+
+        Ok(match filters.len() {
+            1 => filters.remove(0),
+            _ => Box::new(OrFilter::new(filters)),
+        })
+    }
+
+    fn and(&mut self) -> Result<Box<dyn ClassFilter>, QueryError> {
+        let mut filters = vec![self.unary()?];
+        while self.keyword("and") {
+            filters.push(self.unary()?);
+        }
+
+        Ok(match filters.len() {
+            1 => filters.remove(0),
+            _ => Box::new(AndFilter::new(filters)),
+        })
+    }
+
+    fn unary(&mut self) -> Result<Box<dyn ClassFilter>, QueryError> {
+        if self.keyword("not") {
+            return Ok(Box::new(NotFilter::new(self.unary()?)));
+        }
+
+        if self.peek() == Some(&Token::Open) {
+            self.pos += 1;
+            let filter = self.or()?;
+            if self.peek() != Some(&Token::Close) {
+                return Err(self.error("Expected `)`"));
+            }
+            self.pos += 1;
+            return Ok(filter);
+        }
+
+        self.condition()
+    }
+
+    fn condition(&mut self) -> Result<Box<dyn ClassFilter>, QueryError> {
+        let key_offset = self.offset();
+        let key = match self.peek() {
+            Some(Token::Word(word)) => word.clone(),
+            Some(other) => {
+                return Err(self.error(format!("Expected a condition, found {}", describe(other))));
+            }
+            None => return Err(self.error("Expected a condition")),
+        };
+        self.pos += 1;
+
+        if self.peek() != Some(&Token::Colon) {
+            return Err(self.error(format!("Expected `:` after `{}`", key)));
+        }
+        self.pos += 1;
+
+        let value_offset = self.offset();
+        let value = match self.peek() {
+            Some(Token::Word(value) | Token::Quoted(value)) => value.clone(),
+            _ => return Err(self.error(format!("Expected a value for `{}`", key))),
+        };
+        self.pos += 1;
+
+        let value_error = |message: String| QueryError {
+            offset: value_offset,
+            message,
+        };
+        let count = || {
+            value
+                .parse::<usize>()
+                .map_err(|_| value_error(format!("`{}` needs a number", key)))
+        };
+
+        let filter: Box<dyn ClassFilter> = match key.as_str() {
+            "name" => Box::new(ClassNameFilter::exact(value.as_str())),
+            "name_contains" => Box::new(ClassNameFilter::contains(value.as_str())),
+            "name_matches" => Box::new(ClassNameFilter::regex(
+                Regex::new(&value).map_err(|e| value_error(e.to_string()))?,
+            )),
+            "extends" => Box::new(SuperclassFilter::new(value.as_str())),
+            "implements" => Box::new(InterfaceFilter::new(value.as_str())),
+            "annotated" => Box::new(AnnotationFilter::new(&value)),
+            "string" => Box::new(StringConstantFilter::new(&value)),
+            "methods" => Box::new(MethodSignatureFilter::new().with_method_count(count()?)),
+            "fields" => Box::new(MethodSignatureFilter::new().with_field_count(count()?)),
+            _ => {
+                return Err(QueryError {
+                    offset: key_offset,
+                    message: format!("Unknown key `{}`, expected one of {}", key, KEYS),
+                });
+            }
+        };
+
+        Ok(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smali::types::SmaliClass;
+
+    const CHECKER: &str = r#".class public Lcom/game/LicenseChecker;
+.super Landroid/app/Activity;
+.implements Ljava/lang/Runnable;
+
+.annotation runtime Lcom/game/Keep;
+.end annotation
+
+.method public run()V
+    .locals 1
+
+    const-string v0, "License \"check\" failed"
+
+    return-void
+.end method
+"#;
+
+    const HELPER: &str = r#".class public Lcom/google/Helper;
+.super Ljava/lang/Object;
+
+.method public run()V
+    .locals 1
+
+    const-string v0, "License \"check\" failed"
+
+    return-void
+.end method
+"#;
+
+    fn error(query: &str) -> QueryError {
+        match parse_query(query) {
+            Err(err) => err,
+            Ok(_) => panic!("`{}` should be rejected", query),
+        }
+    }
+
+    #[test]
+    fn parses_queries() {
+        assert!(parse_query(r#"string:"License \"check\" failed" and not name_contains:google"#).is_ok());
+        assert!(
+            parse_query("(extends:android.app.Activity or implements:java.lang.Runnable) and methods:12")
+                .is_ok()
+        );
+
+        assert_eq!(error("name:a and").offset, 10);
+        assert_eq!(error("name:a or (methods:1").message, "Expected `)`");
+        assert_eq!(error("methods:many").offset, 8);
+        assert_eq!(error("name_matches:\"(\"").offset, 13);
+        assert!(error("colour:red").message.starts_with("Unknown key `colour`"));
+        assert_eq!(error("name:\"abc").message, "Unclosed quote");
+    }
+
+    #[test]
+    fn matches_classes() {
+        let checker = SmaliClass::from_smali(CHECKER).unwrap();
+        let helper = SmaliClass::from_smali(HELPER).unwrap();
+        let matches = |query: &str| {
+            let filter = parse_query(query).unwrap();
+            [filter.matches(&checker), filter.matches(&helper)]
+        };
+
+        assert_eq!(
+            matches(r#"string:"License \"check\" failed""#),
+            [true, true]
+        );
+        assert_eq!(
+            matches(r#"string:"License \"check\" failed" and not name_contains:google"#),
+            [true, false]
+        );
+        assert_eq!(matches("string:License"), [false, false]);
+        assert_eq!(matches("extends:android.app.Activity"), [true, false]);
+        assert_eq!(matches("not extends:android.app.Activity"), [false, true]);
+        assert_eq!(matches("annotated:com.game.Keep"), [true, false]);
+        assert_eq!(
+            matches("implements:java.lang.Runnable or (methods:1 and name:com.google.Helper)"),
+            [true, true]
+        );
+    }
+}
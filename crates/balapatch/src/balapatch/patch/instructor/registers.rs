@@ -0,0 +1,256 @@
+//! Working out which registers generated smali needs.
+//!
+//! A method has `.locals` registers `v0..vN` followed by its parameters,
+//! which are also reachable as `p0..pM` (`p0` is `this` for non-static
+//! methods, and `long`/`double` parameters take two registers). Injected
+//! code gets enough locals by bumping `.locals`, but must never write to a
+//! `p` register, since that would clobber an argument the rest of the
+//! method still relies on.
+//!
+//! Bumping `.locals` also moves the parameters up, and many opcodes only
+//! have room for `v0..v15`, so a method using `p` registers in those can
+//! only grow so far.
+
+use smali::types::{Modifier, SmaliInstruction, SmaliMethod};
+
+/// Opcodes whose first register is read rather than written
+const READS_FIRST: &[&str] = &[
+    "invoke-",
+    "if-",
+    "return",
+    "throw",
+    "monitor-",
+    "iput",
+    "sput",
+    "aput",
+    "fill-array-data",
+    "packed-switch",
+    "sparse-switch",
+    "filled-new-array",
+    "goto",
+    "nop",
+];
+
+/// Opcodes whose registers are 4 bit fields, so they can only name `v0..v15`
+fn has_narrow_registers(opcode: &str) -> bool {
+    const NARROW: &[&str] = &[
+        "move",
+        "move-wide",
+        "move-object",
+        "const/4",
+        "array-length",
+        "instance-of",
+        "new-array",
+    ];
+
+    NARROW.contains(&opcode)
+        || opcode.ends_with("/2addr")
+        || opcode.ends_with("/lit16")
+        || opcode.starts_with("neg-")
+        || opcode.starts_with("not-")
+        || opcode.contains("-to-")
+        || opcode.starts_with("iget")
+        || opcode.starts_with("iput")
+        // `if-eqz` and friends take an 8 bit register, the two register
+        // forms don't
+        || (opcode.starts_with("if-") && !opcode.ends_with('z'))
+        || ((opcode.starts_with("invoke-") || opcode == "filled-new-array")
+            && !opcode.ends_with("/range"))
+}
+
+/// The first instruction that names a `p` register in a 4 bit field, where
+/// that register would be past `v15` once the method has `locals` locals.
+/// Returns the instruction and the parameter register.
+pub fn first_narrow_param(instructions: &[SmaliInstruction], locals: u32) -> Option<(String, u32)> {
+    instructions.iter().find_map(|instruction| {
+        let SmaliInstruction::Instruction(text) = instruction else {
+            return None;
+        };
+        let stripped = strip_strings(text.trim());
+        let (opcode, operands) = stripped.split_once(char::is_whitespace)?;
+        if !has_narrow_registers(opcode) {
+            return None;
+        }
+
+        registers(operands)
+            .into_iter()
+            .find(|(_, kind, first, _)| *kind == 'p' && locals + first > 15)
+            .map(|(_, _, first, _)| (text.trim().to_string(), first))
+    })
+}
+
+/// Registers used by a list of instructions
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegisterUse {
+    /// `.locals` needed for every `v` register used to exist
+    pub locals_needed: u32,
+    /// Parameter registers needed for every `p` register used to exist
+    pub params_needed: u32,
+    /// `p` registers written to, in order of appearance
+    pub written_params: Vec<u32>,
+}
+
+impl RegisterUse {
+    pub fn of(instructions: &[SmaliInstruction]) -> Self {
+        let mut usage = RegisterUse::default();
+
+        for instruction in instructions {
+            let SmaliInstruction::Instruction(text) = instruction else {
+                continue;
+            };
+            let text = strip_strings(text.trim());
+            let Some((opcode, operands)) = text.split_once(char::is_whitespace) else {
+                continue;
+            };
+
+            // Over-counting is harmless, so every register of an instruction
+            // working with 64 bit values is treated as a pair
+            let width = if ["wide", "long", "double"]
+                .iter()
+                .any(|kind| opcode.contains(kind))
+            {
+                2
+            } else {
+                1
+            };
+            let writes_first = !READS_FIRST.iter().any(|prefix| opcode.starts_with(prefix));
+
+            for (idx, kind, first, last) in registers(operands) {
+                let needed = last + width;
+
+                if kind == 'v' {
+                    usage.locals_needed = usage.locals_needed.max(needed);
+                } else {
+                    usage.params_needed = usage.params_needed.max(needed);
+                    if idx == 0 && writes_first {
+                        usage.written_params.push(first);
+                    }
+                }
+            }
+        }
+
+        usage
+    }
+}
+
+/// Number of registers `method`'s parameters take, including `this`
+pub fn param_registers(method: &SmaliMethod) -> u32 {
+    let this = if method.modifiers.contains(&Modifier::Static) {
+        0
+    } else {
+        1
+    };
+
+    this + method
+        .signature
+        .args
+        .iter()
+        .map(|arg| match arg.to_jni().as_str() {
+            "J" | "D" => 2,
+            _ => 1,
+        })
+        .sum::<u32>()
+}
+
+/// Registers among an instruction's operands, as (operand index, `v` or
+/// `p`, first, last). Ranges like `{v0 .. v5}` are a single entry.
+fn registers(operands: &str) -> Vec<(usize, char, u32, u32)> {
+    let parse = |token: &str| {
+        let token = token.trim();
+        let kind = token.chars().next().filter(|c| matches!(c, 'v' | 'p'))?;
+        token[1..].parse::<u32>().ok().map(|number| (kind, number))
+    };
+
+    operands
+        .split([',', '{', '}'])
+        .filter(|operand| !operand.trim().is_empty())
+        .enumerate()
+        .filter_map(|(idx, operand)| match operand.split_once("..") {
+            Some((first, last)) => parse(first)
+                .zip(parse(last))
+                .map(|((kind, first), (_, last))| (idx, kind, first, last)),
+            None => parse(operand).map(|(kind, number)| (idx, kind, number, number)),
+        })
+        .collect()
+}
+
+/// Blank out string literals so registers can't be matched inside them
+fn strip_strings(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for c in text.chars() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            out.push(' ');
+        } else {
+            in_string = c == '"';
+            out.push(c);
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(lines: &[&str]) -> RegisterUse {
+        let instructions: Vec<SmaliInstruction> = lines
+            .iter()
+            .map(|line| SmaliInstruction::Instruction(line.to_string()))
+            .collect();
+        RegisterUse::of(&instructions)
+    }
+
+    #[test]
+    fn counts_registers() {
+        assert_eq!(
+            usage(&[
+                "const-string v1, \"p9 v20\"",
+                "invoke-static {v0 .. v2}, Lcom/Foo;->bar(III)V",
+                "const-wide v3, 0x1",
+                "iput-object v0, p0, Lcom/Foo;->x:I",
+            ]),
+            RegisterUse {
+                locals_needed: 5,
+                params_needed: 1,
+                written_params: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn finds_parameter_writes() {
+        let usage = usage(&["const/4 p1, 0x0", "if-eqz p2, :cond_0", "move-object p0, v0"]);
+
+        assert_eq!(usage.written_params, [1, 0]);
+        assert_eq!(usage.params_needed, 3);
+    }
+
+    #[test]
+    fn finds_parameters_pushed_out_of_narrow_registers() {
+        let instructions: Vec<SmaliInstruction> = [
+            "if-eqz p1, :cond_0",
+            "iget v0, p0, Lcom/Foo;->x:I",
+            "move/from16 v0, p1",
+        ]
+        .iter()
+        .map(|line| SmaliInstruction::Instruction(line.to_string()))
+        .collect();
+
+        assert_eq!(first_narrow_param(&instructions, 15), None);
+        assert_eq!(
+            first_narrow_param(&instructions, 16),
+            Some(("iget v0, p0, Lcom/Foo;->x:I".to_string(), 0))
+        );
+        assert_eq!(first_narrow_param(&instructions[2..], 200), None);
+    }
+}
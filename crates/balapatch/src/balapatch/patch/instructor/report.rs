@@ -0,0 +1,324 @@
+//! What applying patches did, or would do in a dry run: which classes and
+//! methods each [`ClassPatch`](super::ClassPatch) matched, how their
+//! instructions changed, and anything that looks off.
+
+use crate::balapatch::utils::line_diff::{self, LineOp};
+use smali::types::SmaliInstruction;
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+
+/// Unchanged lines shown around each change in a diff
+const DIFF_CONTEXT: usize = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffLine {
+    Same(String),
+    Removed(String),
+    Added(String),
+}
+
+/// A patched method
+#[derive(Debug, Clone)]
+pub struct MethodChange {
+    pub name: String,
+    pub locals_before: u32,
+    pub locals_after: u32,
+    /// Each edit's description and how many places it was made in
+    pub edits: Vec<(String, usize)>,
+    pub diff: Vec<DiffLine>,
+}
+
+impl MethodChange {
+    pub fn is_changed(&self) -> bool {
+        self.locals_before != self.locals_after
+            || self
+                .diff
+                .iter()
+                .any(|line| !matches!(line, DiffLine::Same(_)))
+    }
+}
+
+/// A class matched by a patch
+#[derive(Debug, Clone)]
+pub struct ClassMatch {
+    /// Java name of the class
+    pub name: String,
+    /// Index of the class among the loaded classes
+    pub index: usize,
+    /// The dex file the class is in, e.g. `classes2.dex`
+    pub dex: String,
+    /// Each class edit's description and how many members it changed
+    pub class_edits: Vec<(String, usize)>,
+    pub methods: Vec<MethodChange>,
+}
+
+impl ClassMatch {
+    pub fn is_changed(&self) -> bool {
+        self.class_edits.iter().any(|(_, count)| *count > 0)
+            || self.methods.iter().any(MethodChange::is_changed)
+    }
+}
+
+/// Everything one [`ClassPatch`](super::ClassPatch) matched
+#[derive(Debug, Clone, Default)]
+pub struct ClassPatchReport {
+    pub classes: Vec<ClassMatch>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchWarning {
+    NoClasses {
+        patch: usize,
+    },
+    TooManyClasses {
+        patch: usize,
+        expected: usize,
+        found: usize,
+    },
+    NoMethods {
+        patch: usize,
+        method_patch: usize,
+        class: String,
+    },
+    TooManyMethods {
+        patch: usize,
+        method_patch: usize,
+        class: String,
+        expected: usize,
+        found: usize,
+    },
+    EditMatchedNothing {
+        patch: usize,
+        class: String,
+        method: String,
+        edit: String,
+    },
+    ClassEditMatchedNothing {
+        patch: usize,
+        class: String,
+        edit: String,
+    },
+}
+
+impl Display for PatchWarning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PatchWarning::NoClasses { patch } => {
+                write!(f, "Patch #{} matched no classes", patch)
+            }
+            PatchWarning::TooManyClasses {
+                patch,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Patch #{} matched {} classes, expected {}",
+                patch, found, expected
+            ),
+            PatchWarning::NoMethods {
+                patch,
+                method_patch,
+                class,
+            } => write!(
+                f,
+                "Method patch #{} of patch #{} matched no methods in {}",
+                method_patch, patch, class
+            ),
+            PatchWarning::TooManyMethods {
+                patch,
+                method_patch,
+                class,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Method patch #{} of patch #{} matched {} methods in {}, expected {}",
+                method_patch, patch, found, class, expected
+            ),
+            PatchWarning::EditMatchedNothing {
+                patch,
+                class,
+                method,
+                edit,
+            } => write!(
+                f,
+                "The {} of patch #{} matched nothing in {}.{}",
+                edit, patch, class, method
+            ),
+            PatchWarning::ClassEditMatchedNothing { patch, class, edit } => write!(
+                f,
+                "The {} of patch #{} matched nothing in {}",
+                edit, patch, class
+            ),
+        }
+    }
+}
+
+/// The result of patching, one entry per patch in the order they were added
+#[derive(Debug, Clone, Default)]
+pub struct PatchReport {
+    pub patches: Vec<ClassPatchReport>,
+    /// Files written for injected classes
+    pub injected_classes: Vec<PathBuf>,
+    pub warnings: Vec<PatchWarning>,
+}
+
+impl PatchReport {
+    /// Indices of the classes that were changed, sorted
+    pub fn modified_classes(&self) -> Vec<usize> {
+        let mut indices: Vec<usize> = self
+            .patches
+            .iter()
+            .flat_map(|patch| &patch.classes)
+            .filter(|class| class.is_changed())
+            .map(|class| class.index)
+            .collect();
+
+        indices.sort();
+        indices.dedup();
+        indices
+    }
+
+    pub fn changed_methods(&self) -> usize {
+        self.patches
+            .iter()
+            .flat_map(|patch| &patch.classes)
+            .flat_map(|class| &class.methods)
+            .filter(|method| method.is_changed())
+            .count()
+    }
+}
+
+impl Display for PatchReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (idx, patch) in self.patches.iter().enumerate() {
+            writeln!(f, "Patch #{}: {} class(es)", idx, patch.classes.len())?;
+
+            for class in &patch.classes {
+                writeln!(f, "  {} ({})", class.name, class.dex)?;
+
+                for (edit, count) in &class.class_edits {
+                    writeln!(f, "    {}: {} member(s)", edit, count)?;
+                }
+
+                for method in &class.methods {
+                    write!(f, "    {}", method.name)?;
+                    if method.locals_before != method.locals_after {
+                        write!(
+                            f,
+                            " (.locals {} -> {})",
+                            method.locals_before, method.locals_after
+                        )?;
+                    }
+                    writeln!(f)?;
+
+                    for (edit, count) in &method.edits {
+                        writeln!(f, "      {}: {} place(s)", edit, count)?;
+                    }
+                    for line in visible_diff(&method.diff) {
+                        match line {
+                            Some(DiffLine::Same(text)) => writeln!(f, "        {}", text)?,
+                            Some(DiffLine::Removed(text)) => writeln!(f, "      - {}", text)?,
+                            Some(DiffLine::Added(text)) => writeln!(f, "      + {}", text)?,
+                            None => writeln!(f, "        ...")?,
+                        }
+                    }
+                }
+            }
+        }
+
+        for path in &self.injected_classes {
+            writeln!(f, "Injected {}", path.display())?;
+        }
+
+        for warning in &self.warnings {
+            writeln!(f, "Warning: {}", warning)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Changed lines with [`DIFF_CONTEXT`] lines around them. `None` marks
+/// skipped unchanged lines.
+fn visible_diff(diff: &[DiffLine]) -> Vec<Option<&DiffLine>> {
+    let changed: Vec<usize> = diff
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, DiffLine::Same(_)))
+        .map(|(idx, _)| idx)
+        .collect();
+    let near_change = |idx: usize| {
+        changed
+            .iter()
+            .any(|changed| changed.abs_diff(idx) <= DIFF_CONTEXT)
+    };
+
+    let mut visible = Vec::new();
+    let mut skipped = false;
+    for (idx, line) in diff.iter().enumerate() {
+        if near_change(idx) {
+            if skipped {
+                visible.push(None);
+                skipped = false;
+            }
+            visible.push(Some(line));
+        } else {
+            skipped = true;
+        }
+    }
+
+    visible
+}
+
+/// How an instruction reads in a diff
+fn render(instruction: &SmaliInstruction) -> String {
+    match instruction {
+        SmaliInstruction::Instruction(text) => text.trim().to_string(),
+        other => format!("{:?}", other),
+    }
+}
+
+/// Line diff between two instruction lists
+pub fn diff_instructions(before: &[SmaliInstruction], after: &[SmaliInstruction]) -> Vec<DiffLine> {
+    let before: Vec<String> = before.iter().map(render).collect();
+    let after: Vec<String> = after.iter().map(render).collect();
+
+    line_diff::diff_lines(&before, &after)
+        .into_iter()
+        .map(|op| match op {
+            LineOp::Equal { old, .. } => DiffLine::Same(before[old].clone()),
+            LineOp::Delete { old } => DiffLine::Removed(before[old].clone()),
+            LineOp::Insert { new } => DiffLine::Added(after[new].clone()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use SmaliInstruction::Instruction;
+
+    fn instructions(lines: &[&str]) -> Vec<SmaliInstruction> {
+        lines.iter().map(|line| Instruction(line.to_string())).collect()
+    }
+
+    #[test]
+    fn diffs_instructions() {
+        let before = instructions(&["a", "b", "c", "d", "e"]);
+        let after = instructions(&["a", "x", "c", "d", "y", "e"]);
+
+        assert_eq!(
+            diff_instructions(&before, &after),
+            [
+                DiffLine::Same("a".to_string()),
+                DiffLine::Removed("b".to_string()),
+                DiffLine::Added("x".to_string()),
+                DiffLine::Same("c".to_string()),
+                DiffLine::Same("d".to_string()),
+                DiffLine::Added("y".to_string()),
+                DiffLine::Same("e".to_string()),
+            ]
+        );
+    }
+}
@@ -1,5 +1,2 @@
 pub mod instructor;
-<<<<<<< HEAD
 pub mod the_lovers; // lol balatro reference
-=======
->>>>>>> 2c49caf265594eab24344216ff042097dc9d287e
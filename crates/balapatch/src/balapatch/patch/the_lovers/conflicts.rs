@@ -0,0 +1,411 @@
+//! Detects mods whose pattern/regex patches land on the same lines
+//! of the same target. Lovely just applies whatever it loads first,
+//! so when two mods fight over a region the result depends on load
+//! order and usually breaks without saying anything.
+//!
+//! Matching is done against the *unpatched* target, which is what
+//! mod authors write their patterns against anyway. Mods are expected in
+//! the order [`ordering::resolve_order`] puts them in, which is the order
+//! [`super::Patcher`] applies them in.
+
+use super::{LoadedPatchFile, PatchError, ordering};
+use lovely_core::patch::{pattern::PatternPatch, regex::RegexPatch, Patch};
+use regex::RegexBuilder;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+use wildmatch::WildMatch;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchKind {
+    Pattern,
+    Regex,
+}
+
+impl Display for PatchKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PatchKind::Pattern => write!(f, "pattern"),
+            PatchKind::Regex => write!(f, "regex"),
+        }
+    }
+}
+
+/// One patch involved in a conflict
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConflictingPatch {
+    pub mod_name: String,
+    /// Index of the patch inside its `lovely.toml`
+    pub patch_index: usize,
+    pub kind: PatchKind,
+}
+
+/// A region of the target that patches from more than one mod touch
+#[derive(Debug, Clone)]
+pub struct PatchConflict {
+    pub target: String,
+    /// 1-based, inclusive line range in the original target
+    pub first_line: usize,
+    pub last_line: usize,
+    pub patches: Vec<ConflictingPatch>,
+    /// Names of the mods involved, in the order they're applied in
+    pub order: Vec<String>,
+    /// Whether manifest priorities or dependencies decide `order`. If not,
+    /// it only comes from the order the mods were loaded in.
+    pub resolved: bool,
+}
+
+impl PatchConflict {
+    /// Names of the mods involved, without duplicates
+    pub fn mods(&self) -> Vec<&str> {
+        let mut mods: Vec<&str> = Vec::new();
+
+        for patch in &self.patches {
+            if !mods.contains(&patch.mod_name.as_str()) {
+                mods.push(&patch.mod_name);
+            }
+        }
+
+        mods
+    }
+
+    pub fn is_resolved(&self) -> bool {
+        self.resolved
+    }
+}
+
+impl Display for PatchConflict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.first_line == self.last_line {
+            write!(f, "'{}' line {}", self.target, self.first_line)?;
+        } else {
+            write!(
+                f,
+                "'{}' lines {}-{}",
+                self.target, self.first_line, self.last_line
+            )?;
+        }
+
+        if self.resolved {
+            writeln!(f, " (resolved: {})", self.order.join(" -> "))?;
+        } else {
+            writeln!(
+                f,
+                " (unresolved, load order applies {})",
+                self.order.join(" -> ")
+            )?;
+        }
+
+        for patch in &self.patches {
+            writeln!(
+                f,
+                "  - {} (patch #{}, {})",
+                patch.mod_name, patch.patch_index, patch.kind
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConflictReport {
+    pub target: String,
+    pub conflicts: Vec<PatchConflict>,
+}
+
+impl ConflictReport {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+
+    /// Conflicts neither priorities nor dependencies settle
+    pub fn unresolved(&self) -> impl Iterator<Item = &PatchConflict> {
+        self.conflicts.iter().filter(|c| !c.is_resolved())
+    }
+}
+
+impl Display for ConflictReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_clean() {
+            return writeln!(f, "No conflicts in '{}'", self.target);
+        }
+
+        writeln!(
+            f,
+            "{} conflict(s) in '{}', {} unresolved:",
+            self.conflicts.len(),
+            self.target,
+            self.unresolved().count()
+        )?;
+
+        for conflict in &self.conflicts {
+            write!(f, "{}", conflict)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Where a single patch lands in the original target
+#[derive(Debug)]
+struct Footprint {
+    patch: ConflictingPatch,
+    /// 0-based, half-open line ranges
+    lines: Vec<Range<usize>>,
+}
+
+/// Finds overlapping patches across a set of mods
+#[derive(Debug, Default)]
+pub struct ConflictAnalyzer;
+
+impl ConflictAnalyzer {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Check every pattern and regex patch aimed at `target` against
+    /// `source`. `mods` have to be in the order they're applied in, as
+    /// returned by [`ordering::resolve_order`].
+    pub fn analyze(
+        &self,
+        target: &str,
+        source: &str,
+        mods: &[LoadedPatchFile],
+    ) -> Result<ConflictReport, PatchError> {
+        let line_starts = line_starts(source);
+        let mut footprints = Vec::new();
+
+        for loaded in mods {
+            for (patch_index, patch) in loaded.file.patches.iter().enumerate() {
+                let (kind, lines) = match patch {
+                    Patch::Pattern(p) if p.target == target => {
+                        (PatchKind::Pattern, pattern_footprint(p, source))
+                    }
+                    Patch::Regex(p) if p.target == target => (
+                        PatchKind::Regex,
+                        regex_footprint(p, source, &line_starts).map_err(|e| {
+                            PatchError::InvalidPatch {
+                                at: loaded.patch_location(patch_index, target, Vec::new()),
+                                message: format!("invalid regex: {}", e),
+                            }
+                        })?,
+                    ),
+                    _ => continue,
+                };
+
+                footprints.push(Footprint {
+                    patch: ConflictingPatch {
+                        mod_name: loaded.name.clone(),
+                        patch_index,
+                        kind,
+                    },
+                    lines,
+                });
+            }
+        }
+
+        Ok(ConflictReport {
+            target: target.to_string(),
+            conflicts: self.find_conflicts(target, &footprints, mods),
+        })
+    }
+
+    fn find_conflicts(
+        &self,
+        target: &str,
+        footprints: &[Footprint],
+        mods: &[LoadedPatchFile],
+    ) -> Vec<PatchConflict> {
+        let mut regions: Vec<(Range<usize>, usize)> = footprints
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, fp)| fp.lines.iter().map(move |r| (r.clone(), idx)))
+            .collect();
+        regions.sort_by_key(|(r, _)| (r.start, r.end));
+
+        // Sweep the sorted regions, merging anything that overlaps into one cluster
+        let mut clusters: Vec<(Range<usize>, Vec<usize>)> = Vec::new();
+
+        for (range, idx) in regions {
+            match clusters.last_mut() {
+                Some((current, members)) if range.start < current.end => {
+                    current.end = current.end.max(range.end);
+                    if !members.contains(&idx) {
+                        members.push(idx);
+                    }
+                }
+                _ => clusters.push((range, vec![idx])),
+            }
+        }
+
+        clusters
+            .into_iter()
+            .filter_map(|(range, members)| {
+                let patches: Vec<ConflictingPatch> = members
+                    .iter()
+                    .map(|&idx| footprints[idx].patch.clone())
+                    .collect();
+
+                let first_mod = &patches[0].mod_name;
+                if patches.iter().all(|p| &p.mod_name == first_mod) {
+                    // A mod overlapping with itself is intentional
+                    return None;
+                }
+
+                // Indices into `mods`, so in the order they're applied in
+                let involved: Vec<usize> = mods
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, loaded)| patches.iter().any(|p| p.mod_name == loaded.name))
+                    .map(|(idx, _)| idx)
+                    .collect();
+                let resolved = involved.iter().enumerate().all(|(pos, &first)| {
+                    involved[pos + 1..]
+                        .iter()
+                        .all(|&second| ordering::is_ordered(mods, first, second))
+                });
+
+                Some(PatchConflict {
+                    target: target.to_string(),
+                    first_line: range.start + 1,
+                    last_line: range.end,
+                    patches,
+                    order: involved.iter().map(|&idx| mods[idx].name.clone()).collect(),
+                    resolved,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Byte offset each line of `source` starts at
+fn line_starts(source: &str) -> Vec<usize> {
+    std::iter::once(0)
+        .chain(source.match_indices('\n').map(|(idx, _)| idx + 1))
+        .collect()
+}
+
+fn line_of(line_starts: &[usize], offset: usize) -> usize {
+    match line_starts.binary_search(&offset) {
+        Ok(line) => line,
+        Err(line) => line - 1,
+    }
+}
+
+/// Lovely matches patterns against each trimmed line
+fn pattern_footprint(patch: &PatternPatch, source: &str) -> Vec<Range<usize>> {
+    let matcher = WildMatch::new(&patch.pattern);
+
+    source
+        .split('\n')
+        .enumerate()
+        .filter(|(_, line)| matcher.matches(line.trim()))
+        .map(|(idx, _)| idx..idx + 1)
+        .take(patch.times.unwrap_or(usize::MAX))
+        .collect()
+}
+
+/// Regex patches match across the whole file, so a match can span several lines.
+/// If a `root_capture` is set only that capture counts as touched.
+fn regex_footprint(
+    patch: &RegexPatch,
+    source: &str,
+    line_starts: &[usize],
+) -> Result<Vec<Range<usize>>, regex::Error> {
+    let re = RegexBuilder::new(&patch.pattern)
+        .multi_line(true)
+        .build()?;
+
+    Ok(re
+        .captures_iter(source)
+        .filter_map(|caps| match &patch.root_capture {
+            Some(root) => match root.parse::<usize>() {
+                Ok(idx) => caps.get(idx),
+                Err(_) => caps.name(root),
+            },
+            None => caps.get(0),
+        })
+        .take(patch.times.unwrap_or(usize::MAX))
+        .map(|m| {
+            let first = line_of(line_starts, m.start());
+            let last = line_of(line_starts, m.end().saturating_sub(1).max(m.start()));
+            first..last + 1
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "function Game:start_run(args)\n    self.round = 0\n    self.ante = 1\nend\n";
+
+    fn patch_file(name: &str, body: &str) -> LoadedPatchFile {
+        patch_file_with_priority(name, 0, body)
+    }
+
+    fn patch_file_with_priority(name: &str, priority: isize, body: &str) -> LoadedPatchFile {
+        let content = format!(
+            "[manifest]\nversion = \"1.0.0\"\ndump_lua = true\npriority = {}\n\n{}",
+            priority, body
+        );
+
+        LoadedPatchFile::parse(name, format!("Mods/{}/lovely.toml", name), &content)
+            .expect("test patch file should parse")
+    }
+
+    fn pattern(pattern: &str) -> String {
+        format!(
+            "[[patches]]\n[patches.pattern]\ntarget = \"game.lua\"\npattern = \"{}\"\nposition = \"after\"\npayload = \"print('hi')\"\nmatch_indent = true\n",
+            pattern
+        )
+    }
+
+    #[test]
+    fn overlapping_mods_conflict() {
+        let mods = vec![
+            patch_file("ModA", &pattern("self.round = 0")),
+            patch_file("ModB", &pattern("self.round = *")),
+            patch_file("ModC", &pattern("self.ante = 1")),
+        ];
+
+        let report = ConflictAnalyzer::new()
+            .analyze("game.lua", SOURCE, &mods)
+            .unwrap();
+
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!(report.conflicts[0].first_line, 2);
+        assert_eq!(report.conflicts[0].mods(), vec!["ModA", "ModB"]);
+        assert_eq!(report.conflicts[0].order, vec!["ModA", "ModB"]);
+        assert!(!report.conflicts[0].is_resolved());
+    }
+
+    #[test]
+    fn priority_resolves_conflicts() {
+        let mods = ordering::resolve_order(vec![
+            patch_file_with_priority("ModA", 5, &pattern("self.round = 0")),
+            patch_file_with_priority("ModB", 0, &pattern("self.round = *")),
+        ])
+        .unwrap();
+        let report = ConflictAnalyzer::new()
+            .analyze("game.lua", SOURCE, &mods)
+            .unwrap();
+
+        // The same order the patcher applies them in
+        assert_eq!(report.conflicts[0].order, vec!["ModB", "ModA"]);
+        assert_eq!(report.unresolved().count(), 0);
+        assert!(report.to_string().contains("(resolved: ModB -> ModA)"));
+    }
+
+    #[test]
+    fn same_mod_overlap_is_ignored() {
+        let body = format!("{}\n{}", pattern("self.round = 0"), pattern("self.round*"));
+        let mods = vec![patch_file("ModA", &body)];
+
+        let report = ConflictAnalyzer::new()
+            .analyze("game.lua", SOURCE, &mods)
+            .unwrap();
+
+        assert!(report.is_clean());
+    }
+}
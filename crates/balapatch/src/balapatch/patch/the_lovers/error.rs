@@ -0,0 +1,341 @@
+//! Errors from loading and applying `lovely.toml` files.
+//!
+//! Anything that can be traced back to a patch file keeps a copy of
+//! that file's text plus the relevant spans, so [`PatchError::render`]
+//! can point mod authors at the exact lines to fix.
+
+use miette::{Diagnostic, GraphicalReportHandler, LabeledSpan, NamedSource, SourceCode};
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+/// Text of a patch file along with the spans an error is about
+#[derive(Debug)]
+pub struct Snippet {
+    pub(crate) source: NamedSource<String>,
+    pub(crate) labels: Vec<LabeledSpan>,
+}
+
+impl Snippet {
+    pub fn new<P: AsRef<Path>>(path: P, text: &str, labels: Vec<LabeledSpan>) -> Self {
+        Self {
+            source: NamedSource::new(path.as_ref().display().to_string(), text.to_string())
+                .with_language("toml"),
+            labels,
+        }
+    }
+}
+
+/// A single patch inside a patch file
+#[derive(Debug)]
+pub struct PatchLocation {
+    pub path: PathBuf,
+    pub mod_name: String,
+    /// Index of the patch in the file's `[[patches]]` array
+    pub patch_index: usize,
+    pub target: String,
+    pub(super) snippet: Snippet,
+}
+
+impl Display for PatchLocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} patch #{} (mod '{}', target '{}')",
+            self.path.display(),
+            self.patch_index,
+            self.mod_name,
+            self.target
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum PatchError {
+    /// Reading or writing a file failed
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// A patch file isn't valid TOML or doesn't fit lovely's format
+    Parse {
+        path: PathBuf,
+        message: String,
+        snippet: Snippet,
+    },
+    /// A required `Patcher` option wasn't set
+    MissingOption(&'static str),
+    /// No target name was set and the source path has no file name to use
+    NoTargetName(PathBuf),
+    /// A patch that can't be applied as written, e.g. a broken regex
+    InvalidPatch {
+        at: PatchLocation,
+        message: String,
+    },
+    UndefinedVars {
+        at: PatchLocation,
+        names: Vec<String>,
+    },
+    /// The patched target isn't valid Lua anymore. `at` is the first patch
+    /// after which it stopped validating, `line` the first line it changed.
+    InvalidLua {
+        at: PatchLocation,
+        line: usize,
+        message: String,
+    },
+    /// The patched target isn't valid Lua, but replaying the patches one by
+    /// one didn't find a single patch to blame
+    InvalidOutput {
+        target: String,
+        line: usize,
+        message: String,
+    },
+    MissingDependency {
+        mod_name: String,
+        dependency: String,
+        snippet: Snippet,
+    },
+    Incompatible {
+        mod_name: String,
+        other: String,
+        snippet: Snippet,
+    },
+    /// Mod ids forming the cycle, the first one repeated at the end
+    DependencyCycle(Vec<String>),
+    /// Two patch files go by the same mod id
+    DuplicateId {
+        id: String,
+        first: PathBuf,
+        second: PathBuf,
+    },
+    /// A source map couldn't be (de)serialized
+    SourceMap {
+        path: PathBuf,
+        message: String,
+    },
+    /// More than one error, so they can all be fixed in one go
+    Multiple(Vec<PatchError>),
+}
+
+impl PatchError {
+    pub fn io<P: AsRef<Path>>(path: P, source: std::io::Error) -> Self {
+        PatchError::Io {
+            path: path.as_ref().to_path_buf(),
+            source,
+        }
+    }
+
+    pub fn parse<P: AsRef<Path>>(path: P, text: &str, err: toml::de::Error) -> Self {
+        let message = err.message().to_string();
+        let labels = err
+            .span()
+            .map(|span| vec![LabeledSpan::new_with_span(Some(message.clone()), span)])
+            .unwrap_or_default();
+
+        PatchError::Parse {
+            path: path.as_ref().to_path_buf(),
+            message,
+            snippet: Snippet::new(path, text, labels),
+        }
+    }
+
+    /// `Ok` if there are no errors, the error itself if there's one, or
+    /// [`PatchError::Multiple`]
+    pub fn collect(mut errors: Vec<PatchError>) -> Result<(), PatchError> {
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            _ => Err(PatchError::Multiple(errors)),
+        }
+    }
+
+    /// Pretty print the error with snippets of the offending patch file
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        if GraphicalReportHandler::new()
+            .render_report(&mut out, self)
+            .is_err()
+        {
+            return self.to_string();
+        }
+
+        out
+    }
+
+    fn snippet(&self) -> Option<&Snippet> {
+        match self {
+            PatchError::Parse { snippet, .. }
+            | PatchError::MissingDependency { snippet, .. }
+            | PatchError::Incompatible { snippet, .. } => Some(snippet),
+            PatchError::InvalidPatch { at, .. }
+            | PatchError::UndefinedVars { at, .. }
+            | PatchError::InvalidLua { at, .. } => Some(&at.snippet),
+            _ => None,
+        }
+    }
+}
+
+impl Display for PatchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PatchError::Io { path, source } => {
+                write!(f, "IO Error: '{}': {}", path.display(), source)
+            }
+            PatchError::Parse { path, message, .. } => {
+                write!(f, "TOML Parse Error: '{}': {}", path.display(), message)
+            }
+            PatchError::MissingOption(option) => {
+                write!(f, "Patcher Error: no {} was specified", option)
+            }
+            PatchError::NoTargetName(path) => write!(
+                f,
+                "Patcher Error: can't work out a target name from '{}'",
+                path.display()
+            ),
+            PatchError::InvalidPatch { at, message } => {
+                write!(f, "Patch Error: {}: {}", at, message)
+            }
+            PatchError::UndefinedVars { at, names } => write!(
+                f,
+                "Variable Error: {} uses undefined variable(s): {}",
+                at,
+                names.join(", ")
+            ),
+            PatchError::InvalidLua { at, line, message } => write!(
+                f,
+                "Lua Error: {} breaks '{}' at line {}: {}",
+                at, at.target, line, message
+            ),
+            PatchError::InvalidOutput {
+                target,
+                line,
+                message,
+            } => write!(
+                f,
+                "Lua Error: patched '{}' is invalid at line {}: {}",
+                target, line, message
+            ),
+            PatchError::MissingDependency {
+                mod_name,
+                dependency,
+                ..
+            } => write!(
+                f,
+                "Dependency Error: '{}' depends on '{}', which isn't being applied",
+                mod_name, dependency
+            ),
+            PatchError::Incompatible {
+                mod_name, other, ..
+            } => write!(
+                f,
+                "Dependency Error: '{}' is incompatible with '{}'",
+                mod_name, other
+            ),
+            PatchError::DependencyCycle(cycle) => {
+                write!(f, "Dependency Error: cycle between {}", cycle.join(" -> "))
+            }
+            PatchError::DuplicateId { id, first, second } => write!(
+                f,
+                "Dependency Error: '{}' and '{}' both have the id '{}'",
+                first.display(),
+                second.display(),
+                id
+            ),
+            PatchError::SourceMap { path, message } => {
+                write!(f, "Source Map Error: '{}': {}", path.display(), message)
+            }
+            PatchError::Multiple(errors) => {
+                write!(f, "{} errors:", errors.len())?;
+                for error in errors {
+                    write!(f, "\n- {}", error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for PatchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PatchError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl Diagnostic for PatchError {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        let code = match self {
+            PatchError::Io { .. } => "balapatch::patch::io",
+            PatchError::Parse { .. } => "balapatch::patch::parse",
+            PatchError::MissingOption(_) => "balapatch::patch::missing_option",
+            PatchError::NoTargetName(_) => "balapatch::patch::target_name",
+            PatchError::InvalidPatch { .. } => "balapatch::patch::invalid",
+            PatchError::UndefinedVars { .. } => "balapatch::patch::undefined_vars",
+            PatchError::InvalidLua { .. } | PatchError::InvalidOutput { .. } => {
+                "balapatch::patch::invalid_lua"
+            }
+            PatchError::MissingDependency { .. }
+            | PatchError::Incompatible { .. }
+            | PatchError::DependencyCycle(_)
+            | PatchError::DuplicateId { .. } => "balapatch::patch::dependencies",
+            PatchError::SourceMap { .. } => "balapatch::patch::source_map",
+            PatchError::Multiple(_) => "balapatch::patch::multiple",
+        };
+
+        Some(Box::new(code))
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        let help = match self {
+            PatchError::NoTargetName(_) => {
+                "set the name patches target (e.g. `main.lua`) with `.target_name()`"
+            }
+            PatchError::Parse { .. } => {
+                "check the file against lovely's patch format (`[manifest]` plus `[[patches]]` tables)"
+            }
+            PatchError::UndefinedVars { .. } => {
+                "define the variables under `[vars]` in the patch file, or pass them to the Patcher with `.var()`"
+            }
+            PatchError::InvalidLua { .. } => {
+                "check the payload of this patch, and that its pattern matches where you expect"
+            }
+            PatchError::InvalidOutput { .. } => {
+                "no single patch could be blamed, check the patches touching the lines around it"
+            }
+            PatchError::MissingDependency { .. } => {
+                "add the missing mod's patch file, or remove it from `dependencies`"
+            }
+            PatchError::Incompatible { .. } => "only one of these mods can be applied at a time",
+            PatchError::DependencyCycle(_) => {
+                "remove one of the `dependencies` entries to break the cycle"
+            }
+            PatchError::DuplicateId { .. } => {
+                "give one of the mods a different `id` in its `[manifest]`"
+            }
+            _ => return None,
+        };
+
+        Some(Box::new(help))
+    }
+
+    fn related<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a dyn Diagnostic> + 'a>> {
+        match self {
+            PatchError::Multiple(errors) => Some(Box::new(
+                errors.iter().map(|error| error as &dyn Diagnostic),
+            )),
+            _ => None,
+        }
+    }
+
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        self.snippet().map(|s| &s.source as &dyn SourceCode)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        self.snippet()
+            .filter(|s| !s.labels.is_empty())
+            .map(|s| Box::new(s.labels.iter().cloned()) as Box<dyn Iterator<Item = LabeledSpan>>)
+    }
+}
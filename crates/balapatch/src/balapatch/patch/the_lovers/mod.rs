@@ -0,0 +1,935 @@
+//! Programmatic wrapper for lovely :3
+//! Lets you patch individual files with specific
+//! `lovely.toml` files instead of needing a
+//! `Mods` directory.
+//! ----------
+//! This will help quite a lot with
+//! patching the android code since
+//! you can't use a `Mods` dir on
+//! android without quite a lot
+//! of tweaks
+
+pub mod conflicts;
+pub mod error;
+pub mod ordering;
+pub mod source_map;
+mod validate;
+pub mod vars;
+
+use crate::balapatch::utils::line_diff;
+use crop::Rope;
+use lua_validator::{FormatOptions, LVal};
+use miette::LabeledSpan;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use lovely_core::patch::{
+    copy::{CopyPatch, CopyPosition},
+    module::ModulePatch,
+    pattern::PatternPatch,
+    regex::RegexPatch,
+    Patch, PatchFile,
+};
+
+pub use error::{PatchError, PatchLocation};
+use conflicts::{ConflictAnalyzer, ConflictReport};
+use error::Snippet;
+use ordering::ModMetadata;
+use source_map::{LineOrigin, SourceMap};
+use vars::VarScope;
+
+/// A parsed `lovely.toml` along with where it came from
+#[derive(Debug)]
+pub struct LoadedPatchFile {
+    /// Name of the mod the patch file belongs to
+    pub name: String,
+    /// Path to the patch file itself
+    pub path: PathBuf,
+    /// Directory relative sources (copy/module patches) are resolved against
+    pub dir: PathBuf,
+    pub file: PatchFile,
+    /// Balapatch specific manifest keys (id, dependencies, incompatibilities)
+    pub meta: ModMetadata,
+    /// Raw text of the patch file, for error snippets
+    pub source: String,
+}
+
+impl LoadedPatchFile {
+    /// Read and parse a patch file, naming it after the mod directory it lives in
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, PatchError> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(|e| PatchError::io(path, e))?;
+
+        Self::parse(mod_name_from_path(path), path, &content)
+    }
+
+    /// Parse an already read patch file
+    pub fn parse<S: AsRef<str>, P: AsRef<Path>>(
+        name: S,
+        path: P,
+        content: &str,
+    ) -> Result<Self, PatchError> {
+        let path = path.as_ref().to_path_buf();
+        let dir = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+
+        let file = toml::from_str(content).map_err(|e| PatchError::parse(&path, content, e))?;
+        let meta =
+            ModMetadata::from_toml(content).map_err(|e| PatchError::parse(&path, content, e))?;
+
+        Ok(LoadedPatchFile {
+            name: name.as_ref().to_string(),
+            path,
+            dir,
+            file,
+            meta,
+            source: content.to_string(),
+        })
+    }
+
+    /// Name dependencies refer to this mod by
+    pub fn id(&self) -> &str {
+        self.meta.id.as_deref().unwrap_or(&self.name)
+    }
+
+    pub fn priority(&self) -> isize {
+        self.file.manifest.priority
+    }
+
+    /// Byte range of the `patch_index`th `[[patches]]` table in the source
+    pub fn patch_span(&self, patch_index: usize) -> Option<std::ops::Range<usize>> {
+        // Offsets of every table header, and whether it starts a new patch.
+        // Sub-tables like `[patches.pattern]` belong to the patch above them.
+        let mut headers = Vec::new();
+        let mut offset = 0;
+
+        for line in self.source.split_inclusive('\n') {
+            let trimmed = line.trim_start();
+
+            if trimmed.starts_with("[[patches]]") {
+                headers.push((offset, true));
+            } else if trimmed.starts_with('[') && !trimmed.starts_with("[patches.") {
+                headers.push((offset, false));
+            }
+
+            offset += line.len();
+        }
+
+        let (idx, &(start, _)) = headers
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, is_patch))| *is_patch)
+            .nth(patch_index)?;
+        let end = headers
+            .get(idx + 1)
+            .map(|(offset, _)| *offset)
+            .unwrap_or(self.source.len());
+
+        Some(start..start + self.source[start..end].trim_end().len())
+    }
+
+    /// Location of a patch for error reporting, with `labels` highlighted on
+    /// top of the patch itself
+    pub fn patch_location<S: AsRef<str>>(
+        &self,
+        patch_index: usize,
+        target: S,
+        mut labels: Vec<LabeledSpan>,
+    ) -> PatchLocation {
+        if labels.is_empty() {
+            if let Some(span) = self.patch_span(patch_index) {
+                labels.push(LabeledSpan::new_with_span(
+                    Some(format!("patch #{}", patch_index)),
+                    span,
+                ));
+            }
+        }
+
+        PatchLocation {
+            path: self.path.clone(),
+            mod_name: self.name.clone(),
+            patch_index,
+            target: target.as_ref().to_string(),
+            snippet: self.snippet(labels),
+        }
+    }
+
+    /// Spans of every occurrence of `needle` in the source, labelled with `label`
+    pub fn find_spans(
+        &self,
+        needle: &str,
+        within: Option<std::ops::Range<usize>>,
+        label: &str,
+    ) -> Vec<LabeledSpan> {
+        let range = within.unwrap_or(0..self.source.len());
+
+        self.source[range.clone()]
+            .match_indices(needle)
+            .map(|(idx, _)| {
+                LabeledSpan::new_with_span(
+                    Some(label.to_string()),
+                    (range.start + idx)..(range.start + idx + needle.len()),
+                )
+            })
+            .collect()
+    }
+
+    pub(crate) fn snippet(&self, labels: Vec<LabeledSpan>) -> Snippet {
+        Snippet::new(&self.path, &self.source, labels)
+    }
+
+    fn undefined_vars_error(
+        &self,
+        patch_index: usize,
+        target: &str,
+        names: Vec<String>,
+    ) -> PatchError {
+        let within = self.patch_span(patch_index);
+        let labels = names
+            .iter()
+            .flat_map(|name| {
+                self.find_spans(
+                    &format!("{{{{lovely:{}}}}}", name),
+                    within.clone(),
+                    "not defined",
+                )
+            })
+            .collect();
+
+        PatchError::UndefinedVars {
+            at: self.patch_location(patch_index, target, labels),
+            names,
+        }
+    }
+}
+
+/// Every patch file in a `Mods` directory, in the usual lovely layouts:
+/// `Mods/<name>/lovely.toml` and `Mods/<name>/lovely/<file>.toml`
+pub fn find_patch_files<P: AsRef<Path>>(mods_dir: P) -> Result<Vec<PathBuf>, PatchError> {
+    let mods_dir = mods_dir.as_ref();
+    let read_dir = |dir: &Path| -> Result<Vec<PathBuf>, PatchError> {
+        let mut paths = fs::read_dir(dir)
+            .map_err(|e| PatchError::io(dir, e))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect::<Vec<_>>();
+        paths.sort();
+        Ok(paths)
+    };
+
+    let mut found = Vec::new();
+    for mod_dir in read_dir(mods_dir)?.into_iter().filter(|path| path.is_dir()) {
+        let single = mod_dir.join("lovely.toml");
+        if single.is_file() {
+            found.push(single);
+        }
+
+        let split = mod_dir.join("lovely");
+        if split.is_dir() {
+            found.extend(
+                read_dir(&split)?
+                    .into_iter()
+                    .filter(|path| path.extension().is_some_and(|ext| ext == "toml")),
+            );
+        }
+    }
+
+    Ok(found)
+}
+
+/// Works out a mod name from the usual lovely layouts:
+/// `Mods/<name>/lovely.toml` and `Mods/<name>/lovely/<file>.toml`
+fn mod_name_from_path(path: &Path) -> String {
+    let file_stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let parent = path.parent();
+    let parent_name = parent
+        .and_then(|p| p.file_name())
+        .map(|s| s.to_string_lossy().to_string());
+
+    match parent_name.as_deref() {
+        Some("lovely") => parent
+            .and_then(|p| p.parent())
+            .and_then(|p| p.file_name())
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or(file_stem),
+        Some(name) if file_stem == "lovely" => name.to_string(),
+        _ => file_stem,
+    }
+}
+
+pub struct Patcher {
+    source_file: Option<PathBuf>,
+    patch_files: Vec<PathBuf>,
+    output_file: Option<PathBuf>,
+    target_name: Option<String>,
+    module_handler: Option<Box<dyn ModuleHandler>>,
+    vars: HashMap<String, String>,
+    validate_lua: bool,
+    format_lua: bool,
+    write_source_map: bool,
+}
+
+pub trait ModuleHandler {
+    /// Handle a module patch aimed at `target`, whose content is `rope`,
+    /// returning whether it was used
+    fn handle_module_patch(
+        &self,
+        module_patch: &ModulePatch,
+        target: &str,
+        rope: &mut Rope,
+        patch_dir: &Path,
+    ) -> bool;
+}
+
+impl Patcher {
+    pub fn new() -> Self {
+        Patcher {
+            source_file: None,
+            patch_files: Vec::new(),
+            output_file: None,
+            target_name: None,
+            module_handler: None,
+            vars: HashMap::new(),
+            validate_lua: true,
+            format_lua: false,
+            write_source_map: true,
+        }
+    }
+
+    pub fn source<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.source_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Add a patch file. Can be called more than once to apply several mods,
+    /// which get ordered by their manifest dependencies and priority.
+    pub fn patch<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.patch_files.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn patches<I, P>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        self.patch_files
+            .extend(paths.into_iter().map(|p| p.as_ref().to_path_buf()));
+        self
+    }
+
+    pub fn output<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.output_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn target_name<S: AsRef<str>>(mut self, name: S) -> Self {
+        self.target_name = Some(name.as_ref().to_string());
+        self
+    }
+
+    pub fn module_handler<H: ModuleHandler + 'static>(mut self, handler: H) -> Self {
+        self.module_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Define a variable visible to every mod's payloads.
+    /// A mod's own `[vars]` take precedence over these.
+    pub fn var<K: AsRef<str>, V: AsRef<str>>(mut self, name: K, value: V) -> Self {
+        self.vars
+            .insert(name.as_ref().to_string(), value.as_ref().to_string());
+        self
+    }
+
+    /// Check `.lua` targets with `lua_validator` before writing them out (on by default).
+    /// A target that no longer validates after patching isn't written, and the
+    /// error names the first patch that broke it.
+    pub fn validate_lua(mut self, validate: bool) -> Self {
+        self.validate_lua = validate;
+        self
+    }
+
+    /// Re-indent the lines patches inserted into `.lua` targets (off by default).
+    /// Lines are never added or removed by this, so line numbers stay the same.
+    pub fn format_lua(mut self, format: bool) -> Self {
+        self.format_lua = format;
+        self
+    }
+
+    /// Save a [`SourceMap`] of the output as `<output>.map.json` (on by default),
+    /// for translating tracebacks from the patched file
+    pub fn source_map(mut self, write: bool) -> Self {
+        self.write_source_map = write;
+        self
+    }
+
+    pub fn patch_file(&self) -> Result<(), PatchError> {
+        let (source_path, target) = self.source_and_target()?;
+        let output_path = self
+            .output_file
+            .as_ref()
+            .ok_or(PatchError::MissingOption("output file"))?;
+
+        let source_content =
+            fs::read_to_string(source_path).map_err(|e| PatchError::io(source_path, e))?;
+        let original = Rope::from(source_content);
+        let mut rope = original.clone();
+        let mut map = SourceMap::new(&target, source_path, &original);
+        let mut mods = self.load_mods()?;
+
+        let mut undefined = Vec::new();
+        for loaded in mods.iter_mut() {
+            undefined.extend(self.interpolate_payloads(&target, loaded));
+        }
+        PatchError::collect(undefined)?;
+
+        for loaded in &mods {
+            self.apply_patches(&target, &mut rope, loaded, &mut map)?;
+        }
+
+        if self.validate_lua && target.ends_with(".lua") {
+            self.validate_output(&target, &original, &rope, &mods)?;
+        }
+
+        if self.format_lua && target.ends_with(".lua") {
+            rope = format_output(&target, &original, &rope);
+        }
+
+        fs::write(output_path, rope.to_string()).map_err(|e| PatchError::io(output_path, e))?;
+
+        if self.write_source_map {
+            map.save(SourceMap::path_for(output_path))?;
+        }
+
+        Ok(())
+    }
+
+    /// Find the lines of the source that patches from more than one mod
+    /// land on, with the mods in the order [`Patcher::patch_file`] applies
+    /// them in. Nothing is written, so no output file is needed.
+    pub fn check_conflicts(&self) -> Result<ConflictReport, PatchError> {
+        let (source_path, target) = self.source_and_target()?;
+        let source = fs::read_to_string(source_path).map_err(|e| PatchError::io(source_path, e))?;
+        let mods = self.load_mods()?;
+
+        ConflictAnalyzer::new().analyze(&target, &source, &mods)
+    }
+
+    /// The source file, and the target name its patches are looked up by
+    fn source_and_target(&self) -> Result<(&PathBuf, String), PatchError> {
+        let source_path = self
+            .source_file
+            .as_ref()
+            .ok_or(PatchError::MissingOption("source file"))?;
+
+        let target = match &self.target_name {
+            Some(name) => name.clone(),
+            None => source_path
+                .file_name()
+                .ok_or_else(|| PatchError::NoTargetName(source_path.clone()))?
+                .to_string_lossy()
+                .to_string(),
+        };
+
+        Ok((source_path, target))
+    }
+
+    /// Load every patch file, in the order they're applied in
+    fn load_mods(&self) -> Result<Vec<LoadedPatchFile>, PatchError> {
+        if self.patch_files.is_empty() {
+            return Err(PatchError::MissingOption("patch file"));
+        }
+
+        let mods = self
+            .patch_files
+            .iter()
+            .map(LoadedPatchFile::load)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        ordering::resolve_order(mods)
+    }
+
+    /// Substitute `{{lovely:...}}` variables in the inline payloads of patches
+    /// aimed at `target`, returning an error for every patch that uses an
+    /// undefined one. Copy patches are interpolated as their sources are read.
+    fn interpolate_payloads(&self, target: &str, loaded: &mut LoadedPatchFile) -> Vec<PatchError> {
+        let scope = VarScope::new(&loaded.file.vars, &self.vars);
+        let mut undefined = Vec::new();
+
+        for (patch_index, patch) in loaded.file.patches.iter_mut().enumerate() {
+            let payload = match patch {
+                Patch::Pattern(p) if p.target == target => &mut p.payload,
+                Patch::Regex(p) if p.target == target => &mut p.payload,
+                _ => continue,
+            };
+
+            match scope.interpolate(payload) {
+                Ok(value) => *payload = value,
+                Err(names) => undefined.push((patch_index, names)),
+            }
+        }
+
+        undefined
+            .into_iter()
+            .map(|(patch_index, names)| loaded.undefined_vars_error(patch_index, target, names))
+            .collect()
+    }
+
+    fn apply_patches(
+        &self,
+        target: &str,
+        rope: &mut Rope,
+        loaded: &LoadedPatchFile,
+        map: &mut SourceMap,
+    ) -> Result<(), PatchError> {
+        let mut applied_count = 0;
+
+        for (patch_index, patch) in loaded.file.patches.iter().enumerate() {
+            let before = rope.clone();
+
+            if self.apply_patch(target, rope, loaded, patch_index, patch)? {
+                applied_count += 1;
+                map.record(
+                    &before,
+                    rope,
+                    LineOrigin::Patch {
+                        mod_name: loaded.name.clone(),
+                        path: loaded.path.clone(),
+                        patch_index,
+                    },
+                );
+            }
+        }
+
+        println!(
+            "Applied {} patches from '{}' to '{}'",
+            applied_count, loaded.name, target
+        );
+        Ok(())
+    }
+
+    /// Apply a single patch, returning whether it was used
+    fn apply_patch(
+        &self,
+        target: &str,
+        rope: &mut Rope,
+        loaded: &LoadedPatchFile,
+        patch_index: usize,
+        patch: &Patch,
+    ) -> Result<bool, PatchError> {
+        let patch_dir = loaded.dir.as_path();
+
+        match patch {
+            Patch::Copy(copy_patch) => {
+                self.apply_copy_patch(target, rope, copy_patch, loaded, patch_index)
+            }
+            Patch::Pattern(pattern_patch) => {
+                self.apply_pattern_patch(target, rope, pattern_patch, patch_dir)?;
+                Ok(true)
+            }
+            Patch::Regex(regex_patch) => {
+                self.apply_regex_patch(target, rope, regex_patch, patch_dir)?;
+                Ok(true)
+            }
+            Patch::Module(module_patch) => {
+                self.apply_module_patch(target, rope, module_patch, patch_dir)
+            }
+        }
+    }
+
+    /// Lovely's copy patch reads its sources itself, so it's reimplemented
+    /// here to be able to interpolate each source before it gets inserted
+    fn apply_copy_patch(
+        &self,
+        target: &str,
+        rope: &mut Rope,
+        patch: &CopyPatch,
+        loaded: &LoadedPatchFile,
+        patch_index: usize,
+    ) -> Result<bool, PatchError> {
+        if target != patch.target {
+            return Ok(false);
+        }
+
+        let scope = VarScope::new(&loaded.file.vars, &self.vars);
+        let mut payload = String::new();
+
+        for source in &patch.sources {
+            let source_path = if source.is_absolute() {
+                source.clone()
+            } else {
+                loaded.dir.join(source)
+            };
+
+            let content = fs::read_to_string(&source_path)
+                .map_err(|e| PatchError::io(&source_path, e))?;
+            let content = scope
+                .interpolate(&content)
+                .map_err(|names| loaded.undefined_vars_error(patch_index, target, names))?;
+
+            payload.push_str(&content);
+            if !payload.ends_with('\n') {
+                payload.push('\n');
+            }
+        }
+
+        match patch.position {
+            CopyPosition::Prepend => rope.insert(0, payload),
+            CopyPosition::Append => {
+                let at = rope.byte_len();
+                if at > 0 && rope.byte(at - 1) != b'\n' {
+                    payload.insert(0, '\n');
+                }
+                rope.insert(at, payload);
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn apply_pattern_patch(
+        &self,
+        target: &str,
+        rope: &mut Rope,
+        patch: &PatternPatch,
+        patch_dir: &Path,
+    ) -> Result<(), PatchError> {
+        if target != patch.target {
+            return Ok(());
+        }
+
+        patch.apply(target, rope, patch_dir);
+        Ok(())
+    }
+
+    fn apply_regex_patch(
+        &self,
+        target: &str,
+        rope: &mut Rope,
+        patch: &RegexPatch,
+        patch_dir: &Path,
+    ) -> Result<(), PatchError> {
+        if target != patch.target {
+            return Ok(());
+        }
+
+        patch.apply(target, rope, patch_dir);
+        Ok(())
+    }
+
+    fn apply_module_patch(
+        &self,
+        target: &str,
+        rope: &mut Rope,
+        patch: &ModulePatch,
+        patch_dir: &Path,
+    ) -> Result<bool, PatchError> {
+        if target != patch.before {
+            return Ok(false);
+        }
+
+        if let Some(handler) = &self.module_handler {
+            Ok(handler.handle_module_patch(patch, target, rope, patch_dir))
+        } else {
+            let module_path = if patch.source.is_absolute() {
+                patch.source.clone()
+            } else {
+                patch_dir.join(&patch.source)
+            };
+
+            match fs::read_to_string(&module_path) {
+                Ok(content) => {
+                    println!(
+                        "Module patch found for '{}'. Module content from {} is available but no handler is registered.",
+                        target,
+                        module_path.display()
+                    );
+                    Ok(false)
+                }
+                Err(e) => {
+                    println!(
+                        "Warning: Could not read module file at {}: {}",
+                        module_path.display(),
+                        e
+                    );
+                    Ok(false)
+                }
+            }
+        }
+    }
+}
+
+/// Re-indent only the lines of `patched` that aren't in `original`
+fn format_output(target: &str, original: &Rope, patched: &Rope) -> Rope {
+    let original_lines: Vec<String> = original.lines().map(|line| line.to_string()).collect();
+    let patched_lines: Vec<String> = patched.lines().map(|line| line.to_string()).collect();
+
+    let touched = line_diff::inserted_ranges(&original_lines, &patched_lines);
+    if touched.is_empty() {
+        return patched.clone();
+    }
+
+    match LVal::new(patched.to_string())
+        .with_name(target)
+        .format(&FormatOptions::new().only_lines(touched))
+    {
+        Ok(formatted) => Rope::from(formatted),
+        Err(e) => {
+            println!("Warning: couldn't format '{}', leaving it as is: {}", target, e);
+            patched.clone()
+        }
+    }
+}
+
+impl Default for Patcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct LoggingModuleHandler;
+
+impl ModuleHandler for LoggingModuleHandler {
+    fn handle_module_patch(
+        &self,
+        module_patch: &ModulePatch,
+        target: &str,
+        _rope: &mut Rope,
+        patch_dir: &Path,
+    ) -> bool {
+        let module_path = if module_patch.source.is_absolute() {
+            module_patch.source.clone()
+        } else {
+            patch_dir.join(&module_patch.source)
+        };
+
+        match fs::read_to_string(&module_path) {
+            Ok(content) => {
+                println!(
+                    "Module '{}' from file {} would be injected before '{}' {}",
+                    module_patch.name,
+                    module_path.display(),
+                    target,
+                    if module_patch.load_now {
+                        "and loaded immediately"
+                    } else {
+                        ""
+                    }
+                );
+                true
+            }
+            Err(e) => {
+                println!(
+                    "Warning: Could not read module file at {}: {}",
+                    module_path.display(),
+                    e
+                );
+                false
+            }
+        }
+    }
+}
+
+/// Injects module patches into the start of their target as a
+/// `package.preload` entry, `require`ing it right away if `load_now` is set.
+/// A module already injected into the target (e.g. when patching a file
+/// in place again) is left alone.
+pub struct InjectingModuleHandler;
+
+impl ModuleHandler for InjectingModuleHandler {
+    fn handle_module_patch(
+        &self,
+        module_patch: &ModulePatch,
+        target: &str,
+        rope: &mut Rope,
+        patch_dir: &Path,
+    ) -> bool {
+        let module_path = if module_patch.source.is_absolute() {
+            module_patch.source.clone()
+        } else {
+            patch_dir.join(&module_patch.source)
+        };
+
+        let content = match fs::read_to_string(&module_path) {
+            Ok(content) => content,
+            Err(e) => {
+                println!(
+                    "Warning: Could not read module file at {}: {}",
+                    module_path.display(),
+                    e
+                );
+                return false;
+            }
+        };
+
+        let name = lua_string(&module_patch.name);
+        let header = format!("package.preload[{}] = function(...)", name);
+        if rope.lines().any(|line| line.to_string() == header) {
+            println!(
+                "Module '{}' is already injected into '{}', skipping it",
+                module_patch.name, target
+            );
+            return false;
+        }
+
+        let mut payload = format!("{}\n{}", header, content);
+        if !payload.ends_with('\n') {
+            payload.push('\n');
+        }
+        payload.push_str("end\n");
+        if module_patch.load_now {
+            payload.push_str(&format!("require({})\n", name));
+        }
+
+        rope.insert(0, payload);
+        println!(
+            "Injected module '{}' into '{}'",
+            module_patch.name, target
+        );
+        true
+    }
+}
+
+/// Quote `value` as a Lua 5.1 string literal. Bytes outside printable
+/// ASCII become decimal escapes, since 5.1 has no `\x` or `\u{}`.
+fn lua_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+
+    for byte in value.bytes() {
+        match byte {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            b'\n' => out.push_str("\\n"),
+            b' '..=b'~' => out.push(byte as char),
+            _ => out.push_str(&format!("\\{:03}", byte)),
+        }
+    }
+
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patch_simple_file() -> anyhow::Result<(), PatchError> {
+        Patcher::new()
+            .patch("D:\\Projects\\woah\\balapatch\\lovely.toml")
+            .source("D:\\Projects\\woah\\balapatch\\main.lua")
+            .output("D:\\Projects\\woah\\balapatch\\skibidi.lua")
+            .module_handler(LoggingModuleHandler)
+            .patch_file()
+    }
+
+    #[test]
+    fn multi_file_patch() {}
+
+    #[test]
+    fn quotes_lua_strings() {
+        assert_eq!(lua_string("dev"), "\"dev\"");
+        assert_eq!(lua_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\n\"");
+        assert_eq!(lua_string("é1"), "\"\\195\\1691\"");
+    }
+
+    #[test]
+    fn reports_every_undefined_var() {
+        let pattern = |payload: &str| {
+            format!(
+                "[[patches]]\n[patches.pattern]\ntarget = \"main.lua\"\npattern = \"x\"\n\
+                 position = \"after\"\npayload = \"{}\"\nmatch_indent = true\n",
+                payload
+            )
+        };
+        let content = format!(
+            "[manifest]\nversion = \"1.0.0\"\ndump_lua = true\npriority = 0\n\n[vars]\nante = \"1\"\n\n{}{}{}",
+            pattern("{{lovely:chips}}"),
+            pattern("{{lovely:ante}}"),
+            pattern("{{lovely:mult}} {{lovely:chips}}")
+        );
+        let load = || LoadedPatchFile::parse("Mod", "Mods/Mod/lovely.toml", &content).unwrap();
+
+        let errors = Patcher::new()
+            .var("chips", "10")
+            .interpolate_payloads("main.lua", &mut load());
+        let undefined: Vec<(usize, Vec<String>)> = errors
+            .iter()
+            .map(|error| match error {
+                PatchError::UndefinedVars { at, names } => (at.patch_index, names.clone()),
+                other => panic!("unexpected error: {}", other),
+            })
+            .collect();
+        assert_eq!(undefined, [(2, vec!["mult".to_string()])]);
+
+        // Without the global, every patch using `chips` is reported at once
+        let errors = Patcher::new().interpolate_payloads("main.lua", &mut load());
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(
+            PatchError::collect(errors),
+            Err(PatchError::Multiple(errors)) if errors.len() == 2
+        ));
+    }
+
+    #[test]
+    fn reports_conflicts_in_patch_order() {
+        let dir = std::env::temp_dir().join(format!("balapatch_conflicts_{}", std::process::id()));
+        let patch = |name: &str, priority: isize| {
+            let mod_dir = dir.join("Mods").join(name);
+            fs::create_dir_all(&mod_dir).unwrap();
+            fs::write(
+                mod_dir.join("lovely.toml"),
+                format!(
+                    "[manifest]\nversion = \"1.0.0\"\ndump_lua = true\npriority = {}\n\n\
+                     [[patches]]\n[patches.pattern]\ntarget = \"main.lua\"\npattern = \"self.round = 0\"\n\
+                     position = \"after\"\npayload = \"print('{}')\"\nmatch_indent = true\n",
+                    priority, name
+                ),
+            )
+            .unwrap();
+            mod_dir.join("lovely.toml")
+        };
+        let first = patch("First", 5);
+        let second = patch("Second", 0);
+        fs::write(dir.join("main.lua"), "self.round = 0\n").unwrap();
+        assert_eq!(find_patch_files(dir.join("Mods")).unwrap(), [first, second]);
+
+        let report = Patcher::new()
+            .source(dir.join("main.lua"))
+            .patches(find_patch_files(dir.join("Mods")).unwrap())
+            .check_conflicts()
+            .unwrap();
+
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!(report.conflicts[0].order, vec!["Second", "First"]);
+        assert!(report.conflicts[0].is_resolved());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn injects_modules_once() {
+        let dir = std::env::temp_dir().join(format!("balapatch_inject_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("mod.lua"), "return 1").unwrap();
+
+        let patch: ModulePatch = toml::from_str(
+            "source = \"mod.lua\"\nbefore = \"main.lua\"\nname = \"mod\"\nload_now = true\n",
+        )
+        .unwrap();
+        let mut rope = Rope::from("print('main')\n");
+
+        assert!(InjectingModuleHandler.handle_module_patch(&patch, "main.lua", &mut rope, &dir));
+        let once = rope.to_string();
+        assert!(!InjectingModuleHandler.handle_module_patch(&patch, "main.lua", &mut rope, &dir));
+
+        assert_eq!(rope.to_string(), once);
+        assert_eq!(
+            once,
+            "package.preload[\"mod\"] = function(...)\nreturn 1\nend\nrequire(\"mod\")\nprint('main')\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,274 @@
+//! Puts mods in the order they have to be applied in.
+//!
+//! Dependencies always come before the mods that need them. Between
+//! mods that don't depend on each other, lower `priority` values from
+//! the manifest go first (same as lovely), then load order.
+
+use super::{LoadedPatchFile, PatchError};
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+/// Extra keys balapatch reads from a patch file's `[manifest]` table.
+/// Lovely ignores unknown manifest keys, so these don't break it.
+///
+/// ```toml
+/// [manifest]
+/// version = "1.0.0"
+/// dump_lua = true
+/// priority = 0
+/// id = "my_mod"
+/// dependencies = ["Steamodded"]
+/// incompatible = ["SomeOtherMod"]
+/// ```
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct ModMetadata {
+    /// Name other mods refer to this one by, defaults to the mod name
+    pub id: Option<String>,
+    /// Mods that have to be applied before this one
+    pub dependencies: Vec<String>,
+    /// Mods that can't be applied alongside this one
+    pub incompatible: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ManifestTable {
+    #[serde(default)]
+    manifest: ModMetadata,
+}
+
+impl ModMetadata {
+    pub(super) fn from_toml(content: &str) -> Result<Self, toml::de::Error> {
+        Ok(toml::from_str::<ManifestTable>(content)?.manifest)
+    }
+}
+
+/// Sort `mods` so every mod comes after its dependencies.
+///
+/// Fails if two mods share an id, a dependency is missing, two
+/// incompatible mods are both present, or the dependencies form a cycle.
+pub fn resolve_order(mods: Vec<LoadedPatchFile>) -> Result<Vec<LoadedPatchFile>, PatchError> {
+    let mut ids: HashMap<&str, usize> = HashMap::with_capacity(mods.len());
+    for (idx, loaded) in mods.iter().enumerate() {
+        if let Some(first) = ids.insert(loaded.id(), idx) {
+            return Err(PatchError::DuplicateId {
+                id: loaded.id().to_string(),
+                first: mods[first].path.clone(),
+                second: loaded.path.clone(),
+            });
+        }
+    }
+
+    let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); mods.len()];
+    let mut pending: Vec<usize> = vec![0; mods.len()];
+
+    for (idx, loaded) in mods.iter().enumerate() {
+        if let Some(other) = loaded
+            .meta
+            .incompatible
+            .iter()
+            .find(|other| ids.contains_key(other.as_str()))
+        {
+            return Err(PatchError::Incompatible {
+                mod_name: loaded.id().to_string(),
+                other: other.clone(),
+                snippet: loaded.snippet(loaded.find_spans(
+                    &format!("\"{}\"", other),
+                    None,
+                    "declared incompatible here",
+                )),
+            });
+        }
+
+        for dependency in &loaded.meta.dependencies {
+            let dep_idx = *ids.get(dependency.as_str()).ok_or_else(|| {
+                PatchError::MissingDependency {
+                    mod_name: loaded.id().to_string(),
+                    dependency: dependency.clone(),
+                    snippet: loaded.snippet(loaded.find_spans(
+                        &format!("\"{}\"", dependency),
+                        None,
+                        "required here",
+                    )),
+                }
+            })?;
+
+            dependents[dep_idx].push(idx);
+            pending[idx] += 1;
+        }
+    }
+
+    // Kahn's algorithm, picking the lowest (priority, load order) among the ready mods
+    let mut ready: BinaryHeap<Reverse<(isize, usize)>> = pending
+        .iter()
+        .enumerate()
+        .filter(|(_, count)| **count == 0)
+        .map(|(idx, _)| Reverse((mods[idx].priority(), idx)))
+        .collect();
+    let mut order = Vec::with_capacity(mods.len());
+
+    while let Some(Reverse((_, idx))) = ready.pop() {
+        order.push(idx);
+
+        for &dependent in &dependents[idx] {
+            pending[dependent] -= 1;
+            if pending[dependent] == 0 {
+                ready.push(Reverse((mods[dependent].priority(), dependent)));
+            }
+        }
+    }
+
+    if order.len() < mods.len() {
+        return Err(PatchError::DependencyCycle(find_cycle(&mods, &ids, &pending)));
+    }
+
+    let mut slots: Vec<Option<LoadedPatchFile>> = mods.into_iter().map(Some).collect();
+
+    Ok(order
+        .into_iter()
+        .map(|idx| slots[idx].take().expect("each mod is ordered once"))
+        .collect())
+}
+
+/// Whether `mods[first]` comes before `mods[second]` because of their
+/// priorities or dependencies, rather than just the order they were loaded
+/// in. `mods` are expected as [`resolve_order`] returns them.
+pub fn is_ordered(mods: &[LoadedPatchFile], first: usize, second: usize) -> bool {
+    if mods[first].priority() != mods[second].priority() {
+        return true;
+    }
+
+    // Walk everything `second` depends on, directly or not
+    let mut stack = vec![second];
+    let mut seen = vec![false; mods.len()];
+    while let Some(idx) = stack.pop() {
+        for dependency in &mods[idx].meta.dependencies {
+            let Some(dep_idx) = mods.iter().position(|m| m.id() == dependency) else {
+                continue;
+            };
+            if dep_idx == first {
+                return true;
+            }
+            if !seen[dep_idx] {
+                seen[dep_idx] = true;
+                stack.push(dep_idx);
+            }
+        }
+    }
+
+    false
+}
+
+/// Walk dependencies from a mod that never became ready until one repeats
+fn find_cycle(
+    mods: &[LoadedPatchFile],
+    ids: &HashMap<&str, usize>,
+    pending: &[usize],
+) -> Vec<String> {
+    let Some(start) = pending.iter().position(|count| *count > 0) else {
+        return Vec::new();
+    };
+
+    let mut path = vec![start];
+    let mut current = start;
+
+    loop {
+        // Any dependency still pending is part of (or leads into) the cycle
+        let next = mods[current]
+            .meta
+            .dependencies
+            .iter()
+            .filter_map(|dep| ids.get(dep.as_str()).copied())
+            .find(|dep| pending[*dep] > 0)
+            .expect("a mod stuck in a cycle has a pending dependency");
+
+        if let Some(pos) = path.iter().position(|idx| *idx == next) {
+            let mut cycle: Vec<String> = path[pos..]
+                .iter()
+                .map(|idx| mods[*idx].id().to_string())
+                .collect();
+            cycle.push(mods[next].id().to_string());
+            return cycle;
+        }
+
+        path.push(next);
+        current = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loaded(name: &str, priority: isize, extra: &str) -> LoadedPatchFile {
+        let content = format!(
+            "patches = []\n\n[manifest]\nversion = \"1.0.0\"\ndump_lua = true\npriority = {}\n{}\n",
+            priority, extra
+        );
+
+        LoadedPatchFile::parse(name, format!("Mods/{}/lovely.toml", name), &content).unwrap()
+    }
+
+    fn names(mods: &[LoadedPatchFile]) -> Vec<&str> {
+        mods.iter().map(|m| m.name.as_str()).collect()
+    }
+
+    #[test]
+    fn dependencies_before_priority() {
+        let mods = vec![
+            loaded("Addon", -10, "dependencies = [\"Core\"]"),
+            loaded("Core", 5, ""),
+            loaded("Other", 0, ""),
+        ];
+
+        let ordered = resolve_order(mods).unwrap();
+        assert_eq!(names(&ordered), vec!["Other", "Core", "Addon"]);
+    }
+
+    #[test]
+    fn tells_decided_order_from_load_order() {
+        let mods = resolve_order(vec![
+            loaded("Addon", 0, "dependencies = [\"Lib\"]"),
+            loaded("Lib", 0, "dependencies = [\"Core\"]"),
+            loaded("Core", 0, ""),
+            loaded("Other", 0, ""),
+            loaded("Late", 1, ""),
+        ])
+        .unwrap();
+        assert_eq!(names(&mods), vec!["Core", "Lib", "Addon", "Other", "Late"]);
+
+        // Addon only depends on Core through Lib
+        assert!(is_ordered(&mods, 0, 2));
+        assert!(is_ordered(&mods, 1, 4));
+        assert!(!is_ordered(&mods, 0, 3));
+        assert!(!is_ordered(&mods, 2, 3));
+    }
+
+    #[test]
+    fn cycles_and_missing_dependencies_fail() {
+        let cycle = resolve_order(vec![
+            loaded("A", 0, "dependencies = [\"B\"]"),
+            loaded("B", 0, "dependencies = [\"A\"]"),
+        ]);
+        assert!(matches!(cycle, Err(PatchError::DependencyCycle(c)) if c.len() == 3));
+
+        let missing = resolve_order(vec![loaded("A", 0, "dependencies = [\"Nope\"]")]);
+        assert!(matches!(missing, Err(PatchError::MissingDependency { .. })));
+    }
+
+    #[test]
+    fn duplicate_ids_fail() {
+        let duplicate = resolve_order(vec![
+            loaded("A", 0, "id = \"shared\""),
+            loaded("B", 0, "id = \"shared\""),
+            loaded("C", 0, "dependencies = [\"shared\"]"),
+        ]);
+
+        assert!(matches!(
+            duplicate,
+            Err(PatchError::DuplicateId { id, first, second })
+                if id == "shared" && first.ends_with("A/lovely.toml") && second.ends_with("B/lovely.toml")
+        ));
+    }
+}
@@ -0,0 +1,227 @@
+//! Where each line of a patched file came from.
+//!
+//! Tracebacks from the phone point at lines of the patched file, which
+//! say nothing about the original game source or the mod responsible.
+//! `Patcher` keeps a [`SourceMap`] up to date while applying patches and
+//! saves it as `<output>.map.json`, which can then be used to annotate a
+//! pasted stack trace.
+
+use super::PatchError;
+use crate::balapatch::utils::line_diff::{self, LineOp};
+use crop::Rope;
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// `file.lua:123:` as it shows up in Lua errors and tracebacks,
+/// optionally as a chunk name (`[string "file.lua"]:123:`)
+static TRACE_LINE_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?:\[string ")?(?P<file>[^\s:"\[\]]+\.lua)"?\]?:(?P<line>\d+):"#)
+        .expect("valid traceback regex")
+});
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "from", rename_all = "snake_case")]
+pub enum LineOrigin {
+    /// Unchanged from the source file, at this (1-based) line
+    Original { line: usize },
+    /// Inserted or rewritten by a patch
+    Patch {
+        mod_name: String,
+        path: PathBuf,
+        patch_index: usize,
+    },
+}
+
+impl Display for LineOrigin {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LineOrigin::Original { line } => write!(f, "original line {}", line),
+            LineOrigin::Patch {
+                mod_name,
+                path,
+                patch_index,
+            } => write!(
+                f,
+                "mod '{}', patch #{} in {}",
+                mod_name,
+                patch_index,
+                path.display()
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SourceMap {
+    pub target: String,
+    pub source: PathBuf,
+    /// Origin of every line of the patched file, line 1 first
+    pub lines: Vec<LineOrigin>,
+}
+
+impl SourceMap {
+    /// Map for a file nothing has been applied to yet
+    pub fn new<P: AsRef<Path>>(target: &str, source: P, content: &Rope) -> Self {
+        Self {
+            target: target.to_string(),
+            source: source.as_ref().to_path_buf(),
+            lines: (1..=content.line_len())
+                .map(|line| LineOrigin::Original { line })
+                .collect(),
+        }
+    }
+
+    /// Where the map for `output` gets saved
+    pub fn path_for<P: AsRef<Path>>(output: P) -> PathBuf {
+        let mut path = output.as_ref().as_os_str().to_owned();
+        path.push(".map.json");
+        PathBuf::from(path)
+    }
+
+    /// Origin of a 1-based line of the patched file
+    pub fn origin(&self, line: usize) -> Option<&LineOrigin> {
+        line.checked_sub(1).and_then(|idx| self.lines.get(idx))
+    }
+
+    /// Update the map after `before` was turned into `after` by a patch.
+    /// Lines the patch added or changed are attributed to `origin`.
+    pub(super) fn record(&mut self, before: &Rope, after: &Rope, origin: LineOrigin) {
+        let before: Vec<String> = before.lines().map(|line| line.to_string()).collect();
+        let after: Vec<String> = after.lines().map(|line| line.to_string()).collect();
+
+        let mut lines = Vec::with_capacity(after.len());
+        for op in line_diff::diff_lines(&before, &after) {
+            match op {
+                LineOp::Equal { old, .. } => lines.push(
+                    self.lines
+                        .get(old)
+                        .cloned()
+                        .unwrap_or_else(|| origin.clone()),
+                ),
+                LineOp::Insert { .. } => lines.push(origin.clone()),
+                LineOp::Delete { .. } => {}
+            }
+        }
+
+        self.lines = lines;
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), PatchError> {
+        let path = path.as_ref();
+        let json = serde_json::to_string_pretty(self).map_err(|e| PatchError::SourceMap {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+
+        fs::write(path, json).map_err(|e| PatchError::io(path, e))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, PatchError> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path).map_err(|e| PatchError::io(path, e))?;
+
+        serde_json::from_str(&json).map_err(|e| PatchError::SourceMap {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })
+    }
+
+    /// Load every `*.map.json` in `dir`
+    pub fn load_dir<P: AsRef<Path>>(dir: P) -> Result<Vec<Self>, PatchError> {
+        let dir = dir.as_ref();
+        let mut maps = Vec::new();
+
+        for entry in fs::read_dir(dir).map_err(|e| PatchError::io(dir, e))? {
+            let path = entry.map_err(|e| PatchError::io(dir, e))?.path();
+            if path.to_string_lossy().ends_with(".map.json") {
+                maps.push(Self::load(&path)?);
+            }
+        }
+
+        Ok(maps)
+    }
+
+    fn applies_to(&self, file: &str) -> bool {
+        let file = file.replace('\\', "/");
+        file == self.target || file.ends_with(&format!("/{}", self.target))
+    }
+}
+
+/// Annotate every `file.lua:line:` in `trace` that one of `maps` covers
+/// with where that line came from, e.g.
+/// `game.lua:1234 (mod 'Foo', patch #2 in Mods/Foo/lovely.toml):`
+pub fn translate_trace(maps: &[SourceMap], trace: &str) -> String {
+    TRACE_LINE_RE
+        .replace_all(trace, |caps: &Captures| {
+            let whole = &caps[0];
+            let origin = caps["line"].parse::<usize>().ok().and_then(|line| {
+                maps.iter()
+                    .find(|map| map.applies_to(&caps["file"]))
+                    .and_then(|map| map.origin(line))
+            });
+
+            match origin {
+                Some(origin) => format!("{} ({}):", &whole[..whole.len() - 1], origin),
+                None => whole.to_string(),
+            }
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patch_origin(patch_index: usize) -> LineOrigin {
+        LineOrigin::Patch {
+            mod_name: "Foo".to_string(),
+            path: PathBuf::from("Mods/Foo/lovely.toml"),
+            patch_index,
+        }
+    }
+
+    #[test]
+    fn tracks_lines_through_patches() {
+        let original = Rope::from("a\nb\nc\n");
+        let mut map = SourceMap::new("game.lua", "game.lua", &original);
+
+        let first = Rope::from("a\nx\nb\nc\n");
+        map.record(&original, &first, patch_origin(0));
+        let second = Rope::from("a\nx\nb\ny\n");
+        map.record(&first, &second, patch_origin(1));
+
+        assert_eq!(
+            map.lines,
+            vec![
+                LineOrigin::Original { line: 1 },
+                patch_origin(0),
+                LineOrigin::Original { line: 2 },
+                patch_origin(1),
+            ]
+        );
+    }
+
+    #[test]
+    fn translates_tracebacks() {
+        let original = Rope::from("a\nb\n");
+        let mut map = SourceMap::new("functions/misc.lua", "misc.lua", &original);
+        map.record(&original, &Rope::from("a\nx\nb\n"), patch_origin(3));
+
+        let trace = "Error: functions/misc.lua:2: attempt to index a nil value\n\
+                     stack traceback:\n\
+                     \t[string \"functions/misc.lua\"]:3: in function 'f'\n\
+                     \tmain.lua:10: in main chunk";
+
+        assert_eq!(
+            translate_trace(&[map], trace),
+            "Error: functions/misc.lua:2 (mod 'Foo', patch #3 in Mods/Foo/lovely.toml): attempt to index a nil value\n\
+             stack traceback:\n\
+             \t[string \"functions/misc.lua\"]:3 (original line 2): in function 'f'\n\
+             \tmain.lua:10: in main chunk"
+        );
+    }
+}
@@ -0,0 +1,147 @@
+//! Lua validation of patched targets.
+//!
+//! Broken Lua only shows up as a crash on the phone, so `Patcher`
+//! checks its output before writing it. When the result doesn't
+//! validate, the patches get replayed one by one to find the first
+//! one that broke it. Module patches are replayed too, through the same
+//! `ModuleHandler`, since handlers can edit the target's text.
+
+use super::{LoadedPatchFile, PatchError, Patcher};
+use crop::Rope;
+use lua_validator::LVal;
+
+impl Patcher {
+    pub(super) fn validate_output(
+        &self,
+        target: &str,
+        original: &Rope,
+        patched: &Rope,
+        mods: &[LoadedPatchFile],
+    ) -> Result<(), PatchError> {
+        let err = match LVal::new(patched.to_string()).with_name(target).validate() {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+
+        if LVal::new(original.to_string()).validate().is_err() {
+            println!(
+                "Warning: '{}' isn't valid Lua even before patching, skipping validation",
+                target
+            );
+            return Ok(());
+        }
+
+        let mut rope = original.clone();
+
+        for loaded in mods {
+            for (patch_index, patch) in loaded.file.patches.iter().enumerate() {
+                let before = rope.clone();
+                self.apply_patch(target, &mut rope, loaded, patch_index, patch)?;
+
+                if let Err(err) = LVal::new(rope.to_string()).with_name(target).validate() {
+                    return Err(PatchError::InvalidLua {
+                        at: loaded.patch_location(patch_index, target, Vec::new()),
+                        line: first_changed_line(&before, &rope),
+                        message: err.to_string(),
+                    });
+                }
+            }
+        }
+
+        // The replay didn't run into the error, e.g. because a module handler
+        // did something different the second time, so blame the whole output
+        Err(PatchError::InvalidOutput {
+            target: target.to_string(),
+            line: err.first_error().map_or(1, |diag| diag.line),
+            message: err.to_string(),
+        })
+    }
+}
+
+/// 1-based number of the first line that differs between `before` and `after`
+fn first_changed_line(before: &Rope, after: &Rope) -> usize {
+    let changed = before
+        .lines()
+        .zip(after.lines())
+        .position(|(a, b)| a != b)
+        .unwrap_or_else(|| before.line_len().min(after.line_len()));
+
+    changed + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL: &str = "local x = 1\nreturn x\n";
+
+    fn pattern(payload: &str) -> String {
+        format!(
+            "[[patches]]\n[patches.pattern]\ntarget = \"main.lua\"\npattern = \"local x = 1\"\nposition = \"after\"\npayload = \"{}\"\nmatch_indent = true\n",
+            payload
+        )
+    }
+
+    fn patch_file(patches: &[&str]) -> LoadedPatchFile {
+        let content = format!(
+            "[manifest]\nversion = \"1.0.0\"\ndump_lua = true\npriority = 0\n\n{}",
+            patches
+                .iter()
+                .map(|payload| pattern(payload))
+                .collect::<String>()
+        );
+
+        LoadedPatchFile::parse("Mod", "Mods/Mod/lovely.toml", &content).unwrap()
+    }
+
+    #[test]
+    fn blames_the_first_breaking_patch() {
+        let patcher = Patcher::new();
+        let loaded = patch_file(&["local y = 2", "local = 3", "if then"]);
+        let original = Rope::from(ORIGINAL);
+        let mut patched = original.clone();
+        for (patch_index, patch) in loaded.file.patches.iter().enumerate() {
+            patcher
+                .apply_patch("main.lua", &mut patched, &loaded, patch_index, patch)
+                .unwrap();
+        }
+
+        match patcher.validate_output("main.lua", &original, &patched, &[loaded]) {
+            Err(PatchError::InvalidLua { at, line, .. }) => {
+                assert_eq!(at.patch_index, 1);
+                assert_eq!(line, 2);
+            }
+            other => panic!("expected the second patch to be blamed, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn blames_the_output_when_no_patch_breaks_it() {
+        let patcher = Patcher::new();
+        let original = Rope::from(ORIGINAL);
+        let patched = Rope::from("local x = 1\nreturn x +\n");
+
+        assert!(
+            patcher
+                .validate_output(
+                    "main.lua",
+                    &original,
+                    &original,
+                    &[patch_file(&["local y = 2"])]
+                )
+                .is_ok()
+        );
+        match patcher.validate_output(
+            "main.lua",
+            &original,
+            &patched,
+            &[patch_file(&["local y = 2"])],
+        ) {
+            Err(PatchError::InvalidOutput { target, line, .. }) => {
+                assert_eq!(target, "main.lua");
+                assert_eq!(line, 3);
+            }
+            other => panic!("expected the whole output to be blamed, got {:?}", other),
+        }
+    }
+}
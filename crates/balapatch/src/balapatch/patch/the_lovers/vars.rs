@@ -0,0 +1,106 @@
+//! `{{lovely:name}}` interpolation for patch payloads.
+//!
+//! Lovely's own `apply_var_interp` panics on unknown variables and is
+//! meant to be run over every line of the patched file, which also
+//! rewrites anything in the game sources that happens to look like a
+//! variable. Here only payloads get interpolated, and each mod only
+//! sees its own `[vars]` (on top of whatever the `Patcher` defines).
+
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+
+static VAR_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\{\{lovely:(\w+)\}\}").expect("valid var regex"));
+
+/// The variables visible to a single mod's patches
+#[derive(Debug, Clone, Copy)]
+pub struct VarScope<'a> {
+    mod_vars: &'a HashMap<String, String>,
+    globals: &'a HashMap<String, String>,
+}
+
+impl<'a> VarScope<'a> {
+    /// Mod variables shadow globals with the same name
+    pub fn new(mod_vars: &'a HashMap<String, String>, globals: &'a HashMap<String, String>) -> Self {
+        Self { mod_vars, globals }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&'a str> {
+        self.mod_vars
+            .get(name)
+            .or_else(|| self.globals.get(name))
+            .map(String::as_str)
+    }
+
+    /// Replace every `{{lovely:name}}` in `text`.
+    ///
+    /// Returns the names of all undefined variables (deduplicated, in
+    /// order of appearance) if any are missing.
+    pub fn interpolate(&self, text: &str) -> Result<String, Vec<String>> {
+        let mut undefined: Vec<String> = Vec::new();
+
+        let result = VAR_RE.replace_all(text, |caps: &Captures| match self.get(&caps[1]) {
+            Some(value) => value.to_string(),
+            None => {
+                if !undefined.iter().any(|name| name == &caps[1]) {
+                    undefined.push(caps[1].to_string());
+                }
+                caps[0].to_string()
+            }
+        });
+
+        if undefined.is_empty() {
+            Ok(result.into_owned())
+        } else {
+            Err(undefined)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn interpolates_mod_vars_over_globals() {
+        let mod_vars = vars(&[("chips", "50")]);
+        let globals = vars(&[("chips", "10"), ("mult", "4")]);
+        let scope = VarScope::new(&mod_vars, &globals);
+
+        assert_eq!(
+            scope.interpolate("G.chips = {{lovely:chips}} * {{lovely:mult}}"),
+            Ok("G.chips = 50 * 4".to_string())
+        );
+        assert_eq!(scope.interpolate("{{ lovely:chips }}"), Ok("{{ lovely:chips }}".to_string()));
+    }
+
+    #[test]
+    fn lists_undefined_vars_once() {
+        let mod_vars = vars(&[("chips", "50")]);
+        let globals = HashMap::new();
+        let scope = VarScope::new(&mod_vars, &globals);
+
+        assert_eq!(
+            scope.interpolate("{{lovely:mult}} {{lovely:chips}} {{lovely:ante}} {{lovely:mult}}"),
+            Err(vec!["mult".to_string(), "ante".to_string()])
+        );
+    }
+
+    #[test]
+    fn scopes_are_per_mod() {
+        let first = vars(&[("secret", "1")]);
+        let second = HashMap::new();
+        let globals = HashMap::new();
+
+        assert!(VarScope::new(&first, &globals).interpolate("{{lovely:secret}}").is_ok());
+        assert!(VarScope::new(&second, &globals).interpolate("{{lovely:secret}}").is_err());
+    }
+}
@@ -1,16 +1,21 @@
 use crate::balapatch::apk::zipalign::ZipAlign;
+use crate::balapatch::patch::the_lovers::source_map::{translate_trace, SourceMap};
 use crate::balapatch::tui::progress::{create_spinner, GLOBAL_MP};
 use crate::balapatch::tui::select_file::select_path_from_current_dir;
-use crate::balapatch::{adb, balatro};
+use crate::balapatch::patch::the_lovers::{InjectingModuleHandler, Patcher, find_patch_files};
+use crate::balapatch::{adb, balatro, dev, logcat};
 use adb_client::ADBServer;
 use balapatch_derive::{EnumChoice, EnumDisplay};
 use indicatif::ProgressBar;
 use inquire::error::InquireResult;
 use inquire::ui::{Attributes, Color, RenderConfig, Styled};
 use inquire::validator::{StringValidator, Validation};
-use inquire::{CustomUserError, InquireError, MultiSelect, Select, Text};
+use inquire::{Confirm, CustomUserError, InquireError, MultiSelect, Select, Text};
+use lua_validator::{BatchValidator, LintConfig};
 use std::clone::Clone;
 use std::fmt::{Debug, Display, Formatter};
+use std::path::Path;
+use std::time::Duration;
 
 #[derive(Debug, Copy, Clone, EnumDisplay, EnumChoice)]
 #[allow(clippy::upper_case_acronyms)]
@@ -25,12 +30,23 @@ enum AdbCommands {
     KillServer,
     ListDevices,
     CheckConnection,
+    CaptureCrashLog,
+    StreamLog,
+    DevPush,
+    LaunchBalatro,
+    StopBalatro,
+    ClearBalatroData,
+    BalatroStatus,
 }
 
 #[derive(Debug, Copy, Clone, EnumDisplay, EnumChoice)]
 enum BalatroCommands {
     Check,
     ValidateAPKs,
+    ValidateLua,
+    CheckConflicts,
+    TranslateTrace,
+    DevBuild,
     Pull,
     Unpack,
     Mod,
@@ -74,6 +90,27 @@ pub async fn balapatch() -> InquireResult<()> {
                         println!("Couldn't find a valid device");
                     }
                 }
+                AdbCommands::CaptureCrashLog => {
+                    balatro_capture_crash_log(&mut adb_server)?;
+                }
+                AdbCommands::StreamLog => {
+                    balatro_stream_log(&mut adb_server)?;
+                }
+                AdbCommands::DevPush => {
+                    balatro_dev_push(&mut adb_server)?;
+                }
+                AdbCommands::LaunchBalatro => {
+                    balatro_launch(&mut adb_server)?;
+                }
+                AdbCommands::StopBalatro => {
+                    balatro_stop(&mut adb_server)?;
+                }
+                AdbCommands::ClearBalatroData => {
+                    balatro_clear_data(&mut adb_server)?;
+                }
+                AdbCommands::BalatroStatus => {
+                    balatro_status(&mut adb_server)?;
+                }
             }
         }
         BalapatchCommands::Balatro => {
@@ -96,6 +133,18 @@ pub async fn balapatch() -> InquireResult<()> {
                 BalatroCommands::ValidateAPKs => {
                     balatro_validate(adb_server).await?;
                 }
+                BalatroCommands::ValidateLua => {
+                    balatro_validate_lua()?;
+                }
+                BalatroCommands::CheckConflicts => {
+                    balatro_check_conflicts()?;
+                }
+                BalatroCommands::TranslateTrace => {
+                    balatro_translate_trace()?;
+                }
+                BalatroCommands::DevBuild => {
+                    balatro_dev_build()?;
+                }
             }
         }
     }
@@ -121,13 +170,22 @@ pub fn balatro_adb_disconnect(adb_server: &mut ADBServer) -> InquireResult<()> {
 
 pub fn balatro_check(adb_server: &mut ADBServer) -> InquireResult<()> {
     let spinner = create_spinner("Checking Balatro installation...");
-    if balatro::check_balatro_install(adb_server)
+    let install = match balatro::balatro_install(adb_server)
         .expect("Failed to check balatro install")
-        .0
     {
-        spinner.finish_with_message("Found a Balatro install on the target device :3");
-    } else {
-        spinner.finish_with_message("Could not find a valid Balatro installation :(");
+        Some(install) => install,
+        None => {
+            spinner.finish_with_message("Could not find a valid Balatro installation :(");
+            return Ok(());
+        }
+    };
+    spinner.finish_with_message("Found a Balatro install on the target device :3");
+    println!("{}", install);
+
+    let base_apk = balatro::pulled_base_apk();
+    match install.compare_with_pulled(&base_apk) {
+        Ok(check) => println!("{}", check),
+        Err(e) => println!("Couldn't read '{}': {:#}", base_apk.display(), e),
     }
 
     Ok(())
@@ -153,7 +211,7 @@ pub fn balatro_pull(adb_server: &mut ADBServer) -> Result<(), InquireError> {
     let out_dir = if change_out_directory {
         select_path_from_current_dir("Please select a directory...")?
     } else {
-        "balapatch/balatro_apks".to_string()
+        balatro::DEFAULT_APKS_DIR.to_string()
     };
 
     let spinner = create_spinner("Pulling Balatro APKs...");
@@ -183,7 +241,7 @@ pub async fn balatro_unpack(mut adb_server: ADBServer) -> Result<(), InquireErro
     let apk_path = if custom_apk_path {
         Text::new("Please input the path to the Balatro APK:").prompt()?
     } else {
-        "balapatch/balatro_apks".to_string()
+        balatro::DEFAULT_APKS_DIR.to_string()
     };
 
     let out_dir = if change_output_dir {
@@ -243,7 +301,7 @@ pub async fn balatro_validate(mut adb_server: ADBServer) -> Result<(), InquireEr
     let apk_path = if change_apk_path {
         Text::new("Please input the path to the Balatro APK:").prompt()?
     } else {
-        "balapatch/balatro_apks".to_string()
+        balatro::DEFAULT_APKS_DIR.to_string()
     };
 
     let out_dir = if change_output_dir {
@@ -273,3 +331,288 @@ pub async fn balatro_validate(mut adb_server: ADBServer) -> Result<(), InquireEr
 
     Ok(())
 }
+
+pub fn balatro_validate_lua() -> Result<(), InquireError> {
+    let path = select_path_from_current_dir(
+        "Please select a game directory, .love or APK to validate...",
+    )?;
+    let lint = Confirm::new("Also lint for common mod mistakes?")
+        .with_default(false)
+        .prompt()?;
+
+    let mut validator = BatchValidator::new();
+    if lint {
+        validator = validator.lint(LintConfig::balatro());
+    }
+
+    let spinner = create_spinner("Validating Lua sources...");
+    match validator.validate_path(&path) {
+        Ok(report) => {
+            spinner.finish_with_message(report.to_string());
+            print!("{}", report.render());
+        }
+        Err(e) => {
+            spinner.finish_with_message(format!("Failed to validate '{}': {:#}", path, e));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn balatro_check_conflicts() -> Result<(), InquireError> {
+    let source = select_path_from_current_dir("Please select the unpacked game file to check...")?;
+    let file_name = Path::new(&source)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let target = Text::new("Target the patches refer to it as (e.g. functions/button_callbacks.lua):")
+        .with_default(&file_name)
+        .prompt()?;
+    let mods_dir = select_path_from_current_dir("Please select the Mods directory...")?;
+
+    let patches = match find_patch_files(&mods_dir) {
+        Ok(patches) => patches,
+        Err(e) => {
+            println!("{}", e.render());
+            return Ok(());
+        }
+    };
+
+    let spinner = create_spinner("Looking for conflicting patches...");
+    let result = Patcher::new()
+        .source(&source)
+        .target_name(target)
+        .patches(patches)
+        .check_conflicts();
+    spinner.finish_and_clear();
+
+    match result {
+        Ok(report) => print!("{}", report),
+        Err(e) => println!("{}", e.render()),
+    }
+
+    Ok(())
+}
+
+/// Asks for a source map (or a directory of them) and loads it.
+/// Returns `None` if loading failed, after printing why.
+fn prompt_source_maps() -> Result<Option<Vec<SourceMap>>, InquireError> {
+    let maps_path =
+        select_path_from_current_dir("Please select a source map, or the directory containing them...")?;
+
+    let maps = if Path::new(&maps_path).is_dir() {
+        SourceMap::load_dir(&maps_path)
+    } else {
+        SourceMap::load(&maps_path).map(|map| vec![map])
+    };
+
+    match maps {
+        Ok(maps) => Ok(Some(maps)),
+        Err(e) => {
+            println!("{}", e.render());
+            Ok(None)
+        }
+    }
+}
+
+pub fn balatro_translate_trace() -> Result<(), InquireError> {
+    let Some(maps) = prompt_source_maps()? else {
+        return Ok(());
+    };
+
+    println!("Paste the stack trace, followed by an empty line:");
+    let trace = std::io::stdin()
+        .lines()
+        .map_while(Result::ok)
+        .take_while(|line| !line.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    println!("{}", translate_trace(&maps, &trace));
+    Ok(())
+}
+
+pub fn balatro_capture_crash_log(adb_server: &mut ADBServer) -> Result<(), InquireError> {
+    let spinner = create_spinner("Capturing Balatro's log...");
+    let log = match logcat::capture_crash_log(adb_server, "balapatch/logs") {
+        Ok(log) => log,
+        Err(e) => {
+            spinner.finish_with_message(format!("Failed to capture the log: {:#}", e));
+            return Ok(());
+        }
+    };
+    spinner.finish_with_message(format!(
+        "Saved {} log lines to {}",
+        log.lines.len(),
+        log.path.display()
+    ));
+
+    if log.tracebacks.is_empty() {
+        println!("No Lua tracebacks found in the log");
+        return Ok(());
+    }
+
+    let translate = Confirm::new(&format!(
+        "Found {} traceback(s). Map them back to the original sources?",
+        log.tracebacks.len()
+    ))
+    .with_default(true)
+    .prompt()?;
+
+    let maps = if translate {
+        prompt_source_maps()?.unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+
+    for trace in &log.tracebacks {
+        println!("{}\n", translate_trace(&maps, trace));
+    }
+
+    Ok(())
+}
+
+pub fn balatro_stream_log(adb_server: &mut ADBServer) -> Result<(), InquireError> {
+    let tags = Text::new("Only show these tags (comma separated, empty for all):")
+        .with_placeholder(&logcat::LOVE_TAGS.join(","))
+        .prompt()?;
+    let min_level = logcat::LogLevel::choice("Minimum log level:")?;
+
+    let filter = logcat::LogFilter {
+        tags: tags
+            .split(',')
+            .map(|tag| tag.trim().to_string())
+            .filter(|tag| !tag.is_empty())
+            .collect(),
+        min_level,
+    };
+
+    if let Err(e) = logcat::stream_logcat(adb_server, filter, "balapatch/logs") {
+        println!("Log streaming failed: {:#}", e);
+    }
+
+    Ok(())
+}
+
+pub fn balatro_dev_build() -> Result<(), InquireError> {
+    let main_lua = select_path_from_current_dir(
+        "Please select the unpacked game's main.lua to turn into a dev build...",
+    )?;
+
+    let patch = match dev::write_dev_patch("balapatch/dev") {
+        Ok(patch) => patch,
+        Err(e) => {
+            println!("Failed to write the dev patch: {:#}", e);
+            return Ok(());
+        }
+    };
+
+    let result = Patcher::new()
+        .source(&main_lua)
+        .patch(patch)
+        .output(&main_lua)
+        .target_name("main.lua")
+        .module_handler(InjectingModuleHandler)
+        // A map next to main.lua would get repacked into the APK
+        .source_map(false)
+        .patch_file();
+
+    match result {
+        Ok(()) => println!(
+            "'{}' now loads overrides from {}. Repack and install the APK once, \
+             then use the dev push action for every change after that.",
+            main_lua,
+            dev::DEV_DIR.as_str()
+        ),
+        Err(e) => println!("{}", e.render()),
+    }
+
+    Ok(())
+}
+
+pub fn balatro_dev_push(adb_server: &mut ADBServer) -> Result<(), InquireError> {
+    let host_dir = select_path_from_current_dir(
+        "Please select the mod directory to push (laid out like the game's sources)...",
+    )?;
+    let clear = Confirm::new("Clear previously pushed files first?")
+        .with_default(false)
+        .prompt()?;
+    let watch = Confirm::new("Keep watching the directory and push on save?")
+        .with_default(true)
+        .prompt()?;
+
+    if clear && let Err(e) = dev::clear_dev_dir(adb_server) {
+        println!("Failed to clear the dev directory: {:#}", e);
+        return Ok(());
+    }
+
+    let mut session = dev::DevSession::new(&host_dir);
+
+    if watch {
+        let spinner = create_spinner("Watching for changes: [q] quit");
+        spinner.enable_steady_tick(Duration::from_millis(120));
+        match dev::watch(adb_server, &mut session, Duration::from_millis(500)) {
+            Ok(()) => spinner.finish_with_message("Stopped watching"),
+            Err(e) => spinner.finish_with_message(format!("Dev push failed: {:#}", e)),
+        }
+        return Ok(());
+    }
+
+    let spinner = create_spinner("Pushing files...");
+    let result = session
+        .push_changed(adb_server)
+        .and_then(|pushed| balatro::restart_balatro(adb_server).map(|_| pushed));
+    match result {
+        Ok(pushed) => spinner.finish_with_message(format!("{} and restarted Balatro", pushed)),
+        Err(e) => spinner.finish_with_message(format!("Dev push failed: {:#}", e)),
+    }
+
+    Ok(())
+}
+
+pub fn balatro_launch(adb_server: &mut ADBServer) -> Result<(), InquireError> {
+    let spinner = create_spinner("Launching Balatro...");
+    match balatro::launch_balatro(adb_server) {
+        Ok(()) => spinner.finish_with_message("Balatro launched"),
+        Err(e) => spinner.finish_with_message(format!("Failed to launch Balatro: {:#}", e)),
+    }
+
+    Ok(())
+}
+
+pub fn balatro_stop(adb_server: &mut ADBServer) -> Result<(), InquireError> {
+    let spinner = create_spinner("Stopping Balatro...");
+    match balatro::force_stop_balatro(adb_server) {
+        Ok(()) => spinner.finish_with_message("Balatro stopped"),
+        Err(e) => spinner.finish_with_message(format!("Failed to stop Balatro: {:#}", e)),
+    }
+
+    Ok(())
+}
+
+pub fn balatro_clear_data(adb_server: &mut ADBServer) -> Result<(), InquireError> {
+    let confirmed = Confirm::new("This deletes all of Balatro's saves and settings on the device. Continue?")
+        .with_default(false)
+        .prompt()?;
+    if !confirmed {
+        return Ok(());
+    }
+
+    let spinner = create_spinner("Clearing Balatro's data...");
+    match balatro::clear_balatro_data(adb_server) {
+        Ok(()) => spinner.finish_with_message("Balatro's data cleared"),
+        Err(e) => spinner.finish_with_message(format!("{:#}", e)),
+    }
+
+    Ok(())
+}
+
+pub fn balatro_status(adb_server: &mut ADBServer) -> Result<(), InquireError> {
+    let spinner = create_spinner("Checking on Balatro...");
+    match balatro::balatro_status(adb_server) {
+        Ok(status) => spinner.finish_with_message(status.to_string()),
+        Err(e) => spinner.finish_with_message(format!("Failed to check on Balatro: {:#}", e)),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,149 @@
+//! Running external tools (apktool, java, ...) without losing what they
+//! printed when they fail.
+
+use std::ffi::OsString;
+use std::fmt::{Display, Formatter};
+use std::process::{Command, ExitStatus, Output};
+
+/// Lines of output kept when displaying a failure. The full output is
+/// still in the error.
+const SHOWN_LINES: usize = 20;
+
+#[derive(Debug)]
+pub enum CommandError {
+    /// The program couldn't be started at all, e.g. it isn't installed
+    Spawn {
+        program: OsString,
+        args: Vec<OsString>,
+        source: std::io::Error,
+    },
+    /// The program ran but didn't exit successfully. Output is kept as raw
+    /// bytes, since tools don't always print valid UTF-8.
+    Failed {
+        program: OsString,
+        args: Vec<OsString>,
+        status: ExitStatus,
+        stdout: Vec<u8>,
+        stderr: Vec<u8>,
+    },
+}
+
+impl CommandError {
+    pub fn stdout(&self) -> &[u8] {
+        match self {
+            CommandError::Spawn { .. } => &[],
+            CommandError::Failed { stdout, .. } => stdout,
+        }
+    }
+
+    pub fn stderr(&self) -> &[u8] {
+        match self {
+            CommandError::Spawn { .. } => &[],
+            CommandError::Failed { stderr, .. } => stderr,
+        }
+    }
+}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandError::Spawn { program, args, .. } => {
+                write!(f, "Failed to run `{}`", command_line(program, args))
+            }
+            CommandError::Failed {
+                program,
+                args,
+                status,
+                stdout,
+                stderr,
+            } => {
+                write!(f, "`{}` failed ({})", command_line(program, args), status)?;
+
+                // apktool reports some errors on stdout, so show both
+                for (name, output) in [("stderr", stderr), ("stdout", stdout)] {
+                    let text = String::from_utf8_lossy(output);
+                    let lines: Vec<&str> = text.trim_end().lines().collect();
+                    if lines.is_empty() {
+                        continue;
+                    }
+
+                    write!(f, "\n{}:", name)?;
+                    if lines.len() > SHOWN_LINES {
+                        write!(f, "\n  ... {} more lines", lines.len() - SHOWN_LINES)?;
+                    }
+                    for line in &lines[lines.len().saturating_sub(SHOWN_LINES)..] {
+                        write!(f, "\n  {}", line)?;
+                    }
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for CommandError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CommandError::Spawn { source, .. } => Some(source),
+            CommandError::Failed { .. } => None,
+        }
+    }
+}
+
+/// Run `command` to completion, capturing its output
+pub fn run(command: &mut Command) -> Result<Output, CommandError> {
+    let program = command.get_program().to_os_string();
+    let args: Vec<OsString> = command.get_args().map(|arg| arg.to_os_string()).collect();
+
+    let output = match command.output() {
+        Ok(output) => output,
+        Err(source) => {
+            return Err(CommandError::Spawn {
+                program,
+                args,
+                source,
+            });
+        }
+    };
+
+    if output.status.success() {
+        Ok(output)
+    } else {
+        Err(CommandError::Failed {
+            program,
+            args,
+            status: output.status,
+            stdout: output.stdout,
+            stderr: output.stderr,
+        })
+    }
+}
+
+fn command_line(program: &OsString, args: &[OsString]) -> String {
+    std::iter::once(program)
+        .chain(args)
+        .map(|part| part.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_output_of_failures() {
+        let err = run(Command::new("sh").args(["-c", "printf 'out\\377'; printf 'bad' >&2; exit 3"]))
+            .unwrap_err();
+
+        assert_eq!(err.stdout(), b"out\xff");
+        assert_eq!(err.stderr(), b"bad");
+        assert!(err.to_string().contains("stderr:\n  bad"));
+
+        assert!(matches!(
+            run(&mut Command::new("balapatch-no-such-program")),
+            Err(CommandError::Spawn { .. })
+        ));
+    }
+}
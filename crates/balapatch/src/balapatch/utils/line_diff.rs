@@ -0,0 +1,208 @@
+//! Line based diffing (Myers' algorithm), for working out which lines of
+//! a patched file came from where.
+
+use std::ops::RangeInclusive;
+
+/// Past this many edits the middle of the diff is treated as fully
+/// replaced, so a completely rewritten file can't eat all the memory
+const MAX_EDITS: usize = 4096;
+
+/// One step of turning `old` into `new`. Indices are 0-based.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineOp {
+    Equal { old: usize, new: usize },
+    Delete { old: usize },
+    Insert { new: usize },
+}
+
+/// Shortest edit script turning `old` into `new`
+pub fn diff_lines<T: PartialEq>(old: &[T], new: &[T]) -> Vec<LineOp> {
+    let prefix = old
+        .iter()
+        .zip(new)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    let mut ops: Vec<LineOp> = (0..prefix)
+        .map(|idx| LineOp::Equal { old: idx, new: idx })
+        .collect();
+
+    match myers(old_mid, new_mid) {
+        Some(middle) => ops.extend(middle.into_iter().map(|op| match op {
+            LineOp::Equal { old, new } => LineOp::Equal {
+                old: old + prefix,
+                new: new + prefix,
+            },
+            LineOp::Delete { old } => LineOp::Delete { old: old + prefix },
+            LineOp::Insert { new } => LineOp::Insert { new: new + prefix },
+        })),
+        None => {
+            ops.extend((0..old_mid.len()).map(|idx| LineOp::Delete { old: idx + prefix }));
+            ops.extend((0..new_mid.len()).map(|idx| LineOp::Insert { new: idx + prefix }));
+        }
+    }
+
+    ops.extend((0..suffix).map(|idx| LineOp::Equal {
+        old: old.len() - suffix + idx,
+        new: new.len() - suffix + idx,
+    }));
+
+    ops
+}
+
+/// 1-based, inclusive ranges of lines in `new` that aren't in `old`
+pub fn inserted_ranges<T: PartialEq>(old: &[T], new: &[T]) -> Vec<RangeInclusive<usize>> {
+    let mut ranges: Vec<RangeInclusive<usize>> = Vec::new();
+
+    for op in diff_lines(old, new) {
+        let LineOp::Insert { new } = op else {
+            continue;
+        };
+
+        let line = new + 1;
+        match ranges.last_mut() {
+            Some(range) if *range.end() + 1 == line => *range = *range.start()..=line,
+            _ => ranges.push(line..=line),
+        }
+    }
+
+    ranges
+}
+
+/// `None` if the edit script would be longer than [`MAX_EDITS`]
+fn myers<T: PartialEq>(old: &[T], new: &[T]) -> Option<Vec<LineOp>> {
+    let n = old.len() as isize;
+    let m = new.len() as isize;
+    let max = (old.len() + new.len()).min(MAX_EDITS) as isize;
+
+    // `v[k + offset]` is the furthest x reached on diagonal k
+    let offset = max + 1;
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    // The part of `v` each round started from, for backtracking
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    for d in 0..=max {
+        trace.push(v[(offset - d - 1) as usize..=(offset + d + 1) as usize].to_vec());
+
+        for k in (-d..=d).step_by(2) {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                v[idx + 1]
+            } else {
+                v[idx - 1] + 1
+            };
+            let mut y = x - k;
+
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+
+            v[idx] = x;
+
+            if x >= n && y >= m {
+                return Some(backtrack(&trace, n, m));
+            }
+        }
+    }
+
+    None
+}
+
+fn backtrack(trace: &[Vec<isize>], n: isize, m: isize) -> Vec<LineOp> {
+    let mut ops = Vec::new();
+    let (mut x, mut y) = (n, m);
+
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        // `v` covers diagonals -(d + 1)..=(d + 1)
+        let at = |k: isize| v[(k + d + 1) as usize];
+        let k = x - y;
+
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = if d == 0 { 0 } else { at(prev_k) };
+        let prev_y = if d == 0 { 0 } else { prev_x - prev_k };
+
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            ops.push(LineOp::Equal {
+                old: x as usize,
+                new: y as usize,
+            });
+        }
+
+        if d > 0 {
+            if x == prev_x {
+                ops.push(LineOp::Insert {
+                    new: (y - 1) as usize,
+                });
+            } else {
+                ops.push(LineOp::Delete {
+                    old: (x - 1) as usize,
+                });
+            }
+        }
+
+        x = prev_x;
+        y = prev_y;
+    }
+
+    ops.reverse();
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(old: &[&str], new: &[&str], ops: &[LineOp]) -> Vec<String> {
+        ops.iter()
+            .filter_map(|op| match *op {
+                LineOp::Equal { old: o, new: n } => {
+                    assert_eq!(old[o], new[n]);
+                    Some(old[o].to_string())
+                }
+                LineOp::Insert { new: n } => Some(new[n].to_string()),
+                LineOp::Delete { .. } => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn produces_a_minimal_script() {
+        let old = ["a", "b", "c", "a", "b", "b", "a"];
+        let new = ["c", "b", "a", "b", "a", "c"];
+        let ops = diff_lines(&old, &new);
+
+        assert_eq!(apply(&old, &new, &ops), new);
+        let edits = ops
+            .iter()
+            .filter(|op| !matches!(op, LineOp::Equal { .. }))
+            .count();
+        assert_eq!(edits, 5);
+    }
+
+    #[test]
+    fn finds_inserted_ranges() {
+        let old = ["local a", "f()", "end"];
+        let new = ["local a", "x()", "y()", "f()", "z()", "end"];
+
+        assert_eq!(inserted_ranges(&old, &new), vec![2..=3, 5..=5]);
+        assert!(inserted_ranges(&old, &old).is_empty());
+        assert_eq!(inserted_ranges(&[] as &[&str], &["a"]), vec![1..=1]);
+    }
+}
@@ -1,4 +1,6 @@
+pub mod command;
 pub mod grammar_police;
+pub mod line_diff;
 pub mod misc;
 pub mod string_buf;
 pub mod writer;
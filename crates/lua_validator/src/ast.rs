@@ -0,0 +1,207 @@
+//! Syntax tree produced by the [`parser`](crate::parser).
+//!
+//! Only what validation and linting need is kept: literals don't carry
+//! their values, but every node has a [`Span`].
+
+use crate::lexer::Span;
+
+#[derive(Debug, Clone, Default)]
+pub struct Block {
+    pub stats: Vec<Stat>,
+    pub ret: Option<Return>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Return {
+    pub values: Vec<Expr>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Name {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum Stat {
+    Assign {
+        targets: Vec<Expr>,
+        values: Vec<Expr>,
+    },
+    Call(Expr),
+    Label(Name),
+    Break(Span),
+    Goto(Name),
+    Do(Block),
+    While {
+        cond: Expr,
+        body: Block,
+    },
+    Repeat {
+        body: Block,
+        cond: Expr,
+    },
+    If {
+        branches: Vec<(Expr, Block)>,
+        else_block: Option<Block>,
+    },
+    NumericFor {
+        var: Name,
+        start: Box<Expr>,
+        end: Box<Expr>,
+        step: Option<Box<Expr>>,
+        body: Block,
+    },
+    GenericFor {
+        names: Vec<Name>,
+        exprs: Vec<Expr>,
+        body: Block,
+    },
+    Function {
+        name: FuncName,
+        func: FuncBody,
+    },
+    LocalFunction {
+        name: Name,
+        func: FuncBody,
+    },
+    Local {
+        names: Vec<Name>,
+        values: Vec<Expr>,
+    },
+}
+
+/// `a.b.c` or `a.b:c` in `function a.b:c() end`
+#[derive(Debug, Clone)]
+pub struct FuncName {
+    pub path: Vec<Name>,
+    pub method: Option<Name>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FuncBody {
+    pub params: Vec<Name>,
+    pub vararg: bool,
+    pub body: Block,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
+    Nil,
+    True,
+    False,
+    Vararg,
+    Number,
+    Str,
+    Function(Box<FuncBody>),
+    Table(Vec<Field>),
+    Name(String),
+    /// `obj[key]`
+    Index {
+        obj: Box<Expr>,
+        key: Box<Expr>,
+    },
+    /// `obj.name`
+    Field {
+        obj: Box<Expr>,
+        name: Name,
+    },
+    Call {
+        func: Box<Expr>,
+        args: Vec<Expr>,
+    },
+    MethodCall {
+        obj: Box<Expr>,
+        method: Name,
+        args: Vec<Expr>,
+    },
+    Binary {
+        op: BinOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+    Unary {
+        op: UnOp,
+        operand: Box<Expr>,
+    },
+    Paren(Box<Expr>),
+}
+
+#[derive(Debug, Clone)]
+pub enum Field {
+    /// `name = value`
+    Named(Name, Expr),
+    /// `[key] = value`
+    Keyed(Expr, Expr),
+    Positional(Expr),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    IDiv,
+    Mod,
+    Pow,
+    Concat,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+    BAnd,
+    BOr,
+    BXor,
+    Shl,
+    Shr,
+}
+
+impl BinOp {
+    /// Left and right binding power, as in Lua's own `lparser.c`
+    pub fn priority(self) -> (u8, u8) {
+        match self {
+            BinOp::Or => (1, 1),
+            BinOp::And => (2, 2),
+            BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => (3, 3),
+            BinOp::BOr => (4, 4),
+            BinOp::BXor => (5, 5),
+            BinOp::BAnd => (6, 6),
+            BinOp::Shl | BinOp::Shr => (7, 7),
+            BinOp::Concat => (9, 8),
+            BinOp::Add | BinOp::Sub => (10, 10),
+            BinOp::Mul | BinOp::Div | BinOp::IDiv | BinOp::Mod => (11, 11),
+            BinOp::Pow => (14, 13),
+        }
+    }
+
+    /// Operators that only exist from Lua 5.3 on
+    pub fn is_lua53(self) -> bool {
+        matches!(
+            self,
+            BinOp::IDiv | BinOp::BAnd | BinOp::BOr | BinOp::BXor | BinOp::Shl | BinOp::Shr
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnOp {
+    Neg,
+    Not,
+    Len,
+    BNot,
+}
+
+pub const UNARY_PRIORITY: u8 = 12;
@@ -0,0 +1,260 @@
+//! Validating every `.lua` file of a game tree at once, either from a
+//! directory or straight from a `.love`/APK zip.
+
+use crate::{Dialect, LVal, LintConfig, LuaDiagnostic, ValidationError};
+use anyhow::Context;
+use rayon::prelude::*;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{Cursor, Read, Seek};
+use std::path::Path;
+use walkdir::WalkDir;
+use zip::ZipArchive;
+
+/// Results for a single Lua file
+#[derive(Debug)]
+pub struct FileReport {
+    /// Path relative to the directory, or the entry name inside the archive
+    pub path: String,
+    /// Set if the file didn't validate
+    pub error: Option<ValidationError>,
+    /// Lint warnings, if linting was enabled and the file parsed
+    pub warnings: Vec<LuaDiagnostic>,
+}
+
+/// Results for a whole directory or archive, sorted by path
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    pub files: Vec<FileReport>,
+}
+
+impl BatchReport {
+    pub fn is_ok(&self) -> bool {
+        self.files.iter().all(|file| file.error.is_none())
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &FileReport> {
+        self.files.iter().filter(|file| file.error.is_some())
+    }
+
+    pub fn warning_count(&self) -> usize {
+        self.files.iter().map(|file| file.warnings.len()).sum()
+    }
+
+    /// Pretty print the diagnostics of every file that has any
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        for file in &self.files {
+            if let Some(error) = &file.error {
+                out.push_str(&error.render());
+            }
+            for warning in &file.warnings {
+                out.push_str(&format!("{}:{}\n", file.path, warning));
+            }
+        }
+
+        out
+    }
+}
+
+impl Display for BatchReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} Lua file(s) checked, {} invalid, {} warning(s)",
+            self.files.len(),
+            self.failed().count(),
+            self.warning_count()
+        )
+    }
+}
+
+/// Validates many Lua files in parallel
+#[derive(Debug, Clone, Default)]
+pub struct BatchValidator {
+    dialect: Dialect,
+    lint: Option<LintConfig>,
+}
+
+impl BatchValidator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Also lint every file that parses
+    pub fn lint(mut self, config: LintConfig) -> Self {
+        self.lint = Some(config);
+        self
+    }
+
+    /// Check every `.lua` file under `dir`
+    pub fn validate_dir<P: AsRef<Path>>(&self, dir: P) -> anyhow::Result<BatchReport> {
+        let dir = dir.as_ref();
+        let mut paths = Vec::new();
+
+        for entry in WalkDir::new(dir) {
+            let entry = entry.with_context(|| format!("Failed to walk '{}'", dir.display()))?;
+            if entry.file_type().is_file() && is_lua(entry.path()) {
+                paths.push(entry.into_path());
+            }
+        }
+
+        let files = paths
+            .par_iter()
+            .map(|path| {
+                let bytes = std::fs::read(path)
+                    .with_context(|| format!("Failed to read '{}'", path.display()))?;
+                let name = path.strip_prefix(dir).unwrap_or(path).display().to_string();
+
+                Ok(self.check(name, &bytes))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Self::finish(files))
+    }
+
+    /// Check every `.lua` file inside a `.love` or APK zip, including the
+    /// ones in `.love` archives nested inside it
+    pub fn validate_archive<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<BatchReport> {
+        let path = path.as_ref();
+        let file =
+            File::open(path).with_context(|| format!("Failed to open '{}'", path.display()))?;
+
+        let mut sources = Vec::new();
+        collect_archive(file, "", &mut sources)
+            .with_context(|| format!("Failed to read archive '{}'", path.display()))?;
+
+        let files = sources
+            .par_iter()
+            .map(|(name, bytes)| self.check(name.clone(), bytes))
+            .collect();
+
+        Ok(Self::finish(files))
+    }
+
+    /// Picks [`Self::validate_dir`] or [`Self::validate_archive`] based on what `path` is
+    pub fn validate_path<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<BatchReport> {
+        if path.as_ref().is_dir() {
+            self.validate_dir(path)
+        } else {
+            self.validate_archive(path)
+        }
+    }
+
+    fn check(&self, name: String, bytes: &[u8]) -> FileReport {
+        let lval = LVal::new(String::from_utf8_lossy(bytes).into_owned())
+            .with_name(name.clone())
+            .with_dialect(self.dialect);
+
+        let (error, warnings) = match &self.lint {
+            Some(config) => match lval.lint(config) {
+                Ok(warnings) => (None, warnings),
+                Err(error) => (Some(error), Vec::new()),
+            },
+            None => (lval.validate().err(), Vec::new()),
+        };
+
+        FileReport {
+            path: name,
+            error,
+            warnings,
+        }
+    }
+
+    fn finish(mut files: Vec<FileReport>) -> BatchReport {
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        BatchReport { files }
+    }
+}
+
+fn is_lua<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("lua"))
+}
+
+/// Read out every Lua file of a zip, prefixing entry names with `prefix`
+fn collect_archive<R: Read + Seek>(
+    reader: R,
+    prefix: &str,
+    sources: &mut Vec<(String, Vec<u8>)>,
+) -> anyhow::Result<()> {
+    let mut archive = ZipArchive::new(reader)?;
+
+    for idx in 0..archive.len() {
+        let mut entry = archive.by_index(idx)?;
+        if !entry.is_file() {
+            continue;
+        }
+
+        let name = entry.name().to_string();
+        let nested = name.to_ascii_lowercase().ends_with(".love");
+        if !nested && !is_lua(&name) {
+            continue;
+        }
+
+        let mut bytes = Vec::with_capacity(entry.size() as usize);
+        entry
+            .read_to_end(&mut bytes)
+            .with_context(|| format!("Failed to read '{}'", name))?;
+
+        if nested {
+            collect_archive(
+                Cursor::new(bytes),
+                &format!("{}{}!/", prefix, name),
+                sources,
+            )?;
+        } else {
+            sources.push((format!("{}{}", prefix, name), bytes));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in files {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(content).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn validates_nested_archives() {
+        let love = zip(&[("main.lua", b"print('hi')"), ("bad.lua", b"if x then")]);
+        let apk = zip(&[
+            ("assets/game.love", &love),
+            ("assets/conf.lua", b"x = 1 // 2"),
+            ("AndroidManifest.xml", b"<manifest/>"),
+        ]);
+
+        let mut sources = Vec::new();
+        collect_archive(Cursor::new(apk), "", &mut sources).unwrap();
+        let report = BatchValidator::finish(
+            sources
+                .iter()
+                .map(|(name, bytes)| BatchValidator::new().check(name.clone(), bytes))
+                .collect(),
+        );
+
+        let failed: Vec<_> = report.failed().map(|file| file.path.as_str()).collect();
+        assert_eq!(failed, ["assets/conf.lua", "assets/game.love!/bad.lua"]);
+        assert_eq!(report.files.len(), 3);
+    }
+}
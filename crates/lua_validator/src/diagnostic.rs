@@ -0,0 +1,161 @@
+//! Diagnostics produced while validating Lua, renderable with `miette`.
+
+use crate::lexer::Span;
+use miette::{Diagnostic, GraphicalReportHandler, LabeledSpan, NamedSource, Severity, SourceCode};
+use std::fmt::{Display, Formatter};
+
+/// A single problem found in a Lua source
+#[derive(Debug, Clone)]
+pub struct LuaDiagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// 1-based
+    pub line: usize,
+    /// 1-based, in characters
+    pub column: usize,
+    /// The source line the problem is on, filled in once the source is known
+    pub snippet: String,
+    pub help: Option<String>,
+    pub span: Span,
+}
+
+impl LuaDiagnostic {
+    pub fn error(span: Span, message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, span, message)
+    }
+
+    pub fn warning(span: Span, message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, span, message)
+    }
+
+    fn new(severity: Severity, span: Span, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            message: message.into(),
+            line: span.line,
+            column: span.column,
+            snippet: String::new(),
+            help: None,
+            span,
+        }
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    pub(crate) fn attach_snippet(&mut self, src: &str) {
+        self.snippet = src
+            .lines()
+            .nth(self.line.saturating_sub(1))
+            .unwrap_or_default()
+            .to_string();
+    }
+}
+
+impl Display for LuaDiagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for LuaDiagnostic {}
+
+impl Diagnostic for LuaDiagnostic {
+    fn severity(&self) -> Option<Severity> {
+        Some(self.severity)
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        self.help
+            .as_ref()
+            .map(|help| Box::new(help) as Box<dyn Display>)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        Some(Box::new(std::iter::once(LabeledSpan::new_with_span(
+            Some(self.message.clone()),
+            self.span.range(),
+        ))))
+    }
+}
+
+/// Everything wrong with a Lua source that failed validation
+#[derive(Debug)]
+pub struct ValidationError {
+    pub name: String,
+    pub diagnostics: Vec<LuaDiagnostic>,
+    source: NamedSource<String>,
+}
+
+impl ValidationError {
+    pub(crate) fn new(name: &str, src: &str, mut diagnostics: Vec<LuaDiagnostic>) -> Self {
+        for diag in &mut diagnostics {
+            diag.attach_snippet(src);
+        }
+
+        Self {
+            name: name.to_string(),
+            diagnostics,
+            source: NamedSource::new(name, src.to_string()).with_language("lua"),
+        }
+    }
+
+    /// The first error (as opposed to warning)
+    pub fn first_error(&self) -> Option<&LuaDiagnostic> {
+        self.diagnostics.iter().find(|diag| diag.is_error())
+    }
+
+    /// Pretty print every diagnostic with a snippet of the source
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        if GraphicalReportHandler::new()
+            .render_report(&mut out, self)
+            .is_err()
+        {
+            return self.to_string();
+        }
+
+        out
+    }
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.first_error().or(self.diagnostics.first()) {
+            Some(diag) if self.diagnostics.len() == 1 => write!(f, "{}:{}", self.name, diag),
+            Some(diag) => write!(
+                f,
+                "{}:{} (and {} more)",
+                self.name,
+                diag,
+                self.diagnostics.len() - 1
+            ),
+            None => write!(f, "{}: invalid Lua", self.name),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl Diagnostic for ValidationError {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        Some(Box::new("lua_validator::invalid"))
+    }
+
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        Some(&self.source)
+    }
+
+    fn related<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a dyn Diagnostic> + 'a>> {
+        Some(Box::new(
+            self.diagnostics.iter().map(|diag| diag as &dyn Diagnostic),
+        ))
+    }
+}
@@ -0,0 +1,260 @@
+//! Re-indenting Lua based on its block structure.
+//!
+//! Only leading whitespace is ever rewritten: every line stays where it
+//! was, so line numbers (and anything mapping them, like stack traces)
+//! don't change. Lines inside multi-line strings and comments are left
+//! alone.
+
+use crate::lexer::{Lexed, TokenKind};
+use std::ops::RangeInclusive;
+
+#[derive(Debug, Clone, Default)]
+pub struct FormatOptions {
+    indent: Option<String>,
+    lines: Option<Vec<RangeInclusive<usize>>>,
+}
+
+impl FormatOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// What one level of indentation looks like.
+    /// Detected from the source if not set.
+    pub fn indent(mut self, unit: impl Into<String>) -> Self {
+        self.indent = Some(unit.into());
+        self
+    }
+
+    /// Only re-indent these (1-based, inclusive) line ranges
+    pub fn only_lines(mut self, lines: Vec<RangeInclusive<usize>>) -> Self {
+        self.lines = Some(lines);
+        self
+    }
+
+    fn touches(&self, line: usize) -> bool {
+        self.lines
+            .as_ref()
+            .is_none_or(|ranges| ranges.iter().any(|range| range.contains(&line)))
+    }
+}
+
+enum Effect {
+    Open,
+    Close,
+    /// `else` closes the `then` block and opens its own
+    CloseOpen,
+}
+
+fn effect(kind: &TokenKind) -> Option<Effect> {
+    match kind {
+        TokenKind::Function
+        | TokenKind::Do
+        | TokenKind::Then
+        | TokenKind::Repeat
+        | TokenKind::LBrace
+        | TokenKind::LParen
+        | TokenKind::LBracket => Some(Effect::Open),
+        TokenKind::End
+        | TokenKind::Until
+        | TokenKind::Elseif
+        | TokenKind::RBrace
+        | TokenKind::RParen
+        | TokenKind::RBracket => Some(Effect::Close),
+        TokenKind::Else => Some(Effect::CloseOpen),
+        _ => None,
+    }
+}
+
+/// Guess the indentation unit from the source, falling back to 4 spaces
+pub fn detect_indent(src: &str) -> String {
+    let mut tabs = 0;
+    let mut spaces = 0;
+    let mut width_gcd = 0;
+
+    for line in src.lines().filter(|line| !line.trim().is_empty()) {
+        if line.starts_with('\t') {
+            tabs += 1;
+        } else {
+            let width = line.len() - line.trim_start_matches(' ').len();
+            if width > 0 {
+                spaces += 1;
+                width_gcd = gcd(width_gcd, width);
+            }
+        }
+    }
+
+    if tabs > spaces {
+        "\t".to_string()
+    } else if matches!(width_gcd, 2 | 3 | 4 | 8) {
+        " ".repeat(width_gcd)
+    } else {
+        "    ".to_string()
+    }
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+/// Re-indent `src`, which must already have lexed into `lexed`
+pub(crate) fn format(src: &str, lexed: &Lexed, options: &FormatOptions) -> String {
+    let unit = options.indent.clone().unwrap_or_else(|| detect_indent(src));
+
+    // Each open construct, and whether it added a level of indentation.
+    // Only the innermost construct opened on a line does, so
+    // `foo(function()` indents its body once rather than twice.
+    let mut stack: Vec<bool> = Vec::new();
+    let mut tokens = lexed
+        .tokens
+        .iter()
+        .filter(|token| token.kind != TokenKind::Eof)
+        .peekable();
+    let mut out = String::with_capacity(src.len());
+
+    for (idx, line) in src.split('\n').enumerate() {
+        let line_no = idx + 1;
+
+        // Closers at the very start of the line dedent the line itself
+        let mut effects = Vec::new();
+        let mut leading = 0;
+        let mut at_start = true;
+        while let Some(token) = tokens.next_if(|token| token.span.line <= line_no) {
+            let effect = effect(&token.kind);
+            if at_start && matches!(effect, Some(Effect::Close | Effect::CloseOpen)) {
+                leading += 1;
+            } else {
+                at_start = false;
+            }
+            effects.extend(effect);
+        }
+
+        for _ in 0..leading {
+            stack.pop();
+        }
+        let level = stack.iter().filter(|indents| **indents).count();
+
+        let mut base = stack.len();
+        for (idx, effect) in effects.iter().enumerate() {
+            match effect {
+                Effect::Open => stack.push(false),
+                Effect::Close => {
+                    if idx >= leading {
+                        stack.pop();
+                    }
+                }
+                Effect::CloseOpen => {
+                    if idx >= leading {
+                        stack.pop();
+                    }
+                    stack.push(false);
+                }
+            }
+            base = base.min(stack.len());
+        }
+        if stack.len() > base
+            && let Some(innermost) = stack.last_mut()
+        {
+            *innermost = true;
+        }
+
+        if idx > 0 {
+            out.push('\n');
+        }
+
+        let verbatim = lexed
+            .verbatim_lines
+            .iter()
+            .any(|(start, end)| line_no > *start && line_no <= *end);
+        let content = line.trim_start_matches([' ', '\t']);
+
+        if verbatim || !options.touches(line_no) {
+            out.push_str(line);
+        } else if content.trim_end().is_empty() {
+            out.push_str(content);
+        } else {
+            out.push_str(&unit.repeat(level));
+            out.push_str(content);
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::LVal;
+    use crate::format::FormatOptions;
+
+    fn format(src: &str, options: FormatOptions) -> String {
+        LVal::new(src.to_string()).format(&options).unwrap()
+    }
+
+    #[test]
+    fn reindents_blocks() {
+        let src = "\
+function f(a)
+if a then
+        print(a)
+  elseif b then
+x()
+     else
+G.E_MANAGER:add_event(Event({
+func = function()
+return true
+end
+}))
+      end
+local s = [[
+   keep
+ this]]
+    if s then return s end
+local t = { 1,
+2 }
+end
+";
+        let expected = "\
+function f(a)
+  if a then
+    print(a)
+  elseif b then
+    x()
+  else
+    G.E_MANAGER:add_event(Event({
+      func = function()
+        return true
+      end
+    }))
+  end
+  local s = [[
+   keep
+ this]]
+  if s then return s end
+  local t = { 1,
+    2 }
+end
+";
+
+        assert_eq!(format(src, FormatOptions::new().indent("  ")), expected);
+    }
+
+    #[test]
+    fn only_touches_given_lines() {
+        let src = "if a then\n   x()\n        y()\n  end";
+
+        assert_eq!(
+            format(
+                src,
+                FormatOptions::new().indent("    ").only_lines(vec![3..=3])
+            ),
+            "if a then\n   x()\n    y()\n  end"
+        );
+    }
+
+    #[test]
+    fn detects_indent() {
+        assert_eq!(crate::format::detect_indent("a\n\tb\n\t\tc"), "\t");
+        assert_eq!(crate::format::detect_indent("a\n  b\n    c\n      d"), "  ");
+        assert_eq!(crate::format::detect_indent("a = 1"), "    ");
+    }
+}
@@ -0,0 +1,691 @@
+//! Turns Lua source into tokens, keeping the position of each one
+//! around so diagnostics can point at it.
+//!
+//! The lexer accepts the operators of Lua 5.1, LuaJIT and 5.3 alike;
+//! whether one is actually allowed is up to the parser. Number and
+//! string escape syntax is checked against the chosen [`Dialect`] here.
+
+use crate::Dialect;
+use crate::diagnostic::LuaDiagnostic;
+use std::ops::Range;
+
+/// Where a token or node is in the source
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    /// Byte offset of the first character
+    pub start: usize,
+    /// Byte offset one past the last character
+    pub end: usize,
+    /// 1-based line of `start`
+    pub line: usize,
+    /// 1-based column (in characters) of `start`
+    pub column: usize,
+}
+
+impl Span {
+    /// Span covering both `self` and `other`
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end.max(self.end),
+            ..self
+        }
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Name(String),
+    Number(String),
+    Str(String),
+
+    And,
+    Break,
+    Do,
+    Else,
+    Elseif,
+    End,
+    False,
+    For,
+    Function,
+    Goto,
+    If,
+    In,
+    Local,
+    Nil,
+    Not,
+    Or,
+    Repeat,
+    Return,
+    Then,
+    True,
+    Until,
+    While,
+
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    DoubleSlash,
+    Percent,
+    Caret,
+    Hash,
+    Ampersand,
+    Tilde,
+    Pipe,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Le,
+    Ge,
+    Lt,
+    Gt,
+    Assign,
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    DoubleColon,
+    Semi,
+    Colon,
+    Comma,
+    Dot,
+    Concat,
+    Ellipsis,
+
+    Eof,
+}
+
+impl TokenKind {
+    fn keyword(name: &str) -> Option<TokenKind> {
+        Some(match name {
+            "and" => TokenKind::And,
+            "break" => TokenKind::Break,
+            "do" => TokenKind::Do,
+            "else" => TokenKind::Else,
+            "elseif" => TokenKind::Elseif,
+            "end" => TokenKind::End,
+            "false" => TokenKind::False,
+            "for" => TokenKind::For,
+            "function" => TokenKind::Function,
+            "goto" => TokenKind::Goto,
+            "if" => TokenKind::If,
+            "in" => TokenKind::In,
+            "local" => TokenKind::Local,
+            "nil" => TokenKind::Nil,
+            "not" => TokenKind::Not,
+            "or" => TokenKind::Or,
+            "repeat" => TokenKind::Repeat,
+            "return" => TokenKind::Return,
+            "then" => TokenKind::Then,
+            "true" => TokenKind::True,
+            "until" => TokenKind::Until,
+            "while" => TokenKind::While,
+            _ => return None,
+        })
+    }
+
+    /// How the token looks in source, for error messages
+    pub fn describe(&self) -> String {
+        let text = match self {
+            TokenKind::Name(name) => return format!("'{}'", name),
+            TokenKind::Number(num) => return format!("'{}'", num),
+            TokenKind::Str(_) => return "string".to_string(),
+            TokenKind::Eof => return "<eof>".to_string(),
+            TokenKind::And => "and",
+            TokenKind::Break => "break",
+            TokenKind::Do => "do",
+            TokenKind::Else => "else",
+            TokenKind::Elseif => "elseif",
+            TokenKind::End => "end",
+            TokenKind::False => "false",
+            TokenKind::For => "for",
+            TokenKind::Function => "function",
+            TokenKind::Goto => "goto",
+            TokenKind::If => "if",
+            TokenKind::In => "in",
+            TokenKind::Local => "local",
+            TokenKind::Nil => "nil",
+            TokenKind::Not => "not",
+            TokenKind::Or => "or",
+            TokenKind::Repeat => "repeat",
+            TokenKind::Return => "return",
+            TokenKind::Then => "then",
+            TokenKind::True => "true",
+            TokenKind::Until => "until",
+            TokenKind::While => "while",
+            TokenKind::Plus => "+",
+            TokenKind::Minus => "-",
+            TokenKind::Star => "*",
+            TokenKind::Slash => "/",
+            TokenKind::DoubleSlash => "//",
+            TokenKind::Percent => "%",
+            TokenKind::Caret => "^",
+            TokenKind::Hash => "#",
+            TokenKind::Ampersand => "&",
+            TokenKind::Tilde => "~",
+            TokenKind::Pipe => "|",
+            TokenKind::Shl => "<<",
+            TokenKind::Shr => ">>",
+            TokenKind::Eq => "==",
+            TokenKind::Ne => "~=",
+            TokenKind::Le => "<=",
+            TokenKind::Ge => ">=",
+            TokenKind::Lt => "<",
+            TokenKind::Gt => ">",
+            TokenKind::Assign => "=",
+            TokenKind::LParen => "(",
+            TokenKind::RParen => ")",
+            TokenKind::LBrace => "{",
+            TokenKind::RBrace => "}",
+            TokenKind::LBracket => "[",
+            TokenKind::RBracket => "]",
+            TokenKind::DoubleColon => "::",
+            TokenKind::Semi => ";",
+            TokenKind::Colon => ":",
+            TokenKind::Comma => ",",
+            TokenKind::Dot => ".",
+            TokenKind::Concat => "..",
+            TokenKind::Ellipsis => "...",
+        };
+
+        format!("'{}'", text)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+/// Output of the lexer
+#[derive(Debug, Default)]
+pub struct Lexed {
+    pub tokens: Vec<Token>,
+    /// Line ranges (1-based, inclusive) covered by multi-line long strings
+    /// and comments, whose contents must be left alone when reformatting
+    pub verbatim_lines: Vec<(usize, usize)>,
+}
+
+pub struct Lexer<'a> {
+    src: &'a str,
+    dialect: Dialect,
+    chars: Vec<(usize, char)>,
+    pos: usize,
+    line: usize,
+    column: usize,
+    lexed: Lexed,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(src: &'a str) -> Self {
+        Self {
+            src,
+            dialect: Dialect::default(),
+            chars: src.char_indices().collect(),
+            pos: 0,
+            line: 1,
+            column: 1,
+            lexed: Lexed::default(),
+        }
+    }
+
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Tokenize the whole source, stopping at the first malformed token
+    pub fn run(mut self) -> Result<Lexed, Box<LuaDiagnostic>> {
+        // A shebang line is allowed (and ignored) at the very start
+        if self.src.starts_with("#!") {
+            while self.peek().is_some_and(|c| c != '\n') {
+                self.bump();
+            }
+        }
+
+        loop {
+            self.skip_trivia()?;
+
+            let start = self.mark();
+            let Some(c) = self.peek() else {
+                self.push(TokenKind::Eof, start);
+                return Ok(self.lexed);
+            };
+
+            let kind = match c {
+                c if c.is_ascii_alphabetic() || c == '_' => self.name(),
+                c if c.is_ascii_digit() => self.number(start)?,
+                '.' if self.peek_at(1).is_some_and(|c| c.is_ascii_digit()) => self.number(start)?,
+                '"' | '\'' => self.short_string(start)?,
+                '[' if matches!(self.peek_at(1), Some('[') | Some('=')) => {
+                    match self.long_bracket(start)? {
+                        Some(content) => TokenKind::Str(content),
+                        None => {
+                            self.bump();
+                            TokenKind::LBracket
+                        }
+                    }
+                }
+                _ => self.symbol(start)?,
+            };
+
+            self.push(kind, start);
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).map(|(_, c)| *c)
+    }
+
+    fn offset(&self) -> usize {
+        self.chars
+            .get(self.pos)
+            .map(|(idx, _)| *idx)
+            .unwrap_or(self.src.len())
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+
+        Some(c)
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn mark(&self) -> Span {
+        Span {
+            start: self.offset(),
+            end: self.offset(),
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    fn finish(&self, start: Span) -> Span {
+        Span {
+            end: self.offset(),
+            ..start
+        }
+    }
+
+    fn push(&mut self, kind: TokenKind, start: Span) {
+        let span = self.finish(start);
+        self.lexed.tokens.push(Token { kind, span });
+    }
+
+    fn error(&self, start: Span, message: impl Into<String>) -> Box<LuaDiagnostic> {
+        Box::new(LuaDiagnostic::error(self.finish(start), message))
+    }
+
+    /// Error for syntax that's valid, just not in the chosen dialect
+    fn unsupported(&self, start: Span, message: &str, syntax: &str) -> Box<LuaDiagnostic> {
+        Box::new(
+            LuaDiagnostic::error(self.finish(start), message)
+                .with_help(format!("{} isn't supported in {}", syntax, self.dialect)),
+        )
+    }
+
+    fn skip_trivia(&mut self) -> Result<(), Box<LuaDiagnostic>> {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_whitespace() => {
+                    self.bump();
+                }
+                Some('-') if self.peek_at(1) == Some('-') => {
+                    let start = self.mark();
+                    self.bump();
+                    self.bump();
+
+                    if self.peek() == Some('[')
+                        && self
+                            .long_bracket(start)
+                            .map_err(|mut diag| {
+                                diag.message = "unfinished long comment".to_string();
+                                diag
+                            })?
+                            .is_some()
+                    {
+                        continue;
+                    }
+
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn name(&mut self) -> TokenKind {
+        let start = self.offset();
+
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            self.bump();
+        }
+
+        let text = &self.src[start..self.offset()];
+        TokenKind::keyword(text).unwrap_or_else(|| TokenKind::Name(text.to_string()))
+    }
+
+    fn number(&mut self, start: Span) -> Result<TokenKind, Box<LuaDiagnostic>> {
+        let hex = self.peek() == Some('0') && matches!(self.peek_at(1), Some('x') | Some('X'));
+        let (exponent, is_digit): (&[char], fn(char) -> bool) = if hex {
+            self.bump();
+            self.bump();
+            (&['p', 'P'], |c: char| c.is_ascii_hexdigit())
+        } else {
+            (&['e', 'E'], |c: char| c.is_ascii_digit())
+        };
+
+        let mut digits = 0;
+        let mut fraction = false;
+        let mut has_exponent = false;
+        let mut malformed = false;
+
+        while let Some(c) = self.peek() {
+            if is_digit(c) {
+                digits += 1;
+            } else if c == '.' {
+                malformed |= fraction;
+                fraction = true;
+            } else if exponent.contains(&c) {
+                has_exponent = true;
+                self.bump();
+                if matches!(self.peek(), Some('+') | Some('-')) {
+                    self.bump();
+                }
+
+                // The exponent is decimal even in hex numbers, and ends the numeral
+                malformed |= !self.peek().is_some_and(|c| c.is_ascii_digit());
+                while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                    self.bump();
+                }
+                break;
+            } else {
+                break;
+            }
+            self.bump();
+        }
+
+        // LuaJIT's 64 bit integer and imaginary suffixes (`1LL`, `1ULL`, `2i`)
+        let suffix_start = self.offset();
+        while self
+            .peek()
+            .is_some_and(|c| matches!(c, 'u' | 'U' | 'l' | 'L' | 'i' | 'I'))
+        {
+            self.bump();
+        }
+        let suffix = self.src[suffix_start..self.offset()].to_ascii_lowercase();
+
+        if self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            while self
+                .peek()
+                .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+            {
+                self.bump();
+            }
+            return Err(self.error(start, "malformed number"));
+        }
+
+        if malformed || digits == 0 {
+            return Err(self.error(start, "malformed number"));
+        }
+
+        if hex && (fraction || has_exponent) && self.dialect == Dialect::Lua51 {
+            return Err(self.unsupported(start, "malformed number", "a hexadecimal float"));
+        }
+
+        match suffix.as_str() {
+            "" => {}
+            "i" | "ll" | "ull" if self.dialect != Dialect::LuaJit => {
+                return Err(self.unsupported(
+                    start,
+                    "malformed number",
+                    &format!("the `{}` suffix", suffix.to_ascii_uppercase()),
+                ));
+            }
+            "i" => {}
+            "ll" | "ull" if !fraction && !has_exponent => {}
+            _ => return Err(self.error(start, "malformed number")),
+        }
+
+        let text = &self.src[start.start..self.offset()];
+        Ok(TokenKind::Number(text.to_string()))
+    }
+
+    fn short_string(&mut self, start: Span) -> Result<TokenKind, Box<LuaDiagnostic>> {
+        let quote = self.bump().expect("called on a quote");
+        let mut content = String::new();
+
+        loop {
+            match self.peek() {
+                None | Some('\n') => return Err(self.error(start, "unfinished string")),
+                Some(c) if c == quote => {
+                    self.bump();
+                    return Ok(TokenKind::Str(content));
+                }
+                Some('\\') => {
+                    self.bump();
+                    self.escape(start, &mut content)?;
+                }
+                Some(c) => {
+                    self.bump();
+                    content.push(c);
+                }
+            }
+        }
+    }
+
+    fn escape(&mut self, start: Span, content: &mut String) -> Result<(), Box<LuaDiagnostic>> {
+        let Some(c) = self.bump() else {
+            return Err(self.error(start, "unfinished string"));
+        };
+
+        match c {
+            'a' => content.push('\u{7}'),
+            'b' => content.push('\u{8}'),
+            'f' => content.push('\u{c}'),
+            'n' | '\n' => content.push('\n'),
+            'r' => content.push('\r'),
+            't' => content.push('\t'),
+            'v' => content.push('\u{b}'),
+            '\\' | '"' | '\'' => content.push(c),
+            'x' | 'z' | 'u' if self.dialect == Dialect::Lua51 => {
+                return Err(self.unsupported(
+                    start,
+                    "invalid escape sequence",
+                    &format!("the `\\{}` escape", c),
+                ));
+            }
+            'x' => {
+                let mut value = 0;
+                for _ in 0..2 {
+                    match self.peek().and_then(|c| c.to_digit(16)) {
+                        Some(digit) => {
+                            self.bump();
+                            value = value * 16 + digit;
+                        }
+                        None => return Err(self.error(start, "hexadecimal digit expected")),
+                    }
+                }
+                content.push(char::from_u32(value).unwrap_or('\u{fffd}'));
+            }
+            'z' => {
+                while self.peek().is_some_and(char::is_whitespace) {
+                    self.bump();
+                }
+            }
+            'u' => {
+                if !self.eat('{') {
+                    return Err(self.error(start, "missing '{' in \\u{xxxx}"));
+                }
+                let mut value: u32 = 0;
+                while let Some(digit) = self.peek().and_then(|c| c.to_digit(16)) {
+                    self.bump();
+                    value = value.saturating_mul(16).saturating_add(digit);
+                }
+                if !self.eat('}') {
+                    return Err(self.error(start, "missing '}' in \\u{xxxx}"));
+                }
+                content.push(char::from_u32(value).unwrap_or('\u{fffd}'));
+            }
+            c if c.is_ascii_digit() => {
+                let mut value = c.to_digit(10).unwrap_or(0);
+                for _ in 0..2 {
+                    match self.peek().and_then(|c| c.to_digit(10)) {
+                        Some(digit) => {
+                            self.bump();
+                            value = value * 10 + digit;
+                        }
+                        None => break,
+                    }
+                }
+                if value > 255 {
+                    return Err(self.error(start, "decimal escape too large"));
+                }
+                content.push(char::from_u32(value).unwrap_or('\u{fffd}'));
+            }
+            _ => return Err(self.error(start, "invalid escape sequence")),
+        }
+
+        Ok(())
+    }
+
+    /// Lexes `[[...]]` / `[==[...]==]` starting at the current `[`.
+    /// Returns `None` (consuming nothing) if this isn't actually a long bracket.
+    fn long_bracket(&mut self, start: Span) -> Result<Option<String>, Box<LuaDiagnostic>> {
+        let mut level = 0;
+        while self.peek_at(1 + level) == Some('=') {
+            level += 1;
+        }
+
+        if self.peek_at(1 + level) != Some('[') {
+            if level > 0 && self.src[start.start..].starts_with('[') {
+                return Err(self.error(start, "invalid long string delimiter"));
+            }
+            return Ok(None);
+        }
+
+        for _ in 0..level + 2 {
+            self.bump();
+        }
+
+        // A newline right after the opening bracket is skipped
+        if self.peek() == Some('\r') {
+            self.bump();
+        }
+        if self.peek() == Some('\n') {
+            self.bump();
+        }
+
+        let content_start = self.offset();
+        loop {
+            match self.peek() {
+                None => return Err(self.error(start, "unfinished long string")),
+                Some(']')
+                    if (1..=level).all(|i| self.peek_at(i) == Some('='))
+                        && self.peek_at(level + 1) == Some(']') =>
+                {
+                    let content = self.src[content_start..self.offset()].to_string();
+                    for _ in 0..level + 2 {
+                        self.bump();
+                    }
+
+                    if self.line > start.line {
+                        self.lexed.verbatim_lines.push((start.line, self.line));
+                    }
+
+                    return Ok(Some(content));
+                }
+                Some(_) => {
+                    self.bump();
+                }
+            }
+        }
+    }
+
+    fn symbol(&mut self, start: Span) -> Result<TokenKind, Box<LuaDiagnostic>> {
+        let c = self.bump().expect("called with input left");
+
+        let kind = match c {
+            '+' => TokenKind::Plus,
+            '-' => TokenKind::Minus,
+            '*' => TokenKind::Star,
+            '/' if self.eat('/') => TokenKind::DoubleSlash,
+            '/' => TokenKind::Slash,
+            '%' => TokenKind::Percent,
+            '^' => TokenKind::Caret,
+            '#' => TokenKind::Hash,
+            '&' => TokenKind::Ampersand,
+            '~' if self.eat('=') => TokenKind::Ne,
+            '~' => TokenKind::Tilde,
+            '|' => TokenKind::Pipe,
+            '<' if self.eat('<') => TokenKind::Shl,
+            '<' if self.eat('=') => TokenKind::Le,
+            '<' => TokenKind::Lt,
+            '>' if self.eat('>') => TokenKind::Shr,
+            '>' if self.eat('=') => TokenKind::Ge,
+            '>' => TokenKind::Gt,
+            '=' if self.eat('=') => TokenKind::Eq,
+            '=' => TokenKind::Assign,
+            '(' => TokenKind::LParen,
+            ')' => TokenKind::RParen,
+            '{' => TokenKind::LBrace,
+            '}' => TokenKind::RBrace,
+            '[' => TokenKind::LBracket,
+            ']' => TokenKind::RBracket,
+            ':' if self.eat(':') => TokenKind::DoubleColon,
+            ':' => TokenKind::Colon,
+            ';' => TokenKind::Semi,
+            ',' => TokenKind::Comma,
+            '.' if self.peek() == Some('.') => {
+                self.bump();
+                if self.eat('.') {
+                    TokenKind::Ellipsis
+                } else {
+                    TokenKind::Concat
+                }
+            }
+            '.' => TokenKind::Dot,
+            other => {
+                return Err(self.error(start, format!("unexpected symbol '{}'", other)));
+            }
+        };
+
+        Ok(kind)
+    }
+}
@@ -1,29 +1,276 @@
 //! This does not need to be a whole separate crate but whatever lmao
+//!
+//! Parses Lua and reports what's wrong with it, for checking patched
+//! Balatro sources before they end up on a phone.
+//!
+//! The lexer and parser are written here instead of using `rslua`, which
+//! only parses Lua 5.3. Balatro runs on LuaJIT, so 5.1 and LuaJIT-only
+//! syntax has to be told apart from 5.3's, and the lints and formatter
+//! need the span of every token and node.
 
-use anyhow::anyhow;
-use rslua::lexer::Lexer;
+pub mod ast;
+pub mod batch;
+pub mod diagnostic;
+pub mod format;
+pub mod lexer;
+pub mod lint;
+pub mod parser;
+
+pub use batch::{BatchReport, BatchValidator};
+pub use diagnostic::{LuaDiagnostic, ValidationError};
+pub use format::FormatOptions;
+pub use lint::LintConfig;
+
+use std::fmt::{Display, Formatter};
+
+/// Which Lua the source is meant to run on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dialect {
+    Lua51,
+    /// What LÖVE (and so Balatro) runs on: 5.1 plus `goto` and labels
+    #[default]
+    LuaJit,
+    Lua53,
+}
+
+impl Display for Dialect {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Dialect::Lua51 => write!(f, "Lua 5.1"),
+            Dialect::LuaJit => write!(f, "LuaJIT"),
+            Dialect::Lua53 => write!(f, "Lua 5.3"),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct LVal {
     pub src: String,
+    /// Used as the file name in diagnostics
+    pub name: String,
+    pub dialect: Dialect,
 }
 
 impl LVal {
     pub fn new(src: String) -> Self {
-        Self { src }
+        Self {
+            src,
+            name: "<lua>".to_string(),
+            dialect: Dialect::default(),
+        }
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
     }
 
     pub fn print_src(&self) {
         println!("{}", self.src);
     }
 
-    pub fn validate(&self) -> anyhow::Result<(), String> {
-        let mut lexer = Lexer::default();
+    /// Parse the source, failing if there are any errors
+    pub fn parse(&self) -> Result<ast::Block, ValidationError> {
+        let parsed = parser::parse(&self.src, self.dialect);
 
-        if let Ok(tokens) = lexer.run(&self.src) {
-            Ok(())
-        } else {
-            Err(anyhow!("Couldn't validate provided Lua code").to_string())
+        match parsed.block {
+            Some(block) if !parsed.diagnostics.iter().any(LuaDiagnostic::is_error) => Ok(block),
+            _ => Err(ValidationError::new(
+                &self.name,
+                &self.src,
+                parsed.diagnostics,
+            )),
         }
     }
+
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        self.parse().map(|_| ())
+    }
+
+    /// Run the lints over the source, which has to parse first.
+    ///
+    /// Lints only ever produce warnings; use [`LVal::report`] to render them.
+    pub fn lint(&self, config: &LintConfig) -> Result<Vec<LuaDiagnostic>, ValidationError> {
+        let block = self.parse()?;
+        let mut diagnostics = lint::Linter::new(config).run(&block);
+
+        for diag in &mut diagnostics {
+            diag.attach_snippet(&self.src);
+        }
+
+        Ok(diagnostics)
+    }
+
+    /// Re-indent the source without moving any lines around.
+    /// It has to be valid Lua first.
+    pub fn format(&self, options: &FormatOptions) -> Result<String, ValidationError> {
+        self.validate()?;
+
+        let lexed = lexer::Lexer::new(&self.src)
+            .with_dialect(self.dialect)
+            .run()
+            .map_err(|diag| self.report(vec![*diag]))?;
+
+        Ok(format::format(&self.src, &lexed, options))
+    }
+
+    /// Bundle diagnostics for this source so they can be rendered together
+    pub fn report(&self, diagnostics: Vec<LuaDiagnostic>) -> ValidationError {
+        ValidationError::new(&self.name, &self.src, diagnostics)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(src: &str, dialect: Dialect) -> Vec<String> {
+        match LVal::new(src.to_string()).with_dialect(dialect).validate() {
+            Ok(()) => Vec::new(),
+            Err(err) => err.diagnostics.into_iter().map(|d| d.message).collect(),
+        }
+    }
+
+    #[test]
+    fn accepts_valid_luajit() {
+        let src = r#"
+            local function f(a, ...)
+                local t = { 1, 2, x = 3, ["y"] = 4; }
+                for i = 1, #t do
+                    if t[i] == nil then goto continue end
+                    print(("%d"):format(t[i]), [[long
+string]])
+                    ::continue::
+                end
+                return a .. select('#', ...), 0x1Fp2, 1e-3, 5ULL
+            end
+            --[==[ comment ]==]
+            G.FUNCS.thing = function(e) return not e or -e ^ 2 end
+        "#;
+
+        assert_eq!(errors(src, Dialect::LuaJit), Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_unbalanced_end() {
+        let err = LVal::new("function f()\n  return 1\nend\nend\n".to_string())
+            .validate()
+            .unwrap_err();
+        let diag = err.first_error().unwrap();
+
+        assert_eq!((diag.line, diag.column), (4, 1));
+        assert_eq!(diag.message, "'<eof>' expected near 'end'");
+        assert_eq!(diag.snippet, "end");
+    }
+
+    #[test]
+    fn reports_missing_end() {
+        let messages = errors("if x then\n  y()\n", Dialect::LuaJit);
+        assert_eq!(
+            messages,
+            ["'end' expected (to close 'if' at line 1) near <eof>"]
+        );
+    }
+
+    #[test]
+    fn rejects_lua53_operators_in_luajit() {
+        let src = "local a = 7 // 2\nlocal b = a & 1 | ~a";
+
+        assert_eq!(errors(src, Dialect::LuaJit).len(), 4);
+        assert!(errors(src, Dialect::Lua53).is_empty());
+    }
+
+    #[test]
+    fn checks_goto_labels() {
+        assert_eq!(
+            errors("goto nowhere", Dialect::LuaJit),
+            ["no visible label 'nowhere' for goto"]
+        );
+        assert_eq!(
+            errors("::a:: ::a::", Dialect::LuaJit),
+            ["label 'a' already defined on line 1"]
+        );
+        assert_eq!(
+            errors(
+                "goto skip\nlocal x = 1\n::skip::\nprint(x)",
+                Dialect::LuaJit
+            ),
+            ["<goto skip> at line 1 jumps into the scope of local 'x'"]
+        );
+        assert!(errors("do goto done end\nlocal x = 1\n::done::", Dialect::LuaJit).is_empty());
+        assert_eq!(
+            errors("::a::", Dialect::Lua51),
+            ["labels are not supported in Lua 5.1"]
+        );
+        assert!(errors("local goto = 1", Dialect::Lua51).is_empty());
+    }
+
+    #[test]
+    fn reports_misplaced_break_and_vararg() {
+        assert_eq!(errors("break", Dialect::LuaJit), ["'break' outside a loop"]);
+        assert_eq!(
+            errors("function f() return ... end", Dialect::LuaJit),
+            ["cannot use '...' outside a vararg function"]
+        );
+    }
+
+    #[test]
+    fn reports_lexer_errors() {
+        assert_eq!(errors("x = 'abc", Dialect::LuaJit), ["unfinished string"]);
+        assert_eq!(errors("x = 3x", Dialect::LuaJit), ["malformed number"]);
+        assert_eq!(
+            errors("--[[ never closed", Dialect::LuaJit),
+            ["unfinished long comment"]
+        );
+
+        for src in [
+            "x = 1e",
+            "x = 1e+",
+            "x = 0x",
+            "x = 0x.p1",
+            "x = 1..2",
+            "x = 1.5LL",
+        ] {
+            assert_eq!(
+                errors(src, Dialect::LuaJit),
+                ["malformed number"],
+                "{}",
+                src
+            );
+        }
+    }
+
+    #[test]
+    fn gates_number_and_escape_syntax_on_dialect() {
+        let luajit_only = ["x = 1i", "x = 5LL", "x = 0x10ULL"];
+        let since_52 = [
+            "x = 0x1p4",
+            "x = 0x1.8",
+            "x = '\\z\n   b'",
+            "x = '\\x41'",
+            "x = '\\u{48}'",
+        ];
+
+        for src in luajit_only.iter().chain(&since_52) {
+            assert!(errors(src, Dialect::LuaJit).is_empty(), "{}", src);
+            assert_eq!(errors(src, Dialect::Lua51).len(), 1, "{}", src);
+        }
+        for src in luajit_only {
+            assert_eq!(errors(src, Dialect::Lua53), ["malformed number"], "{}", src);
+        }
+        for src in since_52 {
+            assert!(errors(src, Dialect::Lua53).is_empty(), "{}", src);
+        }
+
+        assert_eq!(
+            errors("x = '\\x41'", Dialect::Lua51),
+            ["invalid escape sequence"]
+        );
+        assert!(errors("x = 0x1F, 1e-3, .5, 5.", Dialect::Lua51).is_empty());
+    }
 }
@@ -0,0 +1,530 @@
+//! Scope-aware lints for the mistakes that tend to break mods:
+//! accidental globals, shadowed game globals, unused locals and
+//! references to globals nothing defines.
+
+use crate::ast::*;
+use crate::diagnostic::LuaDiagnostic;
+use crate::lexer::Span;
+use std::collections::HashSet;
+
+/// Lua 5.1 and LuaJIT's standard globals
+const LUA_GLOBALS: &[&str] = &[
+    "_G",
+    "_VERSION",
+    "arg",
+    "assert",
+    "bit",
+    "collectgarbage",
+    "coroutine",
+    "debug",
+    "dofile",
+    "error",
+    "ffi",
+    "gcinfo",
+    "getfenv",
+    "getmetatable",
+    "io",
+    "ipairs",
+    "jit",
+    "load",
+    "loadfile",
+    "loadstring",
+    "math",
+    "module",
+    "newproxy",
+    "next",
+    "os",
+    "package",
+    "pairs",
+    "pcall",
+    "print",
+    "rawequal",
+    "rawget",
+    "rawset",
+    "require",
+    "select",
+    "setfenv",
+    "setmetatable",
+    "string",
+    "table",
+    "tonumber",
+    "tostring",
+    "type",
+    "unpack",
+    "xpcall",
+];
+
+/// Globals set up by LÖVE and Balatro's own sources that mods commonly use
+const GAME_GLOBALS: &[&str] = &[
+    "love",
+    "G",
+    "SMODS",
+    "Game",
+    "Object",
+    "Node",
+    "Moveable",
+    "Sprite",
+    "AnimatedSprite",
+    "Card",
+    "CardArea",
+    "Back",
+    "Blind",
+    "Tag",
+    "Event",
+    "EventManager",
+    "UIBox",
+    "UIElement",
+    "DynaText",
+    "Particles",
+    "Controller",
+    "VERSION",
+    "localize",
+    "create_card",
+    "copy_card",
+    "play_sound",
+    "ease_dollars",
+    "ease_chips",
+    "ease_mult",
+    "ease_hands_played",
+    "ease_discard",
+    "ease_ante",
+    "pseudorandom",
+    "pseudorandom_element",
+    "pseudoseed",
+    "pseudoshuffle",
+    "get_blind_amount",
+    "number_format",
+    "card_eval_status_text",
+    "find_joker",
+    "get_current_pool",
+    "poll_edition",
+    "save_run",
+    "stop_use",
+    "delay",
+    "attention_text",
+    "juice_card",
+    "juice_card_until",
+    "check_for_unlock",
+    "set_consumeable_usage",
+    "set_joker_usage",
+    "update_hand_text",
+    "level_up_hand",
+    "inc_career_stat",
+    "create_UIBox_generic_options",
+    "create_option_cycle",
+    "create_toggle",
+    "create_slider",
+    "UIBox_button",
+    "HEX",
+    "mix_colours",
+    "darken",
+    "lighten",
+    "tprint",
+    "sendDebugMessage",
+    "sendInfoMessage",
+    "sendWarnMessage",
+    "sendErrorMessage",
+    "sendTraceMessage",
+];
+
+/// Which globals a lint pass treats as known
+#[derive(Debug, Clone, Default)]
+pub struct LintConfig {
+    /// Globals that may be read and written without a warning
+    pub globals: HashSet<String>,
+    /// Globals a local shouldn't shadow
+    pub game_globals: HashSet<String>,
+}
+
+impl LintConfig {
+    /// Only the Lua/LuaJIT standard library
+    pub fn new() -> Self {
+        Self::default().allow_all(LUA_GLOBALS.iter().copied())
+    }
+
+    /// The standard library plus LÖVE's and Balatro's globals
+    pub fn balatro() -> Self {
+        let mut config = Self::new().allow_all(GAME_GLOBALS.iter().copied());
+        config
+            .game_globals
+            .extend(GAME_GLOBALS.iter().map(|name| name.to_string()));
+        config
+    }
+
+    pub fn allow(mut self, name: impl Into<String>) -> Self {
+        self.globals.insert(name.into());
+        self
+    }
+
+    pub fn allow_all<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.globals.extend(names.into_iter().map(Into::into));
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LocalKind {
+    Local,
+    Function,
+    /// Parameters and loop variables, which are often unused on purpose
+    Binding,
+}
+
+struct Local {
+    name: String,
+    span: Span,
+    kind: LocalKind,
+    used: bool,
+}
+
+pub(crate) struct Linter<'a> {
+    config: &'a LintConfig,
+    locals: Vec<Local>,
+    /// Indices into `locals`, one list per open scope
+    scopes: Vec<Vec<usize>>,
+    global_writes: HashSet<String>,
+    global_reads: Vec<(String, Span)>,
+    diagnostics: Vec<LuaDiagnostic>,
+}
+
+impl<'a> Linter<'a> {
+    pub(crate) fn new(config: &'a LintConfig) -> Self {
+        Self {
+            config,
+            locals: Vec::new(),
+            scopes: Vec::new(),
+            global_writes: HashSet::new(),
+            global_reads: Vec::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    pub(crate) fn run(mut self, chunk: &Block) -> Vec<LuaDiagnostic> {
+        self.block(chunk);
+
+        // Reads are only checked at the end, so functions can use globals
+        // defined further down the file
+        let mut reported = HashSet::new();
+        for (name, span) in std::mem::take(&mut self.global_reads) {
+            if self.config.globals.contains(&name)
+                || self.global_writes.contains(&name)
+                || !reported.insert(name.clone())
+            {
+                continue;
+            }
+
+            self.diagnostics.push(
+                LuaDiagnostic::warning(span, format!("use of unknown global '{}'", name))
+                    .with_help("check the spelling, or add it to the lint allowlist if something else defines it"),
+            );
+        }
+
+        self.diagnostics.sort_by_key(|diag| diag.span.start);
+        self.diagnostics
+    }
+
+    fn open_scope(&mut self) {
+        self.scopes.push(Vec::new());
+    }
+
+    fn close_scope(&mut self) {
+        for idx in self.scopes.pop().unwrap_or_default() {
+            let local = &self.locals[idx];
+            if local.used || local.name.starts_with('_') || local.kind == LocalKind::Binding {
+                continue;
+            }
+
+            let what = match local.kind {
+                LocalKind::Function => "local function",
+                _ => "local",
+            };
+            self.diagnostics.push(LuaDiagnostic::warning(
+                local.span,
+                format!("unused {} '{}'", what, local.name),
+            ));
+        }
+    }
+
+    /// `init` is the expression the local is initialised with, if any
+    fn declare(&mut self, name: &Name, kind: LocalKind, init: Option<&Expr>) {
+        let caches_itself =
+            matches!(init, Some(Expr { kind: ExprKind::Name(n), .. }) if n == &name.name);
+        if self.config.game_globals.contains(&name.name) && !caches_itself {
+            self.diagnostics.push(
+                LuaDiagnostic::warning(
+                    name.span,
+                    format!(
+                        "local '{}' shadows the game global '{}'",
+                        name.name, name.name
+                    ),
+                )
+                .with_help("rename the local so the game's value stays reachable"),
+            );
+        }
+
+        self.locals.push(Local {
+            name: name.name.clone(),
+            span: name.span,
+            kind,
+            used: false,
+        });
+        let idx = self.locals.len() - 1;
+        self.scopes
+            .last_mut()
+            .expect("always inside a scope")
+            .push(idx);
+    }
+
+    fn resolve(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .copied()
+            .find(|&idx| self.locals[idx].name == name)
+    }
+
+    fn read(&mut self, name: &str, span: Span) {
+        match self.resolve(name) {
+            Some(idx) => self.locals[idx].used = true,
+            None => self.global_reads.push((name.to_string(), span)),
+        }
+    }
+
+    fn write(&mut self, name: &str, span: Span) {
+        if self.resolve(name).is_some() || self.config.globals.contains(name) {
+            return;
+        }
+
+        if self.global_writes.insert(name.to_string()) {
+            self.diagnostics.push(
+                LuaDiagnostic::warning(span, format!("assignment to undeclared global '{}'", name))
+                    .with_help(format!(
+                        "declare it with `local {}`, or add it to the lint allowlist if it's meant to be global",
+                        name
+                    )),
+            );
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.open_scope();
+        self.block_body(block);
+        self.close_scope();
+    }
+
+    /// A block's statements without opening a scope for them
+    fn block_body(&mut self, block: &Block) {
+        for stat in &block.stats {
+            self.stat(stat);
+        }
+
+        if let Some(ret) = &block.ret {
+            self.exprs(&ret.values);
+        }
+    }
+
+    fn stat(&mut self, stat: &Stat) {
+        match stat {
+            Stat::Assign { targets, values } => {
+                self.exprs(values);
+                for target in targets {
+                    match &target.kind {
+                        ExprKind::Name(name) => self.write(name, target.span),
+                        _ => self.expr(target),
+                    }
+                }
+            }
+            Stat::Call(call) => self.expr(call),
+            Stat::Label(_) | Stat::Break(_) | Stat::Goto(_) => {}
+            Stat::Do(body) => self.block(body),
+            Stat::While { cond, body } => {
+                self.expr(cond);
+                self.block(body);
+            }
+            Stat::Repeat { body, cond } => {
+                // The condition can see the body's locals
+                self.open_scope();
+                self.block_body(body);
+                self.expr(cond);
+                self.close_scope();
+            }
+            Stat::If {
+                branches,
+                else_block,
+            } => {
+                for (cond, body) in branches {
+                    self.expr(cond);
+                    self.block(body);
+                }
+                if let Some(body) = else_block {
+                    self.block(body);
+                }
+            }
+            Stat::NumericFor {
+                var,
+                start,
+                end,
+                step,
+                body,
+            } => {
+                self.expr(start);
+                self.expr(end);
+                if let Some(step) = step {
+                    self.expr(step);
+                }
+                self.open_scope();
+                self.declare(var, LocalKind::Binding, None);
+                self.block(body);
+                self.close_scope();
+            }
+            Stat::GenericFor { names, exprs, body } => {
+                self.exprs(exprs);
+                self.open_scope();
+                for name in names {
+                    self.declare(name, LocalKind::Binding, None);
+                }
+                self.block(body);
+                self.close_scope();
+            }
+            Stat::Function { name, func } => {
+                let root = &name.path[0];
+                if name.path.len() == 1 && name.method.is_none() {
+                    self.write(&root.name, root.span);
+                } else {
+                    self.read(&root.name, root.span);
+                }
+                self.func(func);
+            }
+            Stat::LocalFunction { name, func } => {
+                self.declare(name, LocalKind::Function, None);
+                self.func(func);
+            }
+            Stat::Local { names, values } => {
+                self.exprs(values);
+                for (idx, name) in names.iter().enumerate() {
+                    self.declare(name, LocalKind::Local, values.get(idx));
+                }
+            }
+        }
+    }
+
+    fn func(&mut self, func: &FuncBody) {
+        self.open_scope();
+        for param in &func.params {
+            self.declare(param, LocalKind::Binding, None);
+        }
+        self.block(&func.body);
+        self.close_scope();
+    }
+
+    fn exprs(&mut self, exprs: &[Expr]) {
+        for expr in exprs {
+            self.expr(expr);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Nil
+            | ExprKind::True
+            | ExprKind::False
+            | ExprKind::Vararg
+            | ExprKind::Number
+            | ExprKind::Str => {}
+            ExprKind::Function(func) => self.func(func),
+            ExprKind::Table(fields) => {
+                for field in fields {
+                    match field {
+                        Field::Named(_, value) | Field::Positional(value) => self.expr(value),
+                        Field::Keyed(key, value) => {
+                            self.expr(key);
+                            self.expr(value);
+                        }
+                    }
+                }
+            }
+            ExprKind::Name(name) => self.read(name, expr.span),
+            ExprKind::Index { obj, key } => {
+                self.expr(obj);
+                self.expr(key);
+            }
+            ExprKind::Field { obj, .. } => self.expr(obj),
+            ExprKind::Call { func, args } => {
+                self.expr(func);
+                self.exprs(args);
+            }
+            ExprKind::MethodCall { obj, args, .. } => {
+                self.expr(obj);
+                self.exprs(args);
+            }
+            ExprKind::Binary { lhs, rhs, .. } => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            ExprKind::Unary { operand, .. } => self.expr(operand),
+            ExprKind::Paren(inner) => self.expr(inner),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{LVal, LintConfig};
+
+    fn lint(src: &str) -> Vec<String> {
+        LVal::new(src.to_string())
+            .lint(&LintConfig::balatro())
+            .unwrap()
+            .into_iter()
+            .map(|diag| diag.message)
+            .collect()
+    }
+
+    #[test]
+    fn reports_accidental_globals() {
+        assert_eq!(
+            lint("function f()\n  count = 1\n  count = 2\nend"),
+            [
+                "assignment to undeclared global 'f'",
+                "assignment to undeclared global 'count'"
+            ]
+        );
+        assert!(lint("G.GAME.dollars = 10\nlocal x = 1\nx = 2\nprint(x)").is_empty());
+    }
+
+    #[test]
+    fn reports_shadowed_game_globals() {
+        assert_eq!(
+            lint("local G = {}\nprint(G)"),
+            ["local 'G' shadows the game global 'G'"]
+        );
+        assert!(lint("local G = G\nprint(G)").is_empty());
+    }
+
+    #[test]
+    fn reports_unused_locals() {
+        assert_eq!(
+            lint("local a, _b = 1, 2\nlocal function helper() end\nfor i, v in ipairs({}) do end"),
+            ["unused local 'a'", "unused local function 'helper'"]
+        );
+        assert!(lint("local x\nrepeat local y = 1 until y > x").is_empty());
+    }
+
+    #[test]
+    fn reports_unknown_globals() {
+        assert_eq!(
+            lint("local c = create_crad()\nprint(c)"),
+            ["use of unknown global 'create_crad'"]
+        );
+        // Globals defined anywhere in the file are known
+        assert_eq!(
+            lint("local function go() helper() end\ngo()\nfunction helper() end"),
+            ["assignment to undeclared global 'helper'"]
+        );
+    }
+}
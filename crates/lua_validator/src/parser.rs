@@ -0,0 +1,983 @@
+//! Recursive descent parser for Lua, modelled on the reference
+//! implementation's `lparser.c` (including its error messages).
+//!
+//! Syntax errors stop the parse. Problems that don't (5.3-only
+//! operators under LuaJIT, `goto` to a missing label, ...) are
+//! collected and reported together.
+
+use crate::Dialect;
+use crate::ast::*;
+use crate::diagnostic::LuaDiagnostic;
+use crate::lexer::{Lexer, Span, Token, TokenKind};
+
+/// Same limit as `LUAI_MAXCCALLS`
+const MAX_DEPTH: usize = 200;
+
+type PResult<T> = Result<T, Box<LuaDiagnostic>>;
+
+/// Result of parsing a whole chunk
+#[derive(Debug, Default)]
+pub struct Parsed {
+    /// `None` if there was a syntax error
+    pub block: Option<Block>,
+    pub diagnostics: Vec<LuaDiagnostic>,
+}
+
+pub fn parse(src: &str, dialect: Dialect) -> Parsed {
+    let tokens = match Lexer::new(src).with_dialect(dialect).run() {
+        Ok(lexed) => lexed.tokens,
+        Err(diag) => {
+            return Parsed {
+                block: None,
+                diagnostics: vec![*diag],
+            };
+        }
+    };
+
+    let mut parser = Parser::new(&tokens, dialect);
+    let block = parser.chunk();
+    let mut diagnostics = parser.diagnostics;
+
+    match block {
+        Ok(block) => Parsed {
+            block: Some(block),
+            diagnostics,
+        },
+        Err(diag) => {
+            diagnostics.push(*diag);
+            Parsed {
+                block: None,
+                diagnostics,
+            }
+        }
+    }
+}
+
+struct Label {
+    name: String,
+    pos: usize,
+    span: Span,
+}
+
+struct PendingGoto {
+    name: Name,
+    /// Index of the statement containing the `goto` in the current block
+    pos: usize,
+}
+
+#[derive(Default)]
+struct BlockState {
+    labels: Vec<Label>,
+    gotos: Vec<PendingGoto>,
+    locals: Vec<(usize, String)>,
+    /// Number of statements parsed so far
+    count: usize,
+    /// Index of the last statement that isn't a label
+    last_non_void: Option<usize>,
+}
+
+struct FuncState {
+    vararg: bool,
+    loops: usize,
+    blocks: Vec<BlockState>,
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    dialect: Dialect,
+    depth: usize,
+    funcs: Vec<FuncState>,
+    diagnostics: Vec<LuaDiagnostic>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(tokens: &'a [Token], dialect: Dialect) -> Self {
+        Self {
+            tokens,
+            pos: 0,
+            dialect,
+            depth: 0,
+            funcs: Vec::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    fn chunk(&mut self) -> PResult<Block> {
+        self.funcs.push(FuncState {
+            vararg: true,
+            loops: 0,
+            blocks: Vec::new(),
+        });
+
+        let block = self.block()?;
+        self.funcs.pop();
+
+        if !self.check(&TokenKind::Eof) {
+            return Err(self.error_expected("'<eof>'"));
+        }
+
+        Ok(block)
+    }
+
+    // -- token helpers --
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos.min(self.tokens.len() - 1)]
+    }
+
+    fn peek_kind(&self) -> &TokenKind {
+        &self.peek().kind
+    }
+
+    fn lookahead(&self) -> &TokenKind {
+        &self.tokens[(self.pos + 1).min(self.tokens.len() - 1)].kind
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.peek().clone();
+        if token.kind != TokenKind::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn check(&self, kind: &TokenKind) -> bool {
+        self.peek_kind() == kind
+    }
+
+    fn eat(&mut self, kind: &TokenKind) -> bool {
+        if self.check(kind) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, kind: TokenKind) -> PResult<Token> {
+        if self.check(&kind) {
+            Ok(self.advance())
+        } else {
+            Err(self.error_expected(&kind.describe()))
+        }
+    }
+
+    /// Expect the token closing a construct opened by `opener` on `line`
+    fn expect_match(&mut self, kind: TokenKind, opener: &TokenKind, line: usize) -> PResult<Token> {
+        if self.check(&kind) {
+            return Ok(self.advance());
+        }
+
+        if line == self.peek().span.line {
+            Err(self.error_expected(&kind.describe()))
+        } else {
+            Err(self.error_near(format!(
+                "{} expected (to close {} at line {})",
+                kind.describe(),
+                opener.describe(),
+                line
+            )))
+        }
+    }
+
+    fn name(&mut self) -> PResult<Name> {
+        let token = self.peek().clone();
+
+        match token.kind {
+            TokenKind::Name(name) => {
+                self.advance();
+                Ok(Name {
+                    name,
+                    span: token.span,
+                })
+            }
+            // `goto` is a plain identifier before 5.2
+            TokenKind::Goto if self.dialect == Dialect::Lua51 => {
+                self.advance();
+                Ok(Name {
+                    name: "goto".to_string(),
+                    span: token.span,
+                })
+            }
+            _ => Err(self.error_expected("<name>")),
+        }
+    }
+
+    fn error_near(&self, message: impl Into<String>) -> Box<LuaDiagnostic> {
+        let token = self.peek();
+        Box::new(LuaDiagnostic::error(
+            token.span,
+            format!("{} near {}", message.into(), token.kind.describe()),
+        ))
+    }
+
+    fn error_expected(&self, what: &str) -> Box<LuaDiagnostic> {
+        self.error_near(format!("{} expected", what))
+    }
+
+    fn enter(&mut self) -> PResult<()> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(self.error_near("chunk has too many syntax levels"));
+        }
+        Ok(())
+    }
+
+    fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    fn func(&mut self) -> &mut FuncState {
+        self.funcs.last_mut().expect("always inside a function")
+    }
+
+    fn block_state(&mut self) -> &mut BlockState {
+        self.func()
+            .blocks
+            .last_mut()
+            .expect("always inside a block")
+    }
+
+    // -- blocks & goto resolution --
+
+    fn block_follow(&self, with_until: bool) -> bool {
+        match self.peek_kind() {
+            TokenKind::Else | TokenKind::Elseif | TokenKind::End | TokenKind::Eof => true,
+            TokenKind::Until => with_until,
+            _ => false,
+        }
+    }
+
+    fn block(&mut self) -> PResult<Block> {
+        self.enter()?;
+        self.func().blocks.push(BlockState::default());
+
+        let mut block = Block::default();
+
+        while !self.block_follow(true) {
+            if self.check(&TokenKind::Return) {
+                block.ret = Some(self.return_stat()?);
+                break;
+            }
+
+            if let Some(stat) = self.statement()? {
+                let is_label = matches!(stat, Stat::Label(_));
+                let state = self.block_state();
+                if !is_label {
+                    state.last_non_void = Some(state.count);
+                }
+                state.count += 1;
+                block.stats.push(stat);
+            }
+        }
+
+        if block.ret.is_some() {
+            let state = self.block_state();
+            state.last_non_void = Some(state.count);
+        }
+
+        let state = self.func().blocks.pop().expect("pushed above");
+        self.close_block(state);
+        self.leave();
+
+        Ok(block)
+    }
+
+    /// Match the gotos of a finished block against its labels, passing
+    /// the ones that aren't resolved on to the enclosing block
+    fn close_block(&mut self, state: BlockState) {
+        for goto in state.gotos {
+            let Some(label) = state.labels.iter().find(|l| l.name == goto.name.name) else {
+                match self.func().blocks.last_mut() {
+                    Some(parent) => {
+                        let pos = parent.count;
+                        parent.gotos.push(PendingGoto { pos, ..goto });
+                    }
+                    None => self.diagnostics.push(
+                        LuaDiagnostic::error(
+                            goto.name.span,
+                            format!("no visible label '{}' for goto", goto.name.name),
+                        )
+                        .with_help("labels are only visible in the block they're defined in and blocks nested inside it"),
+                    ),
+                }
+                continue;
+            };
+
+            // A label at the very end of a block is outside the scope of
+            // the block's locals, so jumping there is always fine
+            let trailing = state.last_non_void.is_none_or(|last| label.pos > last);
+            if label.pos <= goto.pos || trailing {
+                continue;
+            }
+
+            if let Some((_, local)) = state
+                .locals
+                .iter()
+                .find(|(pos, _)| *pos >= goto.pos && *pos < label.pos)
+            {
+                self.diagnostics.push(LuaDiagnostic::error(
+                    goto.name.span,
+                    format!(
+                        "<goto {}> at line {} jumps into the scope of local '{}'",
+                        goto.name.name, goto.name.span.line, local
+                    ),
+                ));
+            }
+        }
+    }
+
+    // -- statements --
+
+    fn statement(&mut self) -> PResult<Option<Stat>> {
+        self.enter()?;
+        let stat = self.statement_inner();
+        self.leave();
+        stat
+    }
+
+    fn statement_inner(&mut self) -> PResult<Option<Stat>> {
+        let token = self.peek().clone();
+        let line = token.span.line;
+
+        let stat = match token.kind {
+            TokenKind::Semi => {
+                self.advance();
+                return Ok(None);
+            }
+            TokenKind::If => self.if_stat(line)?,
+            TokenKind::While => {
+                self.advance();
+                let cond = self.expr()?;
+                self.expect(TokenKind::Do)?;
+                let body = self.loop_body()?;
+                self.expect_match(TokenKind::End, &TokenKind::While, line)?;
+                Stat::While { cond, body }
+            }
+            TokenKind::Do => {
+                self.advance();
+                let body = self.block()?;
+                self.expect_match(TokenKind::End, &TokenKind::Do, line)?;
+                Stat::Do(body)
+            }
+            TokenKind::For => self.for_stat(line)?,
+            TokenKind::Repeat => {
+                self.advance();
+                let body = self.loop_body()?;
+                self.expect_match(TokenKind::Until, &TokenKind::Repeat, line)?;
+                let cond = self.expr()?;
+                Stat::Repeat { body, cond }
+            }
+            TokenKind::Function => {
+                self.advance();
+                let name = self.func_name()?;
+                let func = self.func_body(name.method.is_some(), line)?;
+                Stat::Function { name, func }
+            }
+            TokenKind::Local => {
+                self.advance();
+                if self.eat(&TokenKind::Function) {
+                    let name = self.name()?;
+                    self.declare_local(&name);
+                    let func = self.func_body(false, line)?;
+                    Stat::LocalFunction { name, func }
+                } else {
+                    let mut names = vec![self.name()?];
+                    while self.eat(&TokenKind::Comma) {
+                        names.push(self.name()?);
+                    }
+                    let values = if self.eat(&TokenKind::Assign) {
+                        self.expr_list()?
+                    } else {
+                        Vec::new()
+                    };
+                    for name in &names {
+                        self.declare_local(name);
+                    }
+                    Stat::Local { names, values }
+                }
+            }
+            TokenKind::DoubleColon => self.label_stat(token.span)?,
+            TokenKind::Break => {
+                self.advance();
+                if self.func().loops == 0 {
+                    self.diagnostics
+                        .push(LuaDiagnostic::error(token.span, "'break' outside a loop"));
+                }
+                Stat::Break(token.span)
+            }
+            TokenKind::Goto if self.dialect != Dialect::Lua51 => {
+                self.advance();
+                let name = self.name()?;
+                let state = self.block_state();
+                let pos = state.count;
+                state.gotos.push(PendingGoto {
+                    name: name.clone(),
+                    pos,
+                });
+                Stat::Goto(name)
+            }
+            _ => self.expr_stat()?,
+        };
+
+        Ok(Some(stat))
+    }
+
+    fn declare_local(&mut self, name: &Name) {
+        let state = self.block_state();
+        let pos = state.count;
+        state.locals.push((pos, name.name.clone()));
+    }
+
+    fn loop_body(&mut self) -> PResult<Block> {
+        self.func().loops += 1;
+        let body = self.block();
+        self.func().loops -= 1;
+        body
+    }
+
+    fn if_stat(&mut self, line: usize) -> PResult<Stat> {
+        let mut branches = Vec::new();
+        let mut else_block = None;
+
+        self.advance();
+        let cond = self.expr()?;
+        self.expect(TokenKind::Then)?;
+        branches.push((cond, self.block()?));
+
+        loop {
+            if self.eat(&TokenKind::Elseif) {
+                let cond = self.expr()?;
+                self.expect(TokenKind::Then)?;
+                branches.push((cond, self.block()?));
+            } else if self.eat(&TokenKind::Else) {
+                else_block = Some(self.block()?);
+                self.expect_match(TokenKind::End, &TokenKind::If, line)?;
+                break;
+            } else {
+                self.expect_match(TokenKind::End, &TokenKind::If, line)?;
+                break;
+            }
+        }
+
+        Ok(Stat::If {
+            branches,
+            else_block,
+        })
+    }
+
+    fn for_stat(&mut self, line: usize) -> PResult<Stat> {
+        self.advance();
+        let first = self.name()?;
+
+        let stat = match self.peek_kind() {
+            TokenKind::Assign => {
+                self.advance();
+                let start = Box::new(self.expr()?);
+                self.expect(TokenKind::Comma)?;
+                let end = Box::new(self.expr()?);
+                let step = if self.eat(&TokenKind::Comma) {
+                    Some(Box::new(self.expr()?))
+                } else {
+                    None
+                };
+                self.expect(TokenKind::Do)?;
+                let body = self.loop_body()?;
+                Stat::NumericFor {
+                    var: first,
+                    start,
+                    end,
+                    step,
+                    body,
+                }
+            }
+            TokenKind::Comma | TokenKind::In => {
+                let mut names = vec![first];
+                while self.eat(&TokenKind::Comma) {
+                    names.push(self.name()?);
+                }
+                self.expect(TokenKind::In)?;
+                let exprs = self.expr_list()?;
+                self.expect(TokenKind::Do)?;
+                let body = self.loop_body()?;
+                Stat::GenericFor { names, exprs, body }
+            }
+            _ => return Err(self.error_expected("'=' or 'in'")),
+        };
+
+        self.expect_match(TokenKind::End, &TokenKind::For, line)?;
+        Ok(stat)
+    }
+
+    fn label_stat(&mut self, start: Span) -> PResult<Stat> {
+        if self.dialect == Dialect::Lua51 {
+            return Err(Box::new(
+                LuaDiagnostic::error(start, "labels are not supported in Lua 5.1")
+                    .with_help("labels and `goto` were added in Lua 5.2 (and LuaJIT 2.0)"),
+            ));
+        }
+
+        self.advance();
+        let name = self.name()?;
+        self.expect(TokenKind::DoubleColon)?;
+
+        let state = self.block_state();
+        let duplicate = state
+            .labels
+            .iter()
+            .find(|l| l.name == name.name)
+            .map(|l| l.span);
+        let pos = state.count;
+        state.labels.push(Label {
+            name: name.name.clone(),
+            pos,
+            span: name.span,
+        });
+
+        if let Some(previous) = duplicate {
+            self.diagnostics.push(LuaDiagnostic::error(
+                name.span,
+                format!(
+                    "label '{}' already defined on line {}",
+                    name.name, previous.line
+                ),
+            ));
+        }
+
+        Ok(Stat::Label(name))
+    }
+
+    fn return_stat(&mut self) -> PResult<Return> {
+        let start = self.advance().span;
+
+        let values = if self.block_follow(true) || self.check(&TokenKind::Semi) {
+            Vec::new()
+        } else {
+            self.expr_list()?
+        };
+        self.eat(&TokenKind::Semi);
+
+        let span = values.last().map_or(start, |last| start.to(last.span));
+        Ok(Return { values, span })
+    }
+
+    fn expr_stat(&mut self) -> PResult<Stat> {
+        let first = self.suffixed_expr()?;
+
+        if self.check(&TokenKind::Assign) || self.check(&TokenKind::Comma) {
+            let mut targets = vec![first];
+            while self.eat(&TokenKind::Comma) {
+                targets.push(self.suffixed_expr()?);
+            }
+
+            for target in &targets {
+                if !matches!(
+                    target.kind,
+                    ExprKind::Name(_) | ExprKind::Index { .. } | ExprKind::Field { .. }
+                ) {
+                    return Err(Box::new(LuaDiagnostic::error(
+                        target.span,
+                        "syntax error: cannot assign to this expression",
+                    )));
+                }
+            }
+
+            self.expect(TokenKind::Assign)?;
+            let values = self.expr_list()?;
+            return Ok(Stat::Assign { targets, values });
+        }
+
+        match first.kind {
+            ExprKind::Call { .. } | ExprKind::MethodCall { .. } => Ok(Stat::Call(first)),
+            _ => Err(self.error_near("syntax error")),
+        }
+    }
+
+    fn func_name(&mut self) -> PResult<FuncName> {
+        let mut path = vec![self.name()?];
+        while self.eat(&TokenKind::Dot) {
+            path.push(self.name()?);
+        }
+
+        let method = if self.eat(&TokenKind::Colon) {
+            Some(self.name()?)
+        } else {
+            None
+        };
+
+        Ok(FuncName { path, method })
+    }
+
+    /// Parameters, body and closing `end` of a function.
+    /// Methods get their implicit `self` parameter added.
+    fn func_body(&mut self, is_method: bool, line: usize) -> PResult<FuncBody> {
+        let start = self.peek().span;
+        let mut params = Vec::new();
+        let mut vararg = false;
+
+        if is_method {
+            params.push(Name {
+                name: "self".to_string(),
+                span: start,
+            });
+        }
+
+        self.expect(TokenKind::LParen)?;
+        if !self.check(&TokenKind::RParen) {
+            loop {
+                if self.eat(&TokenKind::Ellipsis) {
+                    vararg = true;
+                    break;
+                }
+                params.push(self.name()?);
+                if !self.eat(&TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+        self.expect(TokenKind::RParen)?;
+
+        self.funcs.push(FuncState {
+            vararg,
+            loops: 0,
+            blocks: Vec::new(),
+        });
+        let body = self.block();
+        self.funcs.pop();
+        let body = body?;
+
+        let end = self.expect_match(TokenKind::End, &TokenKind::Function, line)?;
+
+        Ok(FuncBody {
+            params,
+            vararg,
+            body,
+            span: start.to(end.span),
+        })
+    }
+
+    // -- expressions --
+
+    fn expr_list(&mut self) -> PResult<Vec<Expr>> {
+        let mut exprs = vec![self.expr()?];
+        while self.eat(&TokenKind::Comma) {
+            exprs.push(self.expr()?);
+        }
+        Ok(exprs)
+    }
+
+    fn expr(&mut self) -> PResult<Expr> {
+        self.sub_expr(0)
+    }
+
+    fn sub_expr(&mut self, limit: u8) -> PResult<Expr> {
+        self.enter()?;
+
+        let token = self.peek().clone();
+        let unary = match token.kind {
+            TokenKind::Minus => Some(UnOp::Neg),
+            TokenKind::Not => Some(UnOp::Not),
+            TokenKind::Hash => Some(UnOp::Len),
+            TokenKind::Tilde => Some(UnOp::BNot),
+            _ => None,
+        };
+
+        let mut lhs = if let Some(op) = unary {
+            self.advance();
+            if op == UnOp::BNot {
+                self.require_lua53(token.span, "~");
+            }
+            let operand = self.sub_expr(UNARY_PRIORITY)?;
+            Expr {
+                span: token.span.to(operand.span),
+                kind: ExprKind::Unary {
+                    op,
+                    operand: Box::new(operand),
+                },
+            }
+        } else {
+            self.simple_expr()?
+        };
+
+        while let Some(op) = self.binary_op() {
+            let (left, right) = op.priority();
+            if left <= limit {
+                break;
+            }
+
+            let op_token = self.advance();
+            if op.is_lua53() {
+                self.require_lua53(op_token.span, &op_token.kind.describe().replace('\'', ""));
+            }
+
+            let rhs = self.sub_expr(right)?;
+            lhs = Expr {
+                span: lhs.span.to(rhs.span),
+                kind: ExprKind::Binary {
+                    op,
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(rhs),
+                },
+            };
+        }
+
+        self.leave();
+        Ok(lhs)
+    }
+
+    fn binary_op(&self) -> Option<BinOp> {
+        Some(match self.peek_kind() {
+            TokenKind::Plus => BinOp::Add,
+            TokenKind::Minus => BinOp::Sub,
+            TokenKind::Star => BinOp::Mul,
+            TokenKind::Slash => BinOp::Div,
+            TokenKind::DoubleSlash => BinOp::IDiv,
+            TokenKind::Percent => BinOp::Mod,
+            TokenKind::Caret => BinOp::Pow,
+            TokenKind::Concat => BinOp::Concat,
+            TokenKind::Eq => BinOp::Eq,
+            TokenKind::Ne => BinOp::Ne,
+            TokenKind::Lt => BinOp::Lt,
+            TokenKind::Le => BinOp::Le,
+            TokenKind::Gt => BinOp::Gt,
+            TokenKind::Ge => BinOp::Ge,
+            TokenKind::And => BinOp::And,
+            TokenKind::Or => BinOp::Or,
+            TokenKind::Ampersand => BinOp::BAnd,
+            TokenKind::Pipe => BinOp::BOr,
+            TokenKind::Tilde => BinOp::BXor,
+            TokenKind::Shl => BinOp::Shl,
+            TokenKind::Shr => BinOp::Shr,
+            _ => return None,
+        })
+    }
+
+    /// Report 5.3-only operators when targeting an older dialect
+    fn require_lua53(&mut self, span: Span, op: &str) {
+        if self.dialect == Dialect::Lua53 {
+            return;
+        }
+
+        let help = match op {
+            "//" => "use math.floor(a / b) instead".to_string(),
+            _ => "use the `bit` library instead (e.g. bit.band, bit.bor, bit.bxor, bit.bnot, bit.lshift, bit.rshift)".to_string(),
+        };
+
+        self.diagnostics.push(
+            LuaDiagnostic::error(
+                span,
+                format!(
+                    "'{}' is Lua 5.3+ syntax, not supported by {}",
+                    op, self.dialect
+                ),
+            )
+            .with_help(help),
+        );
+    }
+
+    fn simple_expr(&mut self) -> PResult<Expr> {
+        let token = self.peek().clone();
+
+        let kind = match token.kind {
+            TokenKind::Number(_) => ExprKind::Number,
+            TokenKind::Str(_) => ExprKind::Str,
+            TokenKind::Nil => ExprKind::Nil,
+            TokenKind::True => ExprKind::True,
+            TokenKind::False => ExprKind::False,
+            TokenKind::Ellipsis => {
+                if !self.func().vararg {
+                    self.diagnostics.push(LuaDiagnostic::error(
+                        token.span,
+                        "cannot use '...' outside a vararg function",
+                    ));
+                }
+                ExprKind::Vararg
+            }
+            TokenKind::LBrace => return self.table(),
+            TokenKind::Function => {
+                self.advance();
+                let func = self.func_body(false, token.span.line)?;
+                return Ok(Expr {
+                    span: token.span.to(func.span),
+                    kind: ExprKind::Function(Box::new(func)),
+                });
+            }
+            _ => return self.suffixed_expr(),
+        };
+
+        self.advance();
+        Ok(Expr {
+            kind,
+            span: token.span,
+        })
+    }
+
+    fn primary_expr(&mut self) -> PResult<Expr> {
+        let token = self.peek().clone();
+
+        match token.kind {
+            TokenKind::LParen => {
+                self.advance();
+                let inner = self.expr()?;
+                let close =
+                    self.expect_match(TokenKind::RParen, &TokenKind::LParen, token.span.line)?;
+                Ok(Expr {
+                    span: token.span.to(close.span),
+                    kind: ExprKind::Paren(Box::new(inner)),
+                })
+            }
+            TokenKind::Name(_) | TokenKind::Goto => {
+                let name = self.name()?;
+                Ok(Expr {
+                    span: name.span,
+                    kind: ExprKind::Name(name.name),
+                })
+            }
+            _ => Err(self.error_near("unexpected symbol")),
+        }
+    }
+
+    fn suffixed_expr(&mut self) -> PResult<Expr> {
+        let mut expr = self.primary_expr()?;
+
+        loop {
+            match self.peek_kind() {
+                TokenKind::Dot => {
+                    self.advance();
+                    let name = self.name()?;
+                    expr = Expr {
+                        span: expr.span.to(name.span),
+                        kind: ExprKind::Field {
+                            obj: Box::new(expr),
+                            name,
+                        },
+                    };
+                }
+                TokenKind::LBracket => {
+                    let open = self.advance();
+                    let key = self.expr()?;
+                    let close = self.expect_match(
+                        TokenKind::RBracket,
+                        &TokenKind::LBracket,
+                        open.span.line,
+                    )?;
+                    expr = Expr {
+                        span: expr.span.to(close.span),
+                        kind: ExprKind::Index {
+                            obj: Box::new(expr),
+                            key: Box::new(key),
+                        },
+                    };
+                }
+                TokenKind::Colon => {
+                    self.advance();
+                    let method = self.name()?;
+                    let (args, end) = self.call_args()?;
+                    expr = Expr {
+                        span: expr.span.to(end),
+                        kind: ExprKind::MethodCall {
+                            obj: Box::new(expr),
+                            method,
+                            args,
+                        },
+                    };
+                }
+                TokenKind::LParen | TokenKind::LBrace | TokenKind::Str(_) => {
+                    // `f\n(g)` is ambiguous in 5.1 and LuaJIT, which reject it
+                    if self.check(&TokenKind::LParen)
+                        && self.dialect != Dialect::Lua53
+                        && self.peek().span.line != self.tokens[self.pos - 1].span.line
+                    {
+                        return Err(
+                            self.error_near("ambiguous syntax (function call x new statement)")
+                        );
+                    }
+
+                    let (args, end) = self.call_args()?;
+                    expr = Expr {
+                        span: expr.span.to(end),
+                        kind: ExprKind::Call {
+                            func: Box::new(expr),
+                            args,
+                        },
+                    };
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
+
+    /// Arguments of a call, along with the span of their closing token
+    fn call_args(&mut self) -> PResult<(Vec<Expr>, Span)> {
+        let token = self.peek().clone();
+
+        match token.kind {
+            TokenKind::Str(_) => {
+                self.advance();
+                Ok((
+                    vec![Expr {
+                        kind: ExprKind::Str,
+                        span: token.span,
+                    }],
+                    token.span,
+                ))
+            }
+            TokenKind::LBrace => {
+                let table = self.table()?;
+                let span = table.span;
+                Ok((vec![table], span))
+            }
+            TokenKind::LParen => {
+                self.advance();
+                let args = if self.check(&TokenKind::RParen) {
+                    Vec::new()
+                } else {
+                    self.expr_list()?
+                };
+                let close =
+                    self.expect_match(TokenKind::RParen, &TokenKind::LParen, token.span.line)?;
+                Ok((args, close.span))
+            }
+            _ => Err(self.error_expected("function arguments")),
+        }
+    }
+
+    fn table(&mut self) -> PResult<Expr> {
+        let open = self.expect(TokenKind::LBrace)?;
+        let mut fields = Vec::new();
+
+        while !self.check(&TokenKind::RBrace) {
+            let field = match self.peek_kind() {
+                TokenKind::LBracket => {
+                    let bracket = self.advance();
+                    let key = self.expr()?;
+                    self.expect_match(
+                        TokenKind::RBracket,
+                        &TokenKind::LBracket,
+                        bracket.span.line,
+                    )?;
+                    self.expect(TokenKind::Assign)?;
+                    Field::Keyed(key, self.expr()?)
+                }
+                TokenKind::Name(_) if self.lookahead() == &TokenKind::Assign => {
+                    let name = self.name()?;
+                    self.advance();
+                    Field::Named(name, self.expr()?)
+                }
+                _ => Field::Positional(self.expr()?),
+            };
+            fields.push(field);
+
+            if !self.eat(&TokenKind::Comma) && !self.eat(&TokenKind::Semi) {
+                break;
+            }
+        }
+
+        let close = self.expect_match(TokenKind::RBrace, &TokenKind::LBrace, open.span.line)?;
+        Ok(Expr {
+            span: open.span.to(close.span),
+            kind: ExprKind::Table(fields),
+        })
+    }
+}